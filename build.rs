@@ -0,0 +1,50 @@
+use std::{env, process::Command};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The records are written out in `src/protobuf.rs`, as the HTTP responses use them in every build.
+    #[cfg(feature = "grpc")]
+    tonic_build::configure()
+        .build_client(false)
+        .file_descriptor_set_path(std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("geoip2_descriptor.bin"))
+        .extern_path(".geoip2.v1.Place", "crate::protobuf::Place")
+        .extern_path(".geoip2.v1.Location", "crate::protobuf::Location")
+        .extern_path(".geoip2.v1.Traits", "crate::protobuf::Traits")
+        .extern_path(".geoip2.v1.CountryRecord", "crate::protobuf::CountryRecord")
+        .extern_path(".geoip2.v1.CityRecord", "crate::protobuf::CityRecord")
+        .extern_path(".geoip2.v1.AsnRecord", "crate::protobuf::AsnRecord")
+        .compile(&["proto/geoip2.proto"], &["proto"])?;
+
+    build_info();
+
+    Ok(())
+}
+
+/// Embeds what `/version` reports. Image builds without the `.git` directory can pass the commit in `GEOIP2_GIT_SHA`, and reproducible builds their time in `SOURCE_DATE_EPOCH`.
+fn build_info() {
+    let git_sha = env::var("GEOIP2_GIT_SHA").ok().or_else(|| output("git", &["rev-parse", "HEAD"])).unwrap_or_default();
+    let build_epoch = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs().to_string());
+    let rustc = output(&env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")), &["--version"]).unwrap_or_default();
+    let mut features = env::vars().filter_map(|(key, _)| Some(key.strip_prefix("CARGO_FEATURE_")?.to_lowercase().replace('_', "-"))).collect::<Vec<_>>();
+    features.sort();
+
+    println!("cargo:rustc-env=GEOIP2_GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=GEOIP2_BUILD_EPOCH={build_epoch}");
+    println!("cargo:rustc-env=GEOIP2_RUSTC_VERSION={rustc}");
+    println!("cargo:rustc-env=GEOIP2_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-env-changed=GEOIP2_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(head) = std::fs::read_to_string(".git/HEAD").ok().and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_owned())) {
+        println!("cargo:rerun-if-changed=.git/{head}");
+    }
+    println!("cargo:rerun-if-changed=src");
+}
+
+/// The trimmed stdout of a command, or `None` if it could not be run or failed.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok().filter(|output| output.status.success())?;
+
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
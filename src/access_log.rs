@@ -0,0 +1,44 @@
+use axum::http::Response;
+use serde::Deserialize;
+use std::{net::IpAddr, time::Duration};
+use tracing::Span;
+
+/// What a lookup found, attached to its response so the access log can say which address was looked up and where it is.
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    pub ip: IpAddr,
+    pub country: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    country: Option<Country>,
+}
+
+#[derive(Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+impl Lookup {
+    /// Notes the lookup of `ip`, taking the country from the serialized `record` if it has one.
+    pub fn new(ip: IpAddr, record: &[u8]) -> Self {
+        let country = serde_json::from_slice::<Record>(record).ok().and_then(|record| record.country?.iso_code);
+
+        Lookup { ip, country }
+    }
+}
+
+/// Logs one event per response with its status, latency, and the address looked up and its country, if any. The method, route, client address, request ID and caller are fields of the request span it is logged in.
+pub fn on_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    let lookup = response.extensions().get::<Lookup>();
+
+    tracing::info!(
+        parent: span,
+        status = response.status().as_u16(),
+        latency_us = latency.as_micros() as u64,
+        lookup_ip = lookup.map(|lookup| tracing::field::display(crate::anonymize::ip(lookup.ip))),
+        country = lookup.and_then(|lookup| lookup.country.as_deref()),
+        "access"
+    );
+}
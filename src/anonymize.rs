@@ -0,0 +1,46 @@
+use std::{
+    borrow::Cow,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes [`ip`] mask the addresses it is given, for `--log-anonymize-ips`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// The form of `ip` that may be written to logs and traces: as is, or with `--log-anonymize-ips` with the last octet of an IPv4 address or the last 80 bits of an IPv6 address zeroed, like Google Analytics does.
+pub fn ip(ip: IpAddr) -> IpAddr {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return ip;
+    }
+
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & !0xff)),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !((1 << 80) - 1))),
+    }
+}
+
+/// `text`, e.g. the URI of a lookup, with every IP address in it passed through [`ip`].
+pub fn text(text: &str) -> Cow<'_, str> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut anonymized = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_hexdigit() || c == ':') {
+        let end = rest[start..].find(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':')).map_or(rest.len(), |len| start + len);
+        anonymized.push_str(&rest[..start]);
+        match rest[start..end].parse::<IpAddr>() {
+            Ok(address) => anonymized.push_str(&ip(address).to_string()),
+            Err(_) => anonymized.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    anonymized.push_str(rest);
+
+    Cow::Owned(anonymized)
+}
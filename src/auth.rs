@@ -0,0 +1,131 @@
+use crate::{jwt::Jwks, AppState, LookupError};
+use anyhow::Context;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::Arc,
+};
+
+/// Who [`require`] authenticated a request as, in its extensions for what comes after it, like the rate limiter.
+#[derive(Clone, Debug)]
+pub struct Subject(pub String);
+
+/// The credentials accepted on lookup routes. With none configured, lookups are open to everyone.
+#[derive(Default)]
+pub struct Auth {
+    api_keys: BTreeSet<String>,
+    /// License key by account ID, as sent by MaxMind's client libraries via Basic auth.
+    accounts: BTreeMap<String, String>,
+    pub jwks: Option<Arc<Jwks>>,
+}
+
+impl Auth {
+    /// Reads API keys from `path`, one per line. Blank lines and lines starting with `#` are ignored.
+    pub fn load_api_keys(&mut self, path: &Path) -> anyhow::Result<()> {
+        let keys = std::fs::read_to_string(path).with_context(|| format!("Failed to read API keys from {}", path.display()))?;
+        self.api_keys.extend(keys.lines().map(str::trim).filter(|key| !key.is_empty() && !key.starts_with('#')).map(str::to_owned));
+
+        if self.api_keys.is_empty() {
+            anyhow::bail!("No API keys found in {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Reads `account_id:license_key` pairs from `path`, one per line. Blank lines and lines starting with `#` are ignored.
+    pub fn load_accounts(&mut self, path: &Path) -> anyhow::Result<()> {
+        let accounts = std::fs::read_to_string(path).with_context(|| format!("Failed to read accounts from {}", path.display()))?;
+
+        for (number, line) in accounts.lines().enumerate().map(|(index, line)| (index + 1, line.trim())).filter(|(_, line)| !line.is_empty() && !line.starts_with('#')) {
+            let (account_id, license_key) = line.split_once(':').with_context(|| format!("Line {number} of {} is not of the form account_id:license_key", path.display()))?;
+            self.accounts.insert(account_id.to_owned(), license_key.to_owned());
+        }
+
+        if self.accounts.is_empty() {
+            anyhow::bail!("No accounts found in {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || !self.accounts.is_empty() || self.jwks.is_some()
+    }
+
+    fn bearer(headers: &HeaderMap) -> Option<&str> {
+        let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, token) = authorization.split_once(' ')?;
+
+        scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+    }
+
+    /// The API key of a request, from `X-API-Key` or an `Authorization: Bearer` header.
+    fn api_key(headers: &HeaderMap) -> Option<&str> {
+        match headers.get("x-api-key") {
+            Some(key) => key.to_str().ok(),
+            None => Self::bearer(headers),
+        }
+    }
+
+    /// The account ID and license key of a request from its `Authorization: Basic` header.
+    fn account(headers: &HeaderMap) -> Option<(String, String)> {
+        let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, credentials) = authorization.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+
+        let credentials = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(credentials.trim()).ok()?).ok()?;
+        let (account_id, license_key) = credentials.split_once(':')?;
+
+        Some((account_id.to_owned(), license_key.to_owned()))
+    }
+
+    /// Returns who is calling if the request has a valid API key or account: a fingerprint of the key, as keys must not end up in logs, or the account ID.
+    fn authorized(&self, headers: &HeaderMap) -> Option<String> {
+        if let Some(key) = Self::api_key(headers).filter(|key| self.api_keys.contains(*key)) {
+            let fingerprint = format!("{:x}", Sha256::digest(key));
+            return Some(format!("key:{}", &fingerprint[..12]));
+        }
+
+        let (account_id, license_key) = Self::account(headers)?;
+        (self.accounts.get(&account_id) == Some(&license_key)).then(|| format!("account:{account_id}"))
+    }
+
+    /// Checks the credentials in `headers`, returning who is calling: the fingerprint of its API key, its account ID or the subject of its JWT, if it has one.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<String>, LookupError> {
+        if let Some(subject) = self.authorized(headers) {
+            return Ok(Some(subject));
+        }
+
+        let claims = match (&self.jwks, Self::bearer(headers)) {
+            (Some(jwks), Some(token)) => jwks.verify(token).await,
+            _ => None,
+        };
+
+        Ok(claims.ok_or(LookupError::AuthorizationInvalid)?.sub)
+    }
+}
+
+/// Rejects requests without valid credentials with `401`, if any are configured. Who made the request is recorded in the request span for the access log, and as its [`Subject`].
+pub async fn require(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Result<Response, LookupError> {
+    let auth = &state.auth;
+    if !auth.is_enabled() {
+        return Ok(next.run(request).await);
+    }
+
+    if let Some(subject) = auth.authenticate(request.headers()).await? {
+        tracing::Span::current().record("subject", subject.as_str());
+        request.extensions_mut().insert(Subject(subject));
+    }
+
+    Ok(next.run(request).await)
+}
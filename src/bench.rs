@@ -0,0 +1,107 @@
+use crate::{lookup_kind, reserved, AppState, LookupError};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Parses a count such as `1000`, `10k` or `1M`, which must be at least one, as there is nothing to measure without requests, tasks or addresses.
+pub fn parse_count(count: &str) -> Result<usize, String> {
+    let (number, multiplier) = match count.trim().strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1_000),
+        None => match count.trim().strip_suffix('M') {
+            Some(number) => (number, 1_000_000),
+            None => (count.trim(), 1),
+        },
+    };
+
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|count| *count > 0)
+        .ok_or_else(|| format!("{count} is not a count of at least 1, e.g. 1000, 10k or 1M"))
+}
+
+/// `count` distinct, random public IPv4 addresses, the same on every run.
+pub fn addresses(count: usize) -> Vec<IpAddr> {
+    // xorshift32, which is plenty random enough to spread lookups over the address space.
+    let mut state = 0x9e37_79b9_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    std::iter::repeat_with(|| IpAddr::V4(Ipv4Addr::from(next()))).filter(|ip| !reserved::is_reserved(*ip)).take(count).collect()
+}
+
+/// What a run measured.
+pub struct Report {
+    pub elapsed: Duration,
+    /// The latency of each request, in nanoseconds, sorted.
+    pub latencies: Vec<u64>,
+    pub not_found: usize,
+    pub errors: usize,
+}
+
+impl Report {
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let index = ((self.latencies.len() as f64 * percentile / 100.0).ceil() as usize).clamp(1, self.latencies.len().max(1)) - 1;
+
+        Duration::from_nanos(self.latencies.get(index).copied().unwrap_or_default())
+    }
+}
+
+/// Runs `requests` requests, each looking up the next address in every database exactly like the lookup routes do, cache included, from `concurrency` tasks.
+pub async fn run(state: Arc<AppState>, addresses: Arc<Vec<IpAddr>>, requests: usize, concurrency: usize) -> Report {
+    let next = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    let workers = (0..concurrency.max(1))
+        .map(|_| {
+            let (state, addresses, next) = (state.clone(), addresses.clone(), next.clone());
+            tokio::spawn(async move {
+                let (mut latencies, mut not_found, mut errors) = (Vec::new(), 0, 0);
+                loop {
+                    let request = next.fetch_add(1, Ordering::Relaxed);
+                    if request >= requests {
+                        break (latencies, not_found, errors);
+                    }
+
+                    let ip = addresses[request % addresses.len()];
+                    let start = Instant::now();
+                    for database in state.databases.iter() {
+                        match lookup_kind(database.kind, &[database.reader()], ip, &state).await {
+                            Ok(_) => {}
+                            Err(LookupError::IpAddressNotFound) => not_found += 1,
+                            Err(_) => errors += 1,
+                        }
+                    }
+                    latencies.push(start.elapsed().as_nanos() as u64);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = Report {
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(requests),
+        not_found: 0,
+        errors: 0,
+    };
+    for worker in workers {
+        let (latencies, not_found, errors) = worker.await.expect("benchmark worker panicked");
+        report.latencies.extend(latencies);
+        report.not_found += not_found;
+        report.errors += errors;
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.sort_unstable();
+
+    report
+}
@@ -0,0 +1,48 @@
+use crate::database::DatabaseKind;
+use bytes::Bytes;
+use ipnetwork::IpNetwork;
+use std::{cmp::Reverse, collections::BTreeSet, net::IpAddr, sync::RwLock, time::Duration};
+
+/// Records are cached per database build, so a reload never serves stale entries; they just stop being hit and expire. Chained databases of a kind may be of the same build, so the position in the chain is part of the key too.
+type Key = (DatabaseKind, usize, u64, IpNetwork);
+
+/// Serialized records by the network they were found in, so every address of a hot network hits the same entry.
+pub struct RecordCache {
+    records: moka::sync::Cache<Key, Bytes>,
+    /// The prefix lengths of the cached networks, longest first, by whether they are IPv6. A lookup only has to probe these to find the network of an address.
+    prefixes: RwLock<BTreeSet<(bool, Reverse<u8>)>>,
+}
+
+impl RecordCache {
+    pub fn new(size: u64, ttl: Duration) -> Self {
+        RecordCache { records: moka::sync::Cache::builder().max_capacity(size).time_to_live(ttl).build(), prefixes: RwLock::new(BTreeSet::new()) }
+    }
+
+    pub fn get(&self, kind: DatabaseKind, position: usize, build_epoch: u64, ip: IpAddr) -> Option<Bytes> {
+        let prefixes = self.prefixes.read().expect("cache prefixes lock poisoned").iter().filter(|(ipv6, _)| *ipv6 == ip.is_ipv6()).map(|(_, prefix_len)| prefix_len.0).collect::<Vec<_>>();
+
+        let record = prefixes.into_iter().filter_map(|prefix_len| network(ip, prefix_len)).find_map(|network| self.records.get(&(kind, position, build_epoch, network)));
+        metrics::counter!("geoip_cache_requests_total", "database" => kind.name(), "result" => if record.is_some() { "hit" } else { "miss" }).increment(1);
+
+        record
+    }
+
+    pub fn insert(&self, kind: DatabaseKind, position: usize, build_epoch: u64, ip: IpAddr, prefix_len: u8, record: Bytes) {
+        let Some(network) = network(ip, prefix_len) else {
+            return;
+        };
+
+        let prefix = (ip.is_ipv6(), Reverse(prefix_len));
+        if !self.prefixes.read().expect("cache prefixes lock poisoned").contains(&prefix) {
+            self.prefixes.write().expect("cache prefixes lock poisoned").insert(prefix);
+        }
+
+        self.records.insert((kind, position, build_epoch, network), record);
+    }
+}
+
+fn network(ip: IpAddr, prefix_len: u8) -> Option<IpNetwork> {
+    let network = IpNetwork::new(ip, prefix_len).ok()?;
+
+    IpNetwork::new(network.network(), prefix_len).ok()
+}
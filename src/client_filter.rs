@@ -0,0 +1,39 @@
+use crate::{client_ip::ClientIp, AppState, LookupError};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnetwork::IpNetwork;
+use std::{net::IpAddr, sync::Arc};
+
+/// Which client addresses the server answers, for deployments that must only answer e.g. their VPC even where the port is reachable more widely.
+#[derive(Clone, Debug, Default)]
+pub struct ClientFilter {
+    /// If not empty, only clients in these networks are answered.
+    pub allow: Vec<IpNetwork>,
+    /// Clients in these networks are never answered, even if they are in an allowed one.
+    pub deny: Vec<IpNetwork>,
+}
+
+impl ClientFilter {
+    pub fn is_enabled(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    /// Whether a client is answered. Clients whose address isn't known are only answered without an allowlist.
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => !self.deny.iter().any(|network| network.contains(ip)) && (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))),
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// Rejects requests of clients `--allow-cidr` and `--deny-cidr` don't allow with `403`, before anything else looks at them.
+pub async fn restrict(State(state): State<Arc<AppState>>, client: ClientIp, request: Request, next: Next) -> Response {
+    match state.client_filter.allows(client.0) {
+        true => next.run(request).await,
+        false => LookupError::ClientNotAllowed.into_response(),
+    }
+}
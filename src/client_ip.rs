@@ -0,0 +1,94 @@
+use crate::AppState;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, Extensions, HeaderMap, HeaderName},
+};
+use ipnetwork::IpNetwork;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+/// The address of the client that made the request, or `None` if it cannot be determined.
+///
+/// When the peer is one of the trusted proxies, the proxies' header is used instead; see [`ClientIpConfig`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+/// How to find the client address of a request, similar to nginx's realip module.
+///
+/// Only requests whose peer is one of `trusted_proxies` have their headers looked at, so untrusted clients cannot spoof their address. The hops of the `header` (or `Forwarded`, then `X-Forwarded-For` if not set) are walked from the right, skipping trusted proxies, and the first untrusted address is the client.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIpConfig {
+    pub trusted_proxies: Vec<IpNetwork>,
+    pub header: Option<HeaderName>,
+}
+
+/// Parses an address out of a header hop, which may be quoted, bracketed, or carry a port: `192.0.2.1`, `"[2001:db8::1]:4711"`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+
+    hop.parse::<IpAddr>().ok().or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip())).or_else(|| hop.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+fn header_hops(headers: &HeaderMap, name: &HeaderName) -> Vec<IpAddr> {
+    headers.get_all(name).iter().filter_map(|value| value.to_str().ok()).flat_map(|value| value.split(',')).filter_map(parse_hop).collect()
+}
+
+fn forwarded_hops(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded = headers.get_all(header::FORWARDED).iter().filter_map(|value| value.to_str().ok()).collect::<Vec<_>>();
+
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| element.split(';').find_map(|pair| pair.trim().split_once('=').filter(|(key, _)| key.eq_ignore_ascii_case("for"))))
+            .filter_map(|(_, value)| parse_hop(value))
+            .collect();
+    }
+
+    header_hops(headers, &HeaderName::from_static("x-forwarded-for"))
+}
+
+impl ClientIpConfig {
+    fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let is_trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|network| network.contains(*ip));
+
+        if let Some(peer) = peer.filter(|peer| !is_trusted(peer)) {
+            return Some(peer);
+        }
+
+        let hops = match &self.header {
+            Some(header) => header_hops(headers, header),
+            None => forwarded_hops(headers),
+        };
+
+        hops.iter().rev().find(|hop| !is_trusted(*hop)).or(hops.first()).copied().or(peer)
+    }
+
+    /// Returns the client address of a request from its extensions and headers, or `None` if it cannot be determined.
+    ///
+    /// Connections over a unix socket have no peer address. Only local processes the socket's permissions allow can connect, so they are trusted like a proxy and the client is taken from the headers.
+    pub fn client_ip(&self, extensions: &Extensions, headers: &HeaderMap) -> Option<IpAddr> {
+        self.peer_client_ip(extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr), headers)
+    }
+
+    /// Returns the client address of a connection from `peer` and the headers of what it sent, like the metadata of gRPC calls.
+    pub(crate) fn peer_client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        // Dual-stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses.
+        let peer = peer.map(|addr| addr.ip().to_canonical());
+
+        self.resolve(peer, headers).map(|ip| ip.to_canonical())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(state.client_ip.client_ip(&parts.extensions, &parts.headers)))
+    }
+}
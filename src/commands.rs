@@ -0,0 +1,273 @@
+use crate::{
+    auth::Auth, bench, cache::RecordCache, cli, client_filter::ClientFilter, client_ip::ClientIpConfig, database::Databases, database_args, database_metadata, decode_kind, diff, enrich::Enricher, locale::Locales, overrides::Overrides, remote,
+    tls::TlsArgs, AppState,
+};
+use anyhow::Context;
+use clap::parser::ValueSource;
+use maxminddb::Reader;
+use std::{
+    fmt::Write as _,
+    io::{BufWriter, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Opens the databases given on the command line, downloading those given by URL first.
+async fn open_databases(args: &clap::ArgMatches) -> anyhow::Result<Databases> {
+    let db = database_args(args)?;
+    remote::Remote::new(&db).await.fetch(&db).await?;
+
+    Databases::open(&db, args.get_flag("in-memory"))
+}
+
+/// `lookup <ip>`: prints the record of the address in each database, keyed by type, or the error looking it up returned.
+pub async fn lookup(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let ip = *args.get_one::<IpAddr>("ip").expect("No valid IP set!");
+    let network = !args.get_flag("no-network");
+    let databases = open_databases(args).await?;
+
+    let records = databases
+        .iter()
+        .map(|database| {
+            let record = match decode_kind(database.kind, &database.reader(), ip, network) {
+                Ok(record) => serde_json::from_slice(&record).expect("records are valid JSON"),
+                Err(err) => err.body().1,
+            };
+            (database.name(), record)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    println!("{}", serde_json::to_string_pretty(&records)?);
+
+    Ok(())
+}
+
+/// `inspect`: prints the metadata of each database, like `/geoip/v2.1/metadata`.
+pub async fn inspect(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let databases = open_databases(args).await?;
+
+    println!("{}", serde_json::to_string_pretty(&database_metadata(&databases))?);
+
+    Ok(())
+}
+
+/// `enrich`: copies a CSV or NDJSON file, adding the requested fields of the record of the address in each row.
+pub async fn enrich(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let input = args.get_one::<PathBuf>("input").expect("No valid input set!");
+    let column = args.get_one::<String>("column").expect("No valid column set!");
+    let fields = args.get_many::<String>("fields").expect("No valid fields set!").cloned().collect();
+    let databases = open_databases(args).await?;
+    let enricher = Enricher { readers: databases.iter().map(|database| (database.kind, database.reader())).collect(), network: !args.get_flag("no-network"), fields };
+
+    let output: Box<dyn Write> = match args.get_one::<PathBuf>("output") {
+        Some(output) => Box::new(BufWriter::new(std::fs::File::create(output).with_context(|| format!("Failed to create {}", output.display()))?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    match input.extension().and_then(|extension| extension.to_str()) {
+        Some("ndjson" | "jsonl") => enricher.ndjson(input, column, output),
+        _ => enricher.csv(input, column, output),
+    }
+}
+
+/// How many errors `verify` lists before only counting them.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// `verify <file>`: walks the whole search tree of a database, decoding the record of every network, and prints what it found. Fails if anything could not be read, so it can gate a deploy.
+pub fn verify(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let path = args.get_one::<PathBuf>("file").expect("No valid file set!");
+    let reader = Reader::open_readfile(path).with_context(|| format!("{} is not a valid database", path.display()))?;
+    let metadata = &reader.metadata;
+
+    let everything = if metadata.ip_version == 6 { "::/0" } else { "0.0.0.0/0" };
+    let (mut networks, mut errors) = (0u64, Vec::new());
+    for item in reader.within::<serde::de::IgnoredAny>(everything.parse()?)? {
+        match item {
+            Ok(_) => networks += 1,
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    let built = humantime::format_rfc3339(std::time::UNIX_EPOCH + Duration::from_secs(metadata.build_epoch));
+    let report = serde_json::json!({
+        "path": path,
+        "valid": errors.is_empty(),
+        "database_type": metadata.database_type,
+        "build_epoch": built.to_string(),
+        "ip_version": metadata.ip_version,
+        "node_count": metadata.node_count,
+        "record_size": metadata.record_size,
+        "networks": networks,
+        "error_count": errors.len(),
+        "errors": errors.iter().take(MAX_REPORTED_ERRORS).collect::<Vec<_>>(),
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if !errors.is_empty() {
+        anyhow::bail!("{} is corrupt, {} of its records could not be read", path.display(), errors.len());
+    }
+
+    Ok(())
+}
+
+/// `diff <old> <new>`: compares the country, city and ASN of a sample of networks between two builds of a database.
+pub fn diff(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let open = |name: &str| {
+        let path = args.get_one::<PathBuf>(name).expect("No valid database set!");
+        let reader = Reader::open_readfile(path).with_context(|| format!("{} is not a valid database", path.display()))?;
+        anyhow::Ok((path, reader))
+    };
+    let ((old_path, old), (new_path, new)) = (open("old")?, open("new")?);
+
+    let networks = match args.get_one::<PathBuf>("networks") {
+        Some(path) => diff::read_networks(path)?,
+        None => diff::sample(&old, *args.get_one::<usize>("sample").expect("No valid sample size set!"))?,
+    };
+
+    let build = |path: &PathBuf, reader: &Reader<Vec<u8>>| serde_json::json!({ "path": path, "build_epoch": humantime::format_rfc3339(std::time::UNIX_EPOCH + Duration::from_secs(reader.metadata.build_epoch)).to_string() });
+    let mut report = diff::compare(&old, &new, &networks)?;
+    report["old"] = build(old_path, &old);
+    report["new"] = build(new_path, &new);
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
+/// `bench`: looks up random addresses in the databases in-process, through the same lookup path and caches as the server, and prints the latency and throughput.
+pub async fn bench(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let requests = *args.get_one::<usize>("requests").expect("No valid request count set!");
+    let concurrency = *args.get_one::<usize>("concurrency").expect("No valid concurrency set!");
+    let addresses = *args.get_one::<usize>("addresses").expect("No valid address count set!");
+    let cache_size = args.get_one::<u64>("cache-size").copied();
+    let cache_ttl = *args.get_one::<Duration>("cache-ttl").expect("No valid cache TTL set!");
+    let databases = open_databases(args).await?;
+
+    #[cfg(feature = "redis")]
+    let redis = match args.get_one::<String>("redis-url") {
+        Some(redis_url) => Some(Arc::new(crate::redis_cache::SharedCache::new(redis_url, *args.get_one::<Duration>("redis-ttl").expect("No valid Redis TTL set!"))?)),
+        None => None,
+    };
+    #[cfg(not(feature = "redis"))]
+    if args.contains_id("redis-url") {
+        anyhow::bail!("This build does not support --redis-url, enable the `redis` feature");
+    }
+
+    let modes = databases.iter().map(|database| (database.name(), serde_json::Value::from(database.mode()))).collect::<serde_json::Map<_, _>>();
+    let state = Arc::new(AppState {
+        databases: Arc::new(databases),
+        batch_limit: 0,
+        client_ip: ClientIpConfig::default(),
+        network: !args.get_flag("no-network"),
+        enrich_countries: false,
+        default_locales: Locales::default(),
+        status_ip: *args.get_one::<IpAddr>("status-ip").expect("No valid status IP set!"),
+        started: Instant::now(),
+        max_database_age: None,
+        auth: Auth::default(),
+        client_filter: ClientFilter::default(),
+        rate_limiter: None,
+        ip_rate_limiter: None,
+        rate_limit_exempt: Vec::new(),
+        hostnames: None,
+        reverse_dns: None,
+        private_response: None,
+        upstream: None,
+        cache_max_age: None,
+        cache: cache_size.map(|size| RecordCache::new(size, cache_ttl)),
+        #[cfg(feature = "redis")]
+        redis,
+    });
+
+    let report = bench::run(state, Arc::new(bench::addresses(addresses)), requests, concurrency).await;
+    let micros = |latency: Duration| latency.as_secs_f64() * 1e6;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "databases": modes,
+            "cache_size": cache_size,
+            "requests": requests,
+            "concurrency": concurrency,
+            "addresses": addresses,
+            "elapsed_seconds": report.elapsed.as_secs_f64(),
+            "requests_per_second": requests as f64 / report.elapsed.as_secs_f64(),
+            "latency_us": { "p50": micros(report.percentile(50.0)), "p90": micros(report.percentile(90.0)), "p99": micros(report.percentile(99.0)), "max": micros(report.percentile(100.0)) },
+            "not_found": report.not_found,
+            "errors": report.errors,
+        }))?
+    );
+
+    Ok(())
+}
+
+/// Settings that are secrets, which `--check-config` prints masked.
+const SECRET_SETTINGS: &[&str] = &["license-key", "upstream-license-key", "redis-url", "sentry-dsn"];
+
+/// Flags that say what to do rather than how to serve, left out of the settings `--check-config` prints.
+const ACTION_FLAGS: &[&str] = &["config", "check-config", "self-test"];
+
+/// `--check-config`: checks what the server would otherwise only find out while starting, i.e. that the local databases open as the type they are served as, and that the overrides, TLS certificate and key, and credential files are valid, then prints the effective settings as a config file, with where each one came from. Databases given by URL, or that `--account-id` would download, aren't downloaded.
+pub fn check_config(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    let updates = args.get_one::<String>("account-id").is_some() && args.get_one::<String>("license-key").is_some();
+    let local = database_args(args)?.into_iter().filter(|arg| arg.url.is_none() && !(updates && !arg.path.exists())).collect::<Vec<_>>();
+    if !local.is_empty() {
+        Databases::open(&local, false)?;
+    }
+    if let Some(path) = args.get_one::<PathBuf>("overrides") {
+        Overrides::open(path.clone())?;
+    }
+
+    if let (Some(cert), Some(key)) = (args.get_one::<PathBuf>("tls-cert"), args.get_one::<PathBuf>("tls-key")) {
+        let tls = TlsArgs {
+            cert: cert.clone(),
+            key: key.clone(),
+            client_ca: args.get_one::<PathBuf>("tls-client-ca").cloned(),
+        };
+        tls.server_config()?;
+    }
+
+    let mut auth = Auth::default();
+    if let Some(path) = args.get_one::<PathBuf>("api-keys-file") {
+        auth.load_api_keys(path)?;
+    }
+    if let Some(path) = args.get_one::<PathBuf>("accounts-file") {
+        auth.load_accounts(path)?;
+    }
+    if let Some(url) = args.get_one::<String>("jwks-url") {
+        reqwest::Url::parse(url).with_context(|| format!("Invalid JWKS URL {url}"))?;
+    }
+
+    let mut settings = String::from("# The effective settings, which can be passed back with --config.\n");
+    for arg in cli().get_arguments() {
+        let (id, Some(long)) = (arg.get_id().as_str(), arg.get_long()) else {
+            continue;
+        };
+        let Some(values) = args.get_raw(id).filter(|_| !ACTION_FLAGS.contains(&long)) else {
+            continue;
+        };
+        let values = values.map(|value| value.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        if values == ["false"] && matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+            continue;
+        }
+
+        // Config file settings are defaults of the flags too, so they are told apart by not being the defaults of the flags themselves.
+        let source = match args.value_source(id) {
+            Some(ValueSource::CommandLine) => "command line",
+            Some(ValueSource::EnvVariable) => "environment",
+            _ if arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned()).eq(values.iter().cloned()) => "default",
+            _ => "config file",
+        };
+        let value = match (SECRET_SETTINGS.contains(&long), values.as_slice()) {
+            (true, _) => toml::Value::from("********"),
+            (false, [value]) => toml::Value::from(value.as_str()),
+            (false, values) => toml::Value::from(values.to_vec()),
+        };
+        let _ = writeln!(settings, "{long} = {value} # {source}");
+    }
+    print!("{settings}");
+
+    Ok(())
+}
@@ -0,0 +1,328 @@
+use crate::{client_ip::ClientIp, countries, database::DatabaseKind, lookup_kind, parse_ip, reserved, resolve_ip, timezone, AppState, LookupError};
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+/// The APIs of other geolocation services the server can also answer, for clients that cannot be changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compat {
+    /// freegeoip's, and ipstack's, `/json/:ip`.
+    Freegeoip,
+    /// ip-api.com's `/json/:ip`.
+    IpApi,
+    /// ipinfo.io's `/:ip` and `/:ip/:field`.
+    Ipinfo,
+}
+
+impl FromStr for Compat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "freegeoip" => Ok(Compat::Freegeoip),
+            "ip-api" => Ok(Compat::IpApi),
+            "ipinfo" => Ok(Compat::Ipinfo),
+            name => Err(format!("{name} is not a compatibility mode, e.g. freegeoip, ip-api or ipinfo")),
+        }
+    }
+}
+
+impl Compat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compat::Freegeoip => "freegeoip",
+            Compat::IpApi => "ip-api",
+            Compat::Ipinfo => "ipinfo",
+        }
+    }
+
+    /// The path the routes of the mode are under, as modes sharing one cannot be enabled together.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Compat::Freegeoip | Compat::IpApi => "/json",
+            Compat::Ipinfo => "/",
+        }
+    }
+
+    pub fn router(self) -> Router<Arc<AppState>> {
+        match self {
+            Compat::Freegeoip => Router::new().route("/json/", get(freegeoip)).route("/json/:ip", get(freegeoip)),
+            Compat::IpApi => Router::new().route("/json/", get(ip_api)).route("/json/:ip", get(ip_api)),
+            Compat::Ipinfo => Router::new().route("/", get(ipinfo_caller)).route("/:ip", get(ipinfo)).route("/:ip/:field", get(ipinfo_field)),
+        }
+    }
+}
+
+fn text(record: &Value, pointer: &str) -> Value {
+    record.pointer(pointer).filter(|value| value.is_string()).cloned().unwrap_or_else(|| Value::String(String::new()))
+}
+
+fn number(record: &Value, pointer: &str) -> Value {
+    record.pointer(pointer).filter(|value| value.is_number()).cloned().unwrap_or_else(|| json!(0))
+}
+
+fn flag(record: &Value, pointer: &str) -> bool {
+    record.pointer(pointer).and_then(Value::as_bool).unwrap_or_default()
+}
+
+/// The record of `ip` in the database serving `kind`, or `Value::Null` if it isn't in there or no such database is loaded, as the other services' responses have their fields either way.
+async fn record(state: &AppState, kind: DatabaseKind, ip: IpAddr) -> Result<Value, LookupError> {
+    let Some(readers) = state.databases.readers(kind) else {
+        return Ok(Value::Null);
+    };
+
+    match lookup_kind(kind, &readers, ip, state).await {
+        Ok(record) => Ok(serde_json::from_slice(&record).expect("records are valid JSON")),
+        Err(LookupError::IpAddressNotFound) => Ok(Value::Null),
+        Err(err) => Err(err),
+    }
+}
+
+/// Looks up the City record of the address, or of the caller without one, as the English names and codes freegeoip returned, empty when the record doesn't have them.
+async fn freegeoip(State(state): State<Arc<AppState>>, client: ClientIp, ip: Option<Path<String>>) -> Result<Json<Value>, LookupError> {
+    let ip = resolve_ip(ip.as_ref().map_or("me", |Path(ip)| ip.as_str()), client)?;
+    if state.databases.get(DatabaseKind::City).is_none() {
+        return Err(LookupError::DatabaseNotLoaded);
+    }
+    let city = record(&state, DatabaseKind::City, ip).await?;
+
+    Ok(Json(json!({
+        "ip": ip.to_string(),
+        "country_code": text(&city, "/country/iso_code"),
+        "country_name": text(&city, "/country/names/en"),
+        "region_code": text(&city, "/subdivisions/0/iso_code"),
+        "region_name": text(&city, "/subdivisions/0/names/en"),
+        "city": text(&city, "/city/names/en"),
+        "zip_code": text(&city, "/postal/code"),
+        "time_zone": text(&city, "/location/time_zone"),
+        "latitude": number(&city, "/location/latitude"),
+        "longitude": number(&city, "/location/longitude"),
+        "metro_code": number(&city, "/location/metro_code"),
+    })))
+}
+
+/// The fields of ip-api.com's responses, with the bit selecting each in a numeric `fields`.
+const IP_API_FIELDS: &[(&str, u32)] = &[
+    ("status", 1 << 14),
+    ("message", 1 << 15),
+    ("continent", 1 << 20),
+    ("continentCode", 1 << 21),
+    ("country", 1 << 0),
+    ("countryCode", 1 << 1),
+    ("region", 1 << 2),
+    ("regionName", 1 << 3),
+    ("city", 1 << 4),
+    ("district", 1 << 19),
+    ("zip", 1 << 5),
+    ("lat", 1 << 6),
+    ("lon", 1 << 7),
+    ("timezone", 1 << 8),
+    ("offset", 1 << 25),
+    ("currency", 1 << 23),
+    ("isp", 1 << 9),
+    ("org", 1 << 10),
+    ("as", 1 << 11),
+    ("asname", 1 << 22),
+    ("reverse", 1 << 12),
+    ("mobile", 1 << 16),
+    ("proxy", 1 << 17),
+    ("hosting", 1 << 24),
+    ("query", 1 << 13),
+];
+
+/// What ip-api.com returns without `fields`.
+const IP_API_DEFAULT_FIELDS: &str = "status,message,country,countryCode,region,regionName,city,zip,lat,lon,timezone,isp,org,as,query";
+
+#[derive(Deserialize)]
+struct IpApiQuery {
+    fields: Option<String>,
+    lang: Option<String>,
+}
+
+/// Whether `fields`, a list of names or a bitmask of them, selects `name`.
+fn selects(fields: &str, name: &str) -> bool {
+    match fields.parse::<u32>() {
+        Ok(mask) => IP_API_FIELDS.iter().any(|&(field, bit)| field == name && mask & bit != 0),
+        Err(_) => fields.split(',').map(str::trim).any(|field| field == name),
+    }
+}
+
+/// ip-api.com tells private addresses apart from the other reserved ones.
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some_and(|ip| is_private(IpAddr::V4(ip))) || ip.is_loopback() || ip.segments()[0] & 0xfe00 == 0xfc00 || ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// Looks up the address, or the caller without one, in the City database and, if they are loaded, the ASN, ISP and Anonymous IP ones, answering with the fields of ip-api.com that `fields` selects. Like ip-api.com, failures are `200` responses with `status: fail` and a `message`.
+async fn ip_api(State(state): State<Arc<AppState>>, client: ClientIp, ip: Option<Path<String>>, Query(query): Query<IpApiQuery>) -> Result<Json<Value>, LookupError> {
+    let fields = query.fields.as_deref().unwrap_or(IP_API_DEFAULT_FIELDS);
+    let requested = ip.as_ref().map(|Path(ip)| ip.clone());
+    let ip = match &requested {
+        Some(ip) => parse_ip(ip),
+        None => client.0.ok_or(LookupError::IpAddressRequired),
+    };
+    let fail = |message: &str, query: String| {
+        let response = [("status", json!("fail")), ("message", json!(message)), ("query", json!(query))];
+        Json(Value::Object(response.into_iter().filter(|&(name, _)| selects(fields, name)).map(|(name, value)| (name.to_owned(), value)).collect()))
+    };
+
+    let ip = match ip {
+        Ok(ip) => ip,
+        Err(_) => return Ok(fail("invalid query", requested.unwrap_or_default())),
+    };
+    if is_private(ip) {
+        return Ok(fail("private range", ip.to_string()));
+    }
+    if reserved::is_reserved(ip) {
+        return Ok(fail("reserved range", ip.to_string()));
+    }
+
+    if state.databases.get(DatabaseKind::City).is_none() {
+        return Err(LookupError::DatabaseNotLoaded);
+    }
+    let city = record(&state, DatabaseKind::City, ip).await?;
+    let asn = record(&state, DatabaseKind::Asn, ip).await?;
+    let isp = record(&state, DatabaseKind::Isp, ip).await?;
+    let anonymous = record(&state, DatabaseKind::AnonymousIp, ip).await?;
+
+    // Names in `lang`, or English like ip-api.com if the record doesn't have that locale.
+    let lang = query.lang.unwrap_or_else(|| "en".to_owned());
+    let name = |pointer: &str| match city.pointer(&format!("{pointer}/names/{lang}")) {
+        Some(Value::String(name)) => json!(name),
+        _ => text(&city, &format!("{pointer}/names/en")),
+    };
+    let integer = |record: &Value, pointer: &str| record.pointer(pointer).and_then(Value::as_u64);
+    let string = |record: &Value, pointer: &str| record.pointer(pointer).and_then(Value::as_str).map(str::to_owned);
+
+    let autonomous_system = integer(&isp, "/autonomous_system_number").or_else(|| integer(&asn, "/autonomous_system_number"));
+    let organization = string(&isp, "/autonomous_system_organization").or_else(|| string(&asn, "/autonomous_system_organization")).unwrap_or_default();
+    let as_field = match autonomous_system {
+        Some(number) => format!("AS{number} {organization}"),
+        None => String::new(),
+    };
+
+    let mut response = Map::new();
+    for &(field, _) in IP_API_FIELDS {
+        if !selects(fields, field) {
+            continue;
+        }
+
+        let value = match field {
+            "status" => json!("success"),
+            // Successful responses have no message.
+            "message" => continue,
+            "continent" => name("/continent"),
+            "continentCode" => text(&city, "/continent/code"),
+            "country" => name("/country"),
+            "countryCode" => text(&city, "/country/iso_code"),
+            "region" => text(&city, "/subdivisions/0/iso_code"),
+            "regionName" => name("/subdivisions/0"),
+            "city" => name("/city"),
+            "zip" => text(&city, "/postal/code"),
+            "lat" => number(&city, "/location/latitude"),
+            "lon" => number(&city, "/location/longitude"),
+            "timezone" => text(&city, "/location/time_zone"),
+            "isp" => json!(string(&isp, "/isp").unwrap_or_else(|| organization.clone())),
+            "org" => json!(string(&isp, "/organization").unwrap_or_else(|| organization.clone())),
+            "as" => json!(as_field),
+            "asname" => json!(organization),
+            "mobile" => json!(isp.pointer("/mobile_network_code").is_some()),
+            "proxy" => json!(flag(&anonymous, "/is_anonymous_proxy") || flag(&anonymous, "/is_public_proxy") || flag(&anonymous, "/is_anonymous_vpn") || flag(&anonymous, "/is_tor_exit_node")),
+            "hosting" => json!(flag(&anonymous, "/is_hosting_provider")),
+            "query" => json!(ip.to_string()),
+            "currency" => json!(city.pointer("/country/iso_code").and_then(Value::as_str).and_then(countries::currency).unwrap_or_default()),
+            "offset" => json!(city.pointer("/location/time_zone").and_then(Value::as_str).and_then(timezone::utc_offset_seconds).unwrap_or_default()),
+            // The databases have no district or hostname, which ip-api.com leaves empty when it doesn't know them either.
+            _ => json!(""),
+        };
+        response.insert(field.to_owned(), value);
+    }
+
+    Ok(Json(Value::Object(response)))
+}
+
+/// The fields of ipinfo.io's responses the databases have data for, which `/:ip/:field` returns one at a time.
+const IPINFO_FIELDS: &[&str] = &["ip", "city", "region", "country", "loc", "org", "postal", "timezone"];
+
+/// The location of `ip` in ipinfo.io's flat schema, from the City and, if loaded, ASN database, without the fields the records don't have. Like ipinfo.io, reserved addresses are `bogon` rather than an error.
+async fn ipinfo_record(state: &AppState, ip: IpAddr) -> Result<Map<String, Value>, LookupError> {
+    let mut response = Map::new();
+    response.insert("ip".to_owned(), json!(ip.to_string()));
+    if reserved::is_reserved(ip) {
+        response.insert("bogon".to_owned(), json!(true));
+        return Ok(response);
+    }
+
+    if state.databases.get(DatabaseKind::City).is_none() {
+        return Err(LookupError::DatabaseNotLoaded);
+    }
+    let city = record(state, DatabaseKind::City, ip).await?;
+    let asn = record(state, DatabaseKind::Asn, ip).await?;
+
+    let string = |record: &Value, pointer: &str| record.pointer(pointer).and_then(Value::as_str).map(str::to_owned);
+    let loc = match (city.pointer("/location/latitude").and_then(Value::as_f64), city.pointer("/location/longitude").and_then(Value::as_f64)) {
+        (Some(latitude), Some(longitude)) => Some(format!("{latitude:.4},{longitude:.4}")),
+        _ => None,
+    };
+    let org = match (asn.pointer("/autonomous_system_number").and_then(Value::as_u64), string(&asn, "/autonomous_system_organization")) {
+        (Some(number), Some(organization)) => Some(format!("AS{number} {organization}")),
+        (Some(number), None) => Some(format!("AS{number}")),
+        _ => None,
+    };
+    let fields = [
+        ("city", string(&city, "/city/names/en")),
+        ("region", string(&city, "/subdivisions/0/names/en")),
+        ("country", string(&city, "/country/iso_code")),
+        ("loc", loc),
+        ("org", org),
+        ("postal", string(&city, "/postal/code")),
+        ("timezone", string(&city, "/location/time_zone")),
+    ];
+    response.extend(fields.into_iter().filter_map(|(name, value)| Some((name.to_owned(), json!(value?)))));
+
+    Ok(response)
+}
+
+/// One field of the location as plain text, like ipinfo.io, which answers `undefined` when it doesn't know it.
+async fn ipinfo_text(state: &AppState, ip: IpAddr, field: &str) -> Result<Response, LookupError> {
+    let response = ipinfo_record(state, ip).await?;
+    let value = response.get(field).and_then(Value::as_str).unwrap_or("undefined");
+
+    Ok(format!("{value}\n").into_response())
+}
+
+async fn ipinfo_json(state: &AppState, ip: IpAddr) -> Result<Response, LookupError> {
+    Ok(Json(ipinfo_record(state, ip).await?).into_response())
+}
+
+/// Looks up the caller, like `GET https://ipinfo.io/`.
+async fn ipinfo_caller(State(state): State<Arc<AppState>>, client: ClientIp) -> Result<Response, LookupError> {
+    ipinfo_json(&state, client.0.ok_or(LookupError::IpAddressRequired)?).await
+}
+
+/// Looks up an address, or the caller for `/json` or the name of a field. Other paths aren't addresses but mistakes, so they stay not found.
+async fn ipinfo(State(state): State<Arc<AppState>>, client: ClientIp, Path(segment): Path<String>) -> Result<Response, LookupError> {
+    let caller = || client.0.ok_or(LookupError::IpAddressRequired);
+    match segment.as_str() {
+        "json" => ipinfo_json(&state, caller()?).await,
+        field if IPINFO_FIELDS.contains(&field) => ipinfo_text(&state, caller()?, field).await,
+        ip => ipinfo_json(&state, parse_ip(ip).map_err(|_| LookupError::RouteNotFound)?).await,
+    }
+}
+
+async fn ipinfo_field(State(state): State<Arc<AppState>>, Path((ip, field)): Path<(String, String)>) -> Result<Response, LookupError> {
+    let ip = parse_ip(&ip).map_err(|_| LookupError::RouteNotFound)?;
+    match field.as_str() {
+        "json" => ipinfo_json(&state, ip).await,
+        field if IPINFO_FIELDS.contains(&field) => ipinfo_text(&state, ip, field).await,
+        _ => Err(LookupError::RouteNotFound),
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Context;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// The path given with `--config` or `GEOIP2_CONFIG`, found before the command line is parsed so the file can provide the defaults it is parsed with.
+fn path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    std::env::var_os("GEOIP2_CONFIG").map(PathBuf::from)
+}
+
+/// Reads a TOML file, or a YAML one if its extension says so.
+fn read(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let config = std::fs::read_to_string(path).with_context(|| format!("Failed to read config {}", path.display()))?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&config).with_context(|| format!("Failed to parse config {}", path.display())),
+        _ => toml::from_str(&config).with_context(|| format!("Failed to parse config {}", path.display())),
+    }
+}
+
+/// Settings whose flags take JSON, so a table of them is passed as it is rather than flattened.
+const JSON_SETTINGS: &[&str] = &["private-response"];
+
+/// Flattens the config into flag names and their values: `port = 3000` sets `--port`, and `size` in a `[cache]` table sets `--cache-size`. Underscores in keys may stand in for dashes, and arrays give repeatable flags several values.
+fn flatten(prefix: Option<&str>, config: serde_json::Value, settings: &mut Vec<(String, Vec<String>)>) -> anyhow::Result<()> {
+    let serde_json::Value::Object(config) = config else {
+        anyhow::bail!("Expected a table of settings");
+    };
+
+    for (key, value) in config {
+        let name = match prefix {
+            Some(prefix) => format!("{prefix}-{}", key.replace('_', "-")),
+            None => key.replace('_', "-"),
+        };
+        let values = match value {
+            serde_json::Value::Object(_) if JSON_SETTINGS.contains(&name.as_str()) => vec![value.to_string()],
+            serde_json::Value::Object(_) => {
+                flatten(Some(&name), value, settings)?;
+                continue;
+            }
+            serde_json::Value::Array(values) => values.into_iter().map(|value| scalar(&name, value)).collect::<anyhow::Result<_>>()?,
+            value => vec![scalar(&name, value)?],
+        };
+        settings.push((name, values));
+    }
+
+    Ok(())
+}
+
+fn scalar(name: &str, value: serde_json::Value) -> anyhow::Result<String> {
+    match value {
+        serde_json::Value::String(value) => Ok(value),
+        serde_json::Value::Number(value) => Ok(value.to_string()),
+        serde_json::Value::Bool(value) => Ok(value.to_string()),
+        _ => anyhow::bail!("Setting {name} must be a string, number, boolean or array of those"),
+    }
+}
+
+/// Makes the settings of the config file given in `args`, if any, the defaults of their flags, so environment variables and the command line still override them.
+pub fn apply(mut command: clap::Command, args: &[OsString]) -> anyhow::Result<clap::Command> {
+    let Some(path) = path(args) else {
+        return Ok(command);
+    };
+
+    let mut config = Vec::new();
+    flatten(None, read(&path)?, &mut config).with_context(|| format!("Invalid config {}", path.display()))?;
+
+    for (name, values) in config {
+        let id = command.get_arguments().find(|arg| arg.get_long() == Some(name.as_str())).map(|arg| arg.get_id().clone()).with_context(|| format!("Unknown setting {name} in config {}", path.display()))?;
+        command = command.mut_arg(id, |arg| arg.default_values(values).required(false));
+    }
+
+    Ok(command)
+}
@@ -0,0 +1,402 @@
+use crate::overrides::Overrides;
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use maxminddb::{Mmap, Reader};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::{error, info, warn};
+
+/// The kinds of database the server knows how to serve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DatabaseKind {
+    City,
+    Country,
+    Asn,
+    AnonymousIp,
+    Isp,
+    Domain,
+    ConnectionType,
+    Enterprise,
+    /// Any other database, served as-is by the raw lookup endpoint.
+    Custom,
+}
+
+impl DatabaseKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DatabaseKind::City => "city",
+            DatabaseKind::Country => "country",
+            DatabaseKind::Asn => "asn",
+            DatabaseKind::AnonymousIp => "anonymous-ip",
+            DatabaseKind::Isp => "isp",
+            DatabaseKind::Domain => "domain",
+            DatabaseKind::ConnectionType => "connection-type",
+            DatabaseKind::Enterprise => "enterprise",
+            DatabaseKind::Custom => "custom",
+        }
+    }
+
+    /// The edition ID used to download this kind of database from MaxMind, the GeoLite2 one where there is one.
+    pub fn edition(self) -> Option<&'static str> {
+        match self {
+            DatabaseKind::City => Some("GeoLite2-City"),
+            DatabaseKind::Country => Some("GeoLite2-Country"),
+            DatabaseKind::Asn => Some("GeoLite2-ASN"),
+            DatabaseKind::AnonymousIp => Some("GeoIP2-Anonymous-IP"),
+            DatabaseKind::Isp => Some("GeoIP2-ISP"),
+            DatabaseKind::Domain => Some("GeoIP2-Domain"),
+            DatabaseKind::ConnectionType => Some("GeoIP2-Connection-Type"),
+            DatabaseKind::Enterprise => Some("GeoIP2-Enterprise"),
+            DatabaseKind::Custom => None,
+        }
+    }
+
+    /// Guesses the kind from the `database_type` metadata field, e.g. `GeoLite2-City`.
+    pub fn detect(database_type: &str) -> Option<Self> {
+        if database_type.ends_with("-City") {
+            Some(DatabaseKind::City)
+        } else if database_type.ends_with("-Country") {
+            Some(DatabaseKind::Country)
+        } else if database_type.ends_with("-ASN") {
+            Some(DatabaseKind::Asn)
+        } else if database_type.ends_with("-Anonymous-IP") {
+            Some(DatabaseKind::AnonymousIp)
+        } else if database_type.ends_with("-ISP") {
+            Some(DatabaseKind::Isp)
+        } else if database_type.ends_with("-Domain") {
+            Some(DatabaseKind::Domain)
+        } else if database_type.ends_with("-Connection-Type") {
+            Some(DatabaseKind::ConnectionType)
+        } else if database_type.ends_with("-Enterprise") {
+            Some(DatabaseKind::Enterprise)
+        } else {
+            None
+        }
+    }
+
+    /// The lookup endpoint serving this kind of database.
+    pub fn endpoint(self) -> &'static str {
+        match self {
+            DatabaseKind::City => "/geoip/v2.1/city/:ip",
+            DatabaseKind::Country => "/geoip/v2.1/country/:ip",
+            DatabaseKind::Asn => "/geoip/v2.1/asn/:ip",
+            DatabaseKind::AnonymousIp => "/geoip/v2.1/anonymous-ip/:ip",
+            DatabaseKind::Isp => "/geoip/v2.1/isp/:ip",
+            DatabaseKind::Domain => "/geoip/v2.1/domain/:ip",
+            DatabaseKind::ConnectionType => "/geoip/v2.1/connection-type/:ip",
+            DatabaseKind::Enterprise => "/geoip/v2.1/enterprise/:ip",
+            DatabaseKind::Custom => "/lookup/:ip",
+        }
+    }
+
+    pub const ALL: [DatabaseKind; 9] = [
+        DatabaseKind::City,
+        DatabaseKind::Country,
+        DatabaseKind::Enterprise,
+        DatabaseKind::Asn,
+        DatabaseKind::AnonymousIp,
+        DatabaseKind::Isp,
+        DatabaseKind::Domain,
+        DatabaseKind::ConnectionType,
+        DatabaseKind::Custom,
+    ];
+
+    /// Whether a database with the given `database_type` metadata can be served as this kind. Types this server does not know are given the benefit of the doubt.
+    pub fn serves(self, database_type: &str) -> bool {
+        match DatabaseKind::detect(database_type) {
+            Some(detected) => self.accepts(detected),
+            None => true,
+        }
+    }
+
+    /// Whether a database of a known `detected` kind can be served as this kind. An Enterprise database has everything a City lookup needs, and both have everything a Country lookup needs.
+    pub fn accepts(self, detected: DatabaseKind) -> bool {
+        match self {
+            DatabaseKind::Country => matches!(detected, DatabaseKind::Country | DatabaseKind::City | DatabaseKind::Enterprise),
+            DatabaseKind::City => matches!(detected, DatabaseKind::City | DatabaseKind::Enterprise),
+            DatabaseKind::Custom => true,
+            kind => kind == detected,
+        }
+    }
+}
+
+impl fmt::Display for DatabaseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DatabaseKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "city" => Ok(DatabaseKind::City),
+            "country" => Ok(DatabaseKind::Country),
+            "asn" => Ok(DatabaseKind::Asn),
+            "anonymous-ip" => Ok(DatabaseKind::AnonymousIp),
+            "isp" => Ok(DatabaseKind::Isp),
+            "domain" => Ok(DatabaseKind::Domain),
+            "connection-type" => Ok(DatabaseKind::ConnectionType),
+            "enterprise" => Ok(DatabaseKind::Enterprise),
+            "custom" => Ok(DatabaseKind::Custom),
+            _ => Err(format!("unknown database type `{s}`")),
+        }
+    }
+}
+
+/// A `--database [type=]location` argument. Without an explicit type, it is detected from the database metadata.
+///
+/// The location is either a local path or a URL (`http://`, `https://` or `s3://`). Databases given by URL are downloaded to `path` in the temporary directory before being opened.
+#[derive(Clone, Debug)]
+pub struct DatabaseArg {
+    pub kind: Option<DatabaseKind>,
+    pub path: PathBuf,
+    pub url: Option<String>,
+}
+
+impl FromStr for DatabaseArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, location) = match s.split_once('=') {
+            Some((kind, location)) => match DatabaseKind::from_str(kind) {
+                Ok(kind) => (Some(kind), location),
+                Err(_) => (None, s),
+            },
+            None => (None, s),
+        };
+
+        if ["http://", "https://", "s3://"].iter().any(|scheme| location.starts_with(scheme)) {
+            let name = location.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("database.mmdb");
+            // Mirrors of a database have the same file name, and several servers may run on a host, so the name alone is not enough to tell their files apart.
+            let fingerprint = format!("{:x}", Sha256::digest(location));
+            let path = std::env::temp_dir().join(format!("geoip2-server-{}-{}-{name}", std::process::id(), &fingerprint[..12]));
+
+            return Ok(DatabaseArg { kind, path, url: Some(location.to_owned()) });
+        }
+
+        Ok(DatabaseArg { kind, path: PathBuf::from(location), url: None })
+    }
+}
+
+/// The databases given as `GEOIP2_DB_<TYPE>=location` environment variables, e.g. `GEOIP2_DB_CITY` or `GEOIP2_DB_ANONYMOUS_IP`.
+pub fn from_env() -> Result<Vec<DatabaseArg>, String> {
+    DatabaseKind::ALL
+        .iter()
+        .filter_map(|kind| {
+            let location = std::env::var(format!("GEOIP2_DB_{}", kind.name().to_ascii_uppercase().replace('-', "_"))).ok()?;
+            Some(DatabaseArg::from_str(&format!("{kind}={location}")))
+        })
+        .collect()
+}
+
+/// Checks that `mmdb` is a valid database and writes it to `path`. The file is written next to `path` and renamed over it, so a reader mapping the old file is unaffected.
+pub fn install(path: &Path, mmdb: &[u8]) -> anyhow::Result<()> {
+    Reader::from_source(mmdb).context("Not a valid database")?;
+
+    let download = path.with_extension("mmdb.download");
+    std::fs::write(&download, mmdb).with_context(|| format!("Failed to write {}", download.display()))?;
+    std::fs::rename(&download, path).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// The contents of a database file, either mapped into memory or, with `--in-memory`, read into an owned buffer.
+pub enum Source {
+    Mmap(Mmap),
+    Memory(Vec<u8>),
+}
+
+impl AsRef<[u8]> for Source {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Source::Mmap(mmap) => mmap,
+            Source::Memory(buffer) => buffer,
+        }
+    }
+}
+
+/// Opens the database at `path`, reading it entirely into memory if `in_memory`. That takes more memory than mapping it, but lookups never wait on page faults for parts of the file that aren't cached, which matters most on networked filesystems.
+fn open_reader(path: &Path, in_memory: bool) -> anyhow::Result<Reader<Source>> {
+    let source = match in_memory {
+        true => Source::Memory(std::fs::read(path).with_context(|| format!("Failed to read database {}", path.display()))?),
+        false => {
+            let file = std::fs::File::open(path).with_context(|| format!("Failed to open database {}", path.display()))?;
+            // SAFETY: databases are only ever replaced by renaming a new file over them (see `install`), never modified in place, so the mapped file doesn't change under the reader.
+            Source::Mmap(unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to map database {}", path.display()))?)
+        }
+    };
+
+    Reader::from_source(source).with_context(|| format!("Failed to open database {}", path.display()))
+}
+
+/// A loaded database whose reader can be swapped out while requests are being served.
+pub struct Database {
+    pub kind: DatabaseKind,
+    /// Where the database is in the chain of its kind, 0 for the one asked first.
+    pub position: usize,
+    pub path: PathBuf,
+    in_memory: bool,
+    reader: ArcSwap<Reader<Source>>,
+}
+
+impl Database {
+    fn open(kind: Option<DatabaseKind>, path: PathBuf, in_memory: bool) -> anyhow::Result<Self> {
+        let reader = open_reader(&path, in_memory)?;
+        let database_type = &reader.metadata.database_type;
+        let kind = match (kind, DatabaseKind::detect(database_type)) {
+            (Some(kind), Some(detected)) if !kind.accepts(detected) => anyhow::bail!("Database {} is a {database_type} database, which cannot be served as {kind}", path.display()),
+            (Some(DatabaseKind::Custom), _) => DatabaseKind::Custom,
+            (Some(kind), None) => {
+                warn!("database {} has unknown type {database_type}, serving it as {kind}", path.display());
+                kind
+            }
+            (Some(kind), Some(_)) => kind,
+            (None, Some(detected)) => detected,
+            (None, None) => anyhow::bail!("Cannot detect the type of database {} ({database_type}), pass it as type=path", path.display()),
+        };
+
+        Ok(Database { kind, position: 0, path, in_memory, reader: ArcSwap::from_pointee(reader) })
+    }
+
+    /// How the database is named in `/status`, `/metadata` and metrics: its kind, followed by its position for the fallbacks of a chain, e.g. `city.1`.
+    pub fn name(&self) -> String {
+        match self.position {
+            0 => self.kind.to_string(),
+            position => format!("{}.{position}", self.kind),
+        }
+    }
+
+    /// Returns the current reader. Callers keep using it until they drop it, even if the database is reloaded in the meantime.
+    pub fn reader(&self) -> Arc<Reader<Source>> {
+        self.reader.load_full()
+    }
+
+    /// How the database is held: `mmap` or `memory`.
+    pub fn mode(&self) -> &'static str {
+        if self.in_memory {
+            "memory"
+        } else {
+            "mmap"
+        }
+    }
+
+    /// Reopens the database from its path and atomically swaps it in, returning the previous reader. On error the current reader stays active.
+    pub fn reload(&self) -> anyhow::Result<Arc<Reader<Source>>> {
+        let reader = open_reader(&self.path, self.in_memory)?;
+        if !self.kind.serves(&reader.metadata.database_type) {
+            warn!("reloaded {} database {} is a {} database, lookups will fail until it is replaced", self.kind, self.path.display(), reader.metadata.database_type);
+        }
+        metrics::gauge!("geoip_database_build_epoch", "database" => self.name()).set(reader.metadata.build_epoch as f64);
+
+        Ok(self.reader.swap(Arc::new(reader)))
+    }
+}
+
+/// All databases loaded by the server, and the overrides of their records. Each kind has a chain of databases, in the order they were given, and the first one that has a record for an address answers for it, e.g. a commercial database, then GeoLite2 for what it lacks.
+#[derive(Default)]
+pub struct Databases {
+    databases: BTreeMap<DatabaseKind, Vec<Database>>,
+    overrides: Option<Overrides>,
+}
+
+impl Databases {
+    /// Opens the databases of `args`, mapping them into memory unless `in_memory`, in which case they are read into memory instead.
+    pub fn open(args: &[DatabaseArg], in_memory: bool) -> anyhow::Result<Self> {
+        let mut databases = BTreeMap::<_, Vec<_>>::new();
+
+        for arg in args {
+            let mut database = Database::open(arg.kind, arg.path.clone(), in_memory)?;
+            let chain = databases.entry(database.kind).or_default();
+            database.position = chain.len();
+
+            metrics::gauge!("geoip_database_build_epoch", "database" => database.name()).set(database.reader().metadata.build_epoch as f64);
+            chain.push(database);
+        }
+
+        Ok(Databases { databases, overrides: None })
+    }
+
+    /// Applies `overrides` to the records of the databases, reloading them along with the databases.
+    pub fn with_overrides(self, overrides: Overrides) -> Self {
+        Databases { overrides: Some(overrides), ..self }
+    }
+
+    pub fn overrides(&self) -> Option<&Overrides> {
+        self.overrides.as_ref()
+    }
+
+    /// Returns the chain of databases serving `kind`. When no database of that kind is loaded, the chain of a kind that is a superset of it is used instead; see [`DatabaseKind::accepts`].
+    pub fn chain(&self, kind: DatabaseKind) -> &[Database] {
+        let fallbacks: &[DatabaseKind] = match kind {
+            DatabaseKind::Country => &[DatabaseKind::City, DatabaseKind::Enterprise],
+            DatabaseKind::City => &[DatabaseKind::Enterprise],
+            _ => &[],
+        };
+
+        std::iter::once(&kind).chain(fallbacks).find_map(|kind| self.databases.get(kind)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the first database of the chain serving `kind`.
+    pub fn get(&self, kind: DatabaseKind) -> Option<&Database> {
+        self.chain(kind).first()
+    }
+
+    /// Returns the current readers of the chain serving `kind`, in order, or `None` if no database serves it.
+    pub fn readers(&self, kind: DatabaseKind) -> Option<Vec<Arc<Reader<Source>>>> {
+        let chain = self.chain(kind);
+
+        (!chain.is_empty()).then(|| chain.iter().map(Database::reader).collect())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Database> {
+        self.databases.values().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.databases.is_empty()
+    }
+
+    /// Reloads every database, logging the build epochs before and after so the swap can be confirmed. A database that fails to reopen keeps serving its current reader.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        self.reload_matching(|_| true)
+    }
+
+    /// Like [`Databases::reload`], but only for the databases `filter` returns true for. The overrides are reread either way.
+    pub fn reload_matching(&self, filter: impl Fn(&Database) -> bool) -> anyhow::Result<()> {
+        let mut failed = 0;
+
+        for database in self.iter().filter(|database| filter(database)) {
+            match database.reload() {
+                Ok(old) => info!(old_build_epoch = old.metadata.build_epoch, new_build_epoch = database.reader().metadata.build_epoch, "reloaded {} database from {}", database.name(), database.path.display()),
+                Err(err) => {
+                    error!("failed to reload {} database: {err:#}", database.name());
+                    failed += 1;
+                }
+            }
+        }
+
+        if let Some(overrides) = &self.overrides {
+            match overrides.reload() {
+                Ok(len) => info!("reloaded {len} overrides from {}", overrides.path.display()),
+                Err(err) => {
+                    error!("failed to reload overrides: {err:#}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} database(s) failed to reload");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Context;
+use ipnetwork::IpNetwork;
+use maxminddb::{MaxMindDBError, Reader};
+use serde_json::Value;
+use std::path::Path;
+
+/// What is compared between two builds, as JSON pointers into their records: the city by its GeoNames ID, as its names may be translated differently.
+const FIELDS: [(&str, &str); 3] = [("country", "/country/iso_code"), ("city", "/city/geoname_id"), ("asn", "/autonomous_system_number")];
+
+/// How many changed networks are listed per field before only counting them.
+const MAX_EXAMPLES: usize = 20;
+
+fn everything(reader: &Reader<Vec<u8>>) -> IpNetwork {
+    let network = if reader.metadata.ip_version == 6 { "::/0" } else { "0.0.0.0/0" };
+    network.parse().expect("valid network")
+}
+
+/// Picks `count` networks of a database, spread evenly over its search tree.
+pub fn sample(reader: &Reader<Vec<u8>>, count: usize) -> anyhow::Result<Vec<IpNetwork>> {
+    let total = reader.within::<serde::de::IgnoredAny>(everything(reader))?.count();
+    let step = (total / count.max(1)).max(1);
+
+    reader.within::<serde::de::IgnoredAny>(everything(reader))?.step_by(step).take(count).map(|item| Ok(item?.ip_net)).collect()
+}
+
+/// Reads networks or addresses from `path`, one per line. Blank lines and lines starting with `#` are ignored.
+pub fn read_networks(path: &Path) -> anyhow::Result<Vec<IpNetwork>> {
+    let networks = std::fs::read_to_string(path).with_context(|| format!("Failed to read networks from {}", path.display()))?;
+
+    networks
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| line.parse().with_context(|| format!("Line {number} of {} is not a network or address", path.display())))
+        .collect()
+}
+
+/// The compared fields of the record of the first address of `network`, or `None` if it isn't in the database.
+fn fields(reader: &Reader<Vec<u8>>, network: IpNetwork) -> anyhow::Result<Option<[Option<Value>; 3]>> {
+    let record = match reader.lookup::<Value>(network.network()) {
+        Ok(record) => record,
+        Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to look up {network}")),
+    };
+
+    Ok(Some(FIELDS.map(|(_, pointer)| record.pointer(pointer).cloned())))
+}
+
+/// Looks `networks` up in both builds and counts those that were added, removed, or changed each field, with a few examples of each change.
+pub fn compare(old: &Reader<Vec<u8>>, new: &Reader<Vec<u8>>, networks: &[IpNetwork]) -> anyhow::Result<Value> {
+    let (mut added, mut removed, mut unchanged) = (0, 0, 0);
+    let mut changed = FIELDS.map(|_| (0, Vec::new()));
+
+    for &network in networks {
+        match (fields(old, network)?, fields(new, network)?) {
+            (None, None) => unchanged += 1,
+            (None, Some(_)) => added += 1,
+            (Some(_), None) => removed += 1,
+            (Some(before), Some(after)) => {
+                let mut same = true;
+                for (index, (before, after)) in before.into_iter().zip(after).enumerate() {
+                    if before == after {
+                        continue;
+                    }
+                    same = false;
+
+                    let (count, examples) = &mut changed[index];
+                    *count += 1;
+                    if examples.len() < MAX_EXAMPLES {
+                        examples.push(serde_json::json!({ "network": network.to_string(), "old": before, "new": after }));
+                    }
+                }
+                unchanged += same as usize;
+            }
+        }
+    }
+
+    let changed = FIELDS
+        .iter()
+        .zip(changed)
+        .map(|((name, _), (count, examples))| (name.to_string(), serde_json::json!({ "count": count, "examples": examples })))
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok(serde_json::json!({
+        "compared": networks.len(),
+        "unchanged": unchanged,
+        "added": added,
+        "removed": removed,
+        "changed": changed,
+    }))
+}
@@ -0,0 +1,49 @@
+use crate::{client_ip::ClientIp, database::DatabaseKind, lookup_shared, resolve_ip, AppState, LookupError};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use maxminddb::geoip2;
+use serde_json::{json, Value};
+use std::{net::IpAddr, sync::Arc};
+
+/// The mean radius of the earth, in kilometers.
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+const KM_PER_MILE: f64 = 1.609344;
+
+/// The great-circle distance between two coordinates in kilometers, by the haversine formula.
+fn haversine((latitude_a, longitude_a): (f64, f64), (latitude_b, longitude_b): (f64, f64)) -> f64 {
+    let (phi_a, phi_b) = (latitude_a.to_radians(), latitude_b.to_radians());
+    let (delta_phi, delta_lambda) = ((latitude_b - latitude_a).to_radians(), (longitude_b - longitude_a).to_radians());
+    let a = (delta_phi / 2.0).sin().powi(2) + phi_a.cos() * phi_b.cos() * (delta_lambda / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+/// The location of `ip` in the City database, as its coordinates and the JSON describing them. Addresses the database has no coordinates for are not found, as there is nothing to measure from.
+async fn location(state: &AppState, ip: IpAddr) -> Result<((f64, f64), Value), LookupError> {
+    let readers = state.databases.readers(DatabaseKind::City).ok_or(LookupError::DatabaseNotLoaded)?;
+    let city = lookup_shared::<geoip2::City>(DatabaseKind::City, &readers, ip, state).await?;
+    let city: Value = serde_json::from_slice(&city).expect("records are valid JSON");
+
+    let location = &city["location"];
+    let (Some(latitude), Some(longitude)) = (location["latitude"].as_f64(), location["longitude"].as_f64()) else {
+        return Err(LookupError::IpAddressNotFound);
+    };
+
+    Ok(((latitude, longitude), json!({ "ip": ip.to_string(), "latitude": latitude, "longitude": longitude, "accuracy_radius": location["accuracy_radius"] })))
+}
+
+/// Looks up the locations of two addresses, either of which may be `me`, and the distance between them, e.g. to flag logins that moved faster than anyone can travel. The accuracy radii, in kilometers, bound how far off the distance may be.
+pub async fn between(State(state): State<Arc<AppState>>, client: ClientIp, Path((ip_a, ip_b)): Path<(String, String)>) -> Result<Json<Value>, LookupError> {
+    let (ip_a, ip_b) = (resolve_ip(&ip_a, client)?, resolve_ip(&ip_b, client)?);
+    let (coordinates_a, from) = location(&state, ip_a).await?;
+    let (coordinates_b, to) = location(&state, ip_b).await?;
+    let km = haversine(coordinates_a, coordinates_b);
+
+    // To the meter, which is far more precise than the locations already.
+    let round = |distance: f64| (distance * 1000.0).round() / 1000.0;
+
+    Ok(Json(json!({ "distance_km": round(km), "distance_miles": round(km / KM_PER_MILE), "from": from, "to": to })))
+}
@@ -0,0 +1,178 @@
+use crate::{access_log, rate_limit::RateLimiter, AppState, LookupError};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode, Uri},
+    middleware::Next,
+    response::Response,
+};
+use hickory_resolver::{
+    config::{LookupIpStrategy, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use serde_json::Value;
+use std::{net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+use tracing::warn;
+
+/// How long an address without a PTR record, or whose lookup timed out, is answered without asking DNS again.
+const NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+/// Which address family to look a hostname up by when it has both.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AddressPreference {
+    #[default]
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for AddressPreference {
+    type Err = String;
+
+    fn from_str(preference: &str) -> Result<Self, Self::Err> {
+        match preference {
+            "ipv4" => Ok(AddressPreference::Ipv4),
+            "ipv6" => Ok(AddressPreference::Ipv6),
+            preference => Err(format!("Invalid address preference {preference}, expected ipv4 or ipv6")),
+        }
+    }
+}
+
+/// Resolves the hostnames of `?resolve=true` lookups, through the resolvers of the system, so lookups of the same names are cached by it.
+pub struct HostnameResolver {
+    resolver: TokioAsyncResolver,
+    /// Limits the resolutions per authenticated caller or client address, like `--rate-limit`, on top of the rate limit of the lookups, as each one may go out to the network.
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl HostnameResolver {
+    /// Falls back to the default resolvers if the system configuration can't be read, e.g. in containers without `/etc/resolv.conf`.
+    pub fn new(preference: AddressPreference, limiter: Option<Arc<RateLimiter>>) -> Self {
+        let (config, mut options) = system_conf();
+        options.ip_strategy = match preference {
+            AddressPreference::Ipv4 => LookupIpStrategy::Ipv4thenIpv6,
+            AddressPreference::Ipv6 => LookupIpStrategy::Ipv6thenIpv4,
+        };
+
+        HostnameResolver {
+            resolver: TokioAsyncResolver::tokio(config, options),
+            limiter,
+        }
+    }
+
+    /// Resolves `hostname` to its first address of the preferred family, taking a resolution from the bucket of `caller`.
+    pub async fn resolve(&self, hostname: &str, caller: &str) -> Result<IpAddr, LookupError> {
+        if !is_hostname(hostname) {
+            return Err(LookupError::IpAddressInvalid);
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(caller).map_err(|_| LookupError::RateLimitExceeded)?;
+        }
+
+        let addresses = self.resolver.lookup_ip(hostname).await.map_err(|_| LookupError::HostnameNotResolved)?;
+        let ip = addresses.iter().next().ok_or(LookupError::HostnameNotResolved)?;
+
+        Ok(ip.to_canonical())
+    }
+}
+
+/// Looks up the PTR records of addresses for `?rdns=true`, giving up on them after a short timeout so that slow name servers don't hold up lookups.
+pub struct ReverseResolver {
+    resolver: TokioAsyncResolver,
+    timeout: Duration,
+    /// Addresses that had no hostname, on top of the negative caching of the resolver, which doesn't remember timeouts.
+    misses: moka::sync::Cache<IpAddr, ()>,
+}
+
+impl ReverseResolver {
+    pub fn new(timeout: Duration) -> Self {
+        let (config, mut options) = system_conf();
+        options.timeout = timeout;
+        options.attempts = 1;
+        options.negative_min_ttl = Some(NEGATIVE_TTL);
+
+        ReverseResolver {
+            resolver: TokioAsyncResolver::tokio(config, options),
+            timeout,
+            misses: moka::sync::Cache::builder().max_capacity(100_000).time_to_live(NEGATIVE_TTL).build(),
+        }
+    }
+
+    /// The first hostname of the PTR records of `ip`, without the trailing dot.
+    pub async fn hostname(&self, ip: IpAddr) -> Option<String> {
+        if self.misses.contains_key(&ip) {
+            return None;
+        }
+
+        let hostname = match tokio::time::timeout(self.timeout, self.resolver.reverse_lookup(ip)).await {
+            Ok(Ok(names)) => names.iter().next().map(|name| name.0.to_utf8().trim_end_matches('.').to_owned()),
+            _ => None,
+        };
+        if hostname.is_none() {
+            self.misses.insert(ip, ());
+        }
+
+        hostname
+    }
+}
+
+/// Falls back to the default resolvers if the system configuration can't be read, e.g. in containers without `/etc/resolv.conf`.
+fn system_conf() -> (ResolverConfig, ResolverOpts) {
+    hickory_resolver::system_conf::read_system_conf().unwrap_or_else(|err| {
+        warn!("failed to read the system resolver configuration, resolving with the defaults: {err}");
+        (ResolverConfig::default(), ResolverOpts::default())
+    })
+}
+
+/// Whether `name` could be a hostname, so that anything else is rejected as an invalid address without asking DNS.
+fn is_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    name.len() <= 253
+        && name.contains('.')
+        && name
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63 && label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-') && !label.starts_with('-') && !label.ends_with('-'))
+}
+
+fn flag(uri: &Uri, name: &str) -> bool {
+    uri.query().is_some_and(|query| query.split('&').filter_map(|pair| pair.split_once('=')).any(|(key, value)| key == name && matches!(value, "true" | "1")))
+}
+
+/// Whether a request asks for the hostname in its path to be resolved, with `?resolve=true`.
+pub fn wants_resolution(uri: &Uri) -> bool {
+    flag(uri, "resolve")
+}
+
+/// Whether a request asks for the PTR record of the address it looks up, with `?rdns=true`.
+pub fn wants_hostname(uri: &Uri) -> bool {
+    flag(uri, "rdns")
+}
+
+/// Adds the `hostname` of the looked up address to successful lookups with `?rdns=true`, under `traits` for records that have them and at the top level otherwise, like `ip_address`. It is `null` for addresses without a PTR record.
+pub async fn add_hostname(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(reverse_dns) = state.reverse_dns.clone().filter(|_| wants_hostname(request.uri())) else {
+        return next.run(request).await;
+    };
+
+    let response = next.run(request).await;
+    let Some(ip) = response.extensions().get::<access_log::Lookup>().map(|lookup| lookup.ip).filter(|_| response.status() == StatusCode::OK) else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(mut record) = serde_json::from_slice::<Value>(&body) else {
+        return Response::from_parts(parts, Body::from(body));
+    };
+
+    let hostname = Value::from(reverse_dns.hostname(ip).await);
+    match record.get_mut("traits").and_then(Value::as_object_mut) {
+        Some(traits) => traits.insert("hostname".to_owned(), hostname),
+        None => record.as_object_mut().and_then(|record| record.insert("hostname".to_owned(), hostname)),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(serde_json::to_vec(&record).expect("records serialize")))
+}
@@ -0,0 +1,118 @@
+use crate::{
+    database::{DatabaseKind, Source},
+    decode_kind, parse_ip,
+};
+use anyhow::Context;
+use maxminddb::Reader;
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+    sync::Arc,
+};
+
+/// How many rows are read, enriched in parallel, and written at a time.
+const BATCH: usize = 16384;
+
+/// Looks up the addresses of rows and picks the requested fields of their records.
+pub struct Enricher {
+    pub readers: Vec<(DatabaseKind, Arc<Reader<Source>>)>,
+    pub network: bool,
+    /// The requested fields as dotted paths, e.g. `country.iso_code`.
+    pub fields: Vec<String>,
+}
+
+impl Enricher {
+    /// The value of each field for `ip`, taken from the first database whose record has it.
+    fn values(&self, ip: &str) -> Vec<Option<serde_json::Value>> {
+        let Ok(ip) = parse_ip(ip) else {
+            return vec![None; self.fields.len()];
+        };
+
+        let records = self
+            .readers
+            .iter()
+            .filter_map(|(kind, reader)| decode_kind(*kind, reader, ip, self.network).ok())
+            .filter_map(|record| serde_json::from_slice::<serde_json::Value>(&record).ok())
+            .collect::<Vec<_>>();
+
+        self.fields.iter().map(|field| records.iter().find_map(|record| record.pointer(&format!("/{}", field.replace('.', "/"))).cloned())).collect()
+    }
+
+    /// Enriches the rows of a batch on every core, keeping their order.
+    fn batch<T: Sync>(&self, rows: &[T], ip: impl Fn(&T) -> &str + Sync) -> Vec<Vec<Option<serde_json::Value>>> {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk = rows.len().div_ceil(threads).max(1);
+
+        std::thread::scope(|scope| {
+            let chunks = rows.chunks(chunk).map(|rows| scope.spawn(|| rows.iter().map(|row| self.values(ip(row))).collect::<Vec<_>>())).collect::<Vec<_>>();
+            chunks.into_iter().flat_map(|chunk| chunk.join().expect("enrichment thread panicked")).collect()
+        })
+    }
+
+    /// Copies a CSV file to `output`, appending a column per field to the right of each row, filled from the address in `column`.
+    pub fn csv(&self, input: &Path, column: &str, output: impl Write) -> anyhow::Result<()> {
+        let mut reader = csv::Reader::from_path(input).with_context(|| format!("Failed to open {}", input.display()))?;
+        let headers = reader.headers()?.clone();
+        let index = headers.iter().position(|header| header == column).with_context(|| format!("{} has no {column} column", input.display()))?;
+
+        let mut writer = csv::Writer::from_writer(output);
+        writer.write_record(headers.iter().chain(self.fields.iter().map(String::as_str)))?;
+
+        let mut rows = reader.into_records();
+        loop {
+            let batch = rows.by_ref().take(BATCH).collect::<Result<Vec<_>, _>>()?;
+            if batch.is_empty() {
+                break;
+            }
+
+            for (row, values) in batch.iter().zip(self.batch(&batch, |row| row.get(index).unwrap_or_default())) {
+                for field in row {
+                    writer.write_field(field)?;
+                }
+                for value in values {
+                    writer.write_field(match value {
+                        Some(serde_json::Value::String(value)) => value,
+                        Some(serde_json::Value::Null) | None => String::new(),
+                        Some(value) => value.to_string(),
+                    })?;
+                }
+                writer.write_record(None::<&[u8]>)?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Copies an NDJSON file to `output`, adding a key per field to each object, filled from the address under `column`.
+    pub fn ndjson(&self, input: &Path, column: &str, mut output: impl Write) -> anyhow::Result<()> {
+        let file = std::fs::File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+        let mut lines = std::io::BufReader::new(file).lines().enumerate();
+
+        loop {
+            let batch = lines
+                .by_ref()
+                .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .take(BATCH)
+                .map(|(index, line)| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&line?).with_context(|| format!("Line {} of {} is not a JSON object", index + 1, input.display())))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if batch.is_empty() {
+                break;
+            }
+
+            let values = self.batch(&batch, |row| row.get(column).and_then(serde_json::Value::as_str).unwrap_or_default());
+            for (mut row, values) in batch.into_iter().zip(values) {
+                for (field, value) in self.fields.iter().zip(values) {
+                    row.insert(field.clone(), value.unwrap_or_default());
+                }
+                serde_json::to_writer(&mut output, &row)?;
+                output.write_all(b"\n")?;
+            }
+        }
+
+        output.flush()?;
+
+        Ok(())
+    }
+}
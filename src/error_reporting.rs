@@ -0,0 +1,61 @@
+use crate::{anonymize, request_id};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use sentry::{integrations::tracing::EventFilter, ClientInitGuard, ClientOptions, Hub, SentryFutureExt};
+use std::sync::Arc;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// Reports to the Sentry project of `dsn`, with the version of the server as the release. Events are sent in the background until the guard is dropped, which flushes the ones still queued.
+pub fn init(dsn: &str) -> anyhow::Result<ClientInitGuard> {
+    let dsn = dsn.parse::<sentry::types::Dsn>()?;
+
+    Ok(sentry::init(ClientOptions {
+        dsn: Some(dsn),
+        release: sentry::release_name!(),
+        attach_stacktrace: true,
+        ..Default::default()
+    }))
+}
+
+/// A layer reporting error events, like failed database reloads, as Sentry events, with the warnings and info events before them as breadcrumbs. Handler panics are only a breadcrumb, as the panic hook already reported them with their backtrace.
+pub fn layer<S: Subscriber + for<'span> LookupSpan<'span>>() -> impl Layer<S> {
+    sentry::integrations::tracing::layer().event_filter(|metadata| match *metadata.level() {
+        Level::ERROR if metadata.fields().field("panic").is_some() => EventFilter::Breadcrumb,
+        Level::ERROR => EventFilter::Event,
+        Level::WARN | Level::INFO => EventFilter::Breadcrumb,
+        _ => EventFilter::Ignore,
+    })
+}
+
+/// Handles a request with a hub of its own, so that what is reported while handling it carries its method, route and ID, and reports server errors nothing else reported. `503`s are left out, as they are answered on purpose while overloaded or not ready.
+pub async fn capture(request: Request, next: Next) -> Response {
+    if Hub::current().client().is_none() {
+        return next.run(request).await;
+    }
+
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    let method = request.method().clone();
+    let route = request.extensions().get::<MatchedPath>().map_or("unmatched", MatchedPath::as_str).to_owned();
+    hub.configure_scope(|scope| {
+        scope.set_tag("http.method", &method);
+        scope.set_tag("http.route", &route);
+        if let Some(request_id) = request_id::id(&request) {
+            scope.set_tag("request_id", request_id);
+        }
+        scope.set_extra("uri", anonymize::text(&request.uri().to_string()).into());
+    });
+
+    let response = next.run(request).bind_hub(hub.clone()).await;
+
+    let status = response.status();
+    if status.is_server_error() && status != StatusCode::SERVICE_UNAVAILABLE && hub.last_event_id().is_none() {
+        hub.capture_message(&format!("{method} {route} answered {status}"), sentry::Level::Error);
+    }
+
+    response
+}
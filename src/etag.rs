@@ -0,0 +1,73 @@
+use crate::{client_ip::ClientIp, AppState};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Lookup results only change when a database is reloaded, so the tag of a response is a hash of the build epochs of the databases, the overrides, and everything about the request that affects the response: its path and query, its client address for lookups of `me`, its locales and the formats it accepts.
+fn etag(state: &AppState, client: ClientIp, request: &Request, me: bool) -> String {
+    let mut hasher = Sha256::new();
+
+    for database in state.databases.iter() {
+        hasher.update(database.reader().metadata.build_epoch.to_be_bytes());
+    }
+    if let Some(overrides) = state.databases.overrides() {
+        hasher.update(overrides.digest());
+    }
+    hasher.update(request.uri().path_and_query().map(|path| path.as_str()).unwrap_or_default());
+    if let (true, Some(ip)) = (me, client.0) {
+        hasher.update(ip.to_string());
+    }
+    for name in [header::ACCEPT_LANGUAGE, header::ACCEPT] {
+        if let Some(value) = request.headers().get(name) {
+            hasher.update(value.as_bytes());
+        }
+    }
+
+    let digest = format!("{:x}", hasher.finalize());
+    format!("\"{}\"", &digest[..32])
+}
+
+fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    if_none_match.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Adds an `ETag` and, with `--cache-max-age`, a `Cache-Control` header to successful lookups, and answers `304` without looking anything up when `If-None-Match` has the current tag.
+pub async fn conditional(State(state): State<Arc<AppState>>, client: ClientIp, request: Request, next: Next) -> Response {
+    // What a hostname resolves to, or what the hostname of an address is, can change at any time.
+    if request.method() != Method::GET || crate::dns::wants_resolution(request.uri()) || crate::dns::wants_hostname(request.uri()) {
+        return next.run(request).await;
+    }
+
+    let me = request.uri().path().split('/').any(|segment| segment == "me");
+    let etag = etag(&state, client, &request, me);
+
+    let mut response = match matches(request.headers(), &etag) {
+        true => StatusCode::NOT_MODIFIED.into_response(),
+        false => next.run(request).await,
+    };
+
+    if !matches!(response.status(), StatusCode::OK | StatusCode::NOT_MODIFIED) {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    headers.insert(header::ETAG, HeaderValue::from_str(&etag).expect("ETag is a valid header value"));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-language, accept"));
+
+    if let Some(max_age) = state.cache_max_age {
+        // Responses to `me` lookups, or behind authentication, must not be served to anyone else from a shared cache.
+        let scope = if me || state.auth.is_enabled() { "private" } else { "public" };
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_str(&format!("{scope}, max-age={}", max_age.as_secs())).expect("Cache-Control is a valid header value"));
+    }
+
+    response
+}
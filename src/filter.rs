@@ -0,0 +1,131 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::convert::Infallible;
+
+#[derive(Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+/// The fields a client wants from `?fields=location.latitude,country.iso_code`. Empty means the whole record.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fields(Vec<Vec<String>>);
+
+impl Fields {
+    pub fn parse(list: &str) -> Self {
+        Fields(list.split(',').map(str::trim).filter(|field| !field.is_empty()).map(|field| field.split('.').map(str::to_owned).collect()).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Reduces `record` to the requested fields, keeping their nesting. Fields that are missing from the record are left out, and a path through an array applies to each element of it.
+    pub fn apply(&self, record: &mut Value) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let paths = self.0.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        *record = project(record, &paths).unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+    }
+}
+
+fn project(value: &Value, paths: &[&[String]]) -> Option<Value> {
+    if paths.iter().any(|path| path.is_empty()) {
+        return Some(value.clone());
+    }
+
+    match value {
+        Value::Object(object) => {
+            let mut projected = serde_json::Map::new();
+
+            for (key, value) in object {
+                let rest = paths.iter().filter(|path| path[0] == *key).map(|path| &path[1..]).collect::<Vec<_>>();
+                if rest.is_empty() {
+                    continue;
+                }
+
+                if let Some(value) = project(value, &rest) {
+                    projected.insert(key.clone(), value);
+                }
+            }
+
+            (!projected.is_empty()).then_some(Value::Object(projected))
+        }
+        Value::Array(values) => {
+            let projected = values.iter().filter_map(|value| project(value, paths)).collect::<Vec<_>>();
+            (!projected.is_empty()).then_some(Value::Array(projected))
+        }
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Fields {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match Query::<FieldsQuery>::try_from_uri(&parts.uri) {
+            Ok(Query(FieldsQuery { fields: Some(fields) })) => Ok(Fields::parse(&fields)),
+            _ => Ok(Fields::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Value {
+        json!({
+            "country": { "iso_code": "GB", "names": { "en": "United Kingdom" } },
+            "location": { "latitude": 51.5142, "longitude": -0.0931, "time_zone": "Europe/London" },
+            "subdivisions": [{ "iso_code": "ENG", "geoname_id": 6269131 }, { "iso_code": "LND" }]
+        })
+    }
+
+    fn apply(fields: &str) -> Value {
+        let mut record = record();
+        Fields::parse(fields).apply(&mut record);
+        record
+    }
+
+    #[test]
+    fn empty_keeps_record() {
+        assert_eq!(apply(""), record());
+    }
+
+    #[test]
+    fn nested_fields() {
+        assert_eq!(apply("location.latitude,location.longitude,country.iso_code"), json!({ "country": { "iso_code": "GB" }, "location": { "latitude": 51.5142, "longitude": -0.0931 } }));
+    }
+
+    #[test]
+    fn whole_subtree() {
+        assert_eq!(apply("country"), json!({ "country": { "iso_code": "GB", "names": { "en": "United Kingdom" } } }));
+    }
+
+    #[test]
+    fn missing_fields_are_left_out() {
+        assert_eq!(apply("city.names.en,location.accuracy_radius,country.iso_code"), json!({ "country": { "iso_code": "GB" } }));
+        assert_eq!(apply("postal.code"), json!({}));
+    }
+
+    #[test]
+    fn fields_through_arrays() {
+        assert_eq!(apply("subdivisions.iso_code"), json!({ "subdivisions": [{ "iso_code": "ENG" }, { "iso_code": "LND" }] }));
+        assert_eq!(apply("subdivisions.geoname_id"), json!({ "subdivisions": [{ "geoname_id": 6269131 }] }));
+    }
+
+    #[test]
+    fn path_into_scalar() {
+        assert_eq!(apply("country.iso_code.value"), json!({}));
+    }
+}
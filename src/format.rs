@@ -0,0 +1,309 @@
+use crate::{database::DatabaseKind, protobuf, LookupError};
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Query, Request},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Default, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+    /// Adds a header row to CSV, like `header=present` in the `text/csv` media type.
+    header: Option<String>,
+    /// The selected fields, which order the columns of CSV.
+    fields: Option<String>,
+    /// The field whose bare value plain text returns, e.g. `country.iso_code`.
+    field: Option<String>,
+}
+
+/// The encodings lookups can be returned in: of the same structures as the JSON records, or of the messages of `geoip2.proto`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+    Protobuf,
+    Xml,
+    Csv {
+        header: bool,
+    },
+    Text,
+    GeoJson,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "msgpack" | "messagepack" => Some(Format::MessagePack),
+            "cbor" => Some(Format::Cbor),
+            "protobuf" | "proto" => Some(Format::Protobuf),
+            "xml" => Some(Format::Xml),
+            "csv" => Some(Format::Csv { header: false }),
+            "text" => Some(Format::Text),
+            "geojson" => Some(Format::GeoJson),
+            _ => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str, params: &[&str]) -> Option<Self> {
+        match media_type.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MessagePack),
+            "application/cbor" => Some(Format::Cbor),
+            "application/x-protobuf" | "application/protobuf" => Some(Format::Protobuf),
+            "application/xml" | "text/xml" => Some(Format::Xml),
+            "text/csv" => Some(Format::Csv {
+                header: params.iter().any(|param| param.eq_ignore_ascii_case("header=present")),
+            }),
+            "text/plain" => Some(Format::Text),
+            "application/geo+json" => Some(Format::GeoJson),
+            _ => None,
+        }
+    }
+
+    /// Whether records of `kind` can be encoded in this format. Only City, Country and ASN records have a protobuf message, and only City records a location for GeoJSON.
+    fn supports(self, kind: Option<DatabaseKind>) -> bool {
+        match self {
+            Format::Protobuf => matches!(kind, Some(DatabaseKind::City | DatabaseKind::Enterprise | DatabaseKind::Country | DatabaseKind::Asn)),
+            Format::GeoJson => matches!(kind, Some(DatabaseKind::City | DatabaseKind::Enterprise)),
+            _ => true,
+        }
+    }
+
+    /// Whether error objects are encoded in this format too, rather than left JSON for lack of a way to encode them.
+    fn encodes_errors(self) -> bool {
+        !matches!(self, Format::Protobuf | Format::GeoJson)
+    }
+
+    /// The format of the most preferred media type of an `Accept` header that has one, ignoring those with `q=0`.
+    fn from_accept(accept: &str, kind: Option<DatabaseKind>) -> Option<Self> {
+        let mut media_types = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next()?;
+                let params = parts.collect::<Vec<_>>();
+                let quality = params.iter().find_map(|param| param.strip_prefix("q=")).map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (quality > 0.0).then_some((media_type, params, quality))
+            })
+            .collect::<Vec<_>>();
+        media_types.sort_by(|(_, _, a), (_, _, b)| b.total_cmp(a));
+
+        media_types.into_iter().filter_map(|(media_type, params, _)| Format::from_media_type(media_type, &params)).find(|format| format.supports(kind))
+    }
+
+    /// The format a request for records of `kind` asks for with `?format=msgpack`, or else its `Accept` header. Requests accepting none of the formats get JSON, but naming an unknown one in the query, or one the records cannot be encoded in, is an error.
+    fn requested(query: &FormatQuery, headers: &HeaderMap, kind: Option<DatabaseKind>) -> Result<Self, LookupError> {
+        let format = match &query.format {
+            Some(format) => Format::from_name(format).filter(|format| format.supports(kind)).ok_or(LookupError::FormatInvalid)?,
+            None => headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()).and_then(|accept| Format::from_accept(accept, kind)).unwrap_or_default(),
+        };
+
+        Ok(match (format, query.header.as_deref()) {
+            (Format::Csv { .. }, Some(header)) => Format::Csv {
+                header: matches!(header, "true" | "present" | "1"),
+            },
+            (format, _) => format,
+        })
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+            Format::Protobuf => "application/x-protobuf",
+            Format::Xml => "application/xml; charset=utf-8",
+            Format::Csv { .. } => "text/csv; charset=utf-8",
+            Format::Text => "text/plain; charset=utf-8",
+            Format::GeoJson => "application/geo+json",
+        }
+    }
+
+    /// Encodes a record, or an array of them, as the message of `kind` for protobuf. The columns of CSV are in the order of `fields`, if given, and plain text is the value of the one field, if there is one.
+    pub fn encode(self, value: &Value, kind: Option<DatabaseKind>, fields: &[&str]) -> Bytes {
+        match self {
+            Format::Json => serde_json::to_vec(value).expect("records serialize to JSON").into(),
+            Format::MessagePack => rmp_serde::to_vec_named(value).expect("records serialize to MessagePack").into(),
+            Format::Cbor => {
+                let mut cbor = Vec::new();
+                ciborium::into_writer(value, &mut cbor).expect("records serialize to CBOR");
+                cbor.into()
+            }
+            Format::Protobuf => kind.and_then(|kind| protobuf::encode(kind, value)).expect("protobuf is only negotiated for records with a message"),
+            Format::Xml => {
+                let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+                write_xml(&mut xml, "response", value);
+                xml.into()
+            }
+            Format::Csv { header } => write_csv(value, header, fields),
+            Format::Text => write_text(value, fields),
+            Format::GeoJson => serde_json::to_vec(&feature(value)).expect("features serialize to JSON").into(),
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// Whether `name` can be used as an element name as it is, which the keys of MaxMind records, locales included, all can.
+fn is_xml_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+/// Writes `value` as the element `name`: objects as one child element per key, arrays as one `item` element per value, and null as an empty element. Keys that cannot be element names become `entry` elements with a `key` attribute.
+fn write_xml(xml: &mut String, name: &str, value: &Value) {
+    let (open, close) = match is_xml_name(name) {
+        true => (name.to_owned(), name),
+        false => (format!("entry key=\"{}\"", escape_xml(name)), "entry"),
+    };
+
+    match value {
+        Value::Null => xml.push_str(&format!("<{open}/>")),
+        Value::Object(object) => {
+            xml.push_str(&format!("<{open}>"));
+            object.iter().for_each(|(key, value)| write_xml(xml, key, value));
+            xml.push_str(&format!("</{close}>"));
+        }
+        Value::Array(values) => {
+            xml.push_str(&format!("<{open}>"));
+            values.iter().for_each(|value| write_xml(xml, "item", value));
+            xml.push_str(&format!("</{close}>"));
+        }
+        Value::String(text) => xml.push_str(&format!("<{open}>{}</{close}>", escape_xml(text))),
+        value => xml.push_str(&format!("<{open}>{value}</{close}>")),
+    }
+}
+
+/// The kind of record a lookup route returns, from its path, e.g. `/geoip/v2.1/city/:ip`, wherever the router is mounted.
+fn record_kind(route: &str) -> Option<DatabaseKind> {
+    let (_, route) = route.split_once("/geoip/v2.1/")?;
+
+    match route.strip_suffix("/:ip")? {
+        "insights" => Some(DatabaseKind::City),
+        segment => DatabaseKind::ALL.into_iter().find(|kind| kind.name() == segment),
+    }
+}
+
+/// Flattens `value` into columns named by the dotted path of each value, e.g. `country.names.en`, with the indexes of arrays as segments, e.g. `subdivisions.0.iso_code`.
+fn flatten(path: String, value: &Value, columns: &mut Vec<(String, String)>) {
+    let child = |key: &str| if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") };
+
+    match value {
+        Value::Object(object) => object.iter().for_each(|(key, value)| flatten(child(key), value, columns)),
+        Value::Array(values) => values.iter().enumerate().for_each(|(index, value)| flatten(child(&index.to_string()), value, columns)),
+        Value::Null => columns.push((path, String::new())),
+        Value::String(text) => columns.push((path, text.clone())),
+        value => columns.push((path, value.to_string())),
+    }
+}
+
+/// Writes one row per record, with the columns of every record, in the order of `fields` if given and of the records otherwise. Values a record doesn't have are left empty.
+fn write_csv(value: &Value, header: bool, fields: &[&str]) -> Bytes {
+    let rows = match value {
+        Value::Array(records) => records.iter().collect::<Vec<_>>(),
+        record => vec![record],
+    };
+    let rows = rows
+        .into_iter()
+        .map(|record| {
+            let mut columns = Vec::new();
+            flatten(String::new(), record, &mut columns);
+            columns
+        })
+        .collect::<Vec<_>>();
+
+    let mut columns = Vec::<&str>::new();
+    for (column, _) in rows.iter().flatten() {
+        if !columns.contains(&column.as_str()) {
+            columns.push(column);
+        }
+    }
+    if !fields.is_empty() {
+        let position = |column: &str| fields.iter().position(|field| column == *field || column.strip_prefix(field).is_some_and(|rest| rest.starts_with('.')));
+        columns.sort_by_key(|&column| position(column).unwrap_or(usize::MAX));
+    }
+
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    if header {
+        csv.write_record(&columns).expect("CSV is written to memory");
+    }
+    for row in &rows {
+        let value = |column: &str| row.iter().find(|(name, _)| name == column).map_or("", |(_, value)| value.as_str());
+        csv.write_record(columns.iter().map(|&column| value(column))).expect("CSV is written to memory");
+    }
+
+    csv.into_inner().expect("CSV is written to memory").into()
+}
+
+/// Writes the bare value of a single field, e.g. `GB` for `country.iso_code`, which is empty if the record doesn't have it. Without a single field, every value is written as `path=value`, one per line.
+fn write_text(value: &Value, fields: &[&str]) -> Bytes {
+    let text = match fields {
+        [field] => match value.pointer(&format!("/{}", field.replace('.', "/"))) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(text)) => text.clone(),
+            Some(value) => value.to_string(),
+        },
+        _ => {
+            let mut columns = Vec::new();
+            flatten(String::new(), value, &mut columns);
+            columns.into_iter().map(|(column, value)| format!("{column}={value}")).collect::<Vec<_>>().join("\n")
+        }
+    };
+
+    format!("{text}\n").into()
+}
+
+/// A GeoJSON Feature of a City record: a Point at its location, or no geometry if it has none, with the rest of the record as its properties.
+fn feature(record: &Value) -> Value {
+    let mut properties = record.clone();
+    let location = properties.get_mut("location").and_then(Value::as_object_mut);
+    let coordinates = location
+        .filter(|location| location.contains_key("longitude") && location.contains_key("latitude"))
+        .map(|location| json!([location.remove("longitude"), location.remove("latitude")]));
+    let geometry = match coordinates {
+        Some(coordinates) => json!({ "type": "Point", "coordinates": coordinates }),
+        None => Value::Null,
+    };
+
+    json!({ "type": "Feature", "geometry": geometry, "properties": properties })
+}
+
+fn is_json(response: &Response) -> bool {
+    response.headers().get(header::CONTENT_TYPE).is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"))
+}
+
+/// Re-encodes the JSON responses of the lookup routes in the format the request asks for: records and error objects alike, except for protobuf and GeoJSON, where errors stay JSON.
+pub async fn negotiate(request: Request, next: Next) -> Result<Response, LookupError> {
+    let kind = request.extensions().get::<MatchedPath>().and_then(|route| record_kind(route.as_str()));
+    let query = Query::<FormatQuery>::try_from_uri(request.uri()).map(|Query(query)| query).unwrap_or_default();
+    let format = Format::requested(&query, request.headers(), kind)?;
+    let response = next.run(request).await;
+    if format == Format::Json || !is_json(&response) || (!format.encodes_errors() && !response.status().is_success()) {
+        return Ok(response);
+    }
+
+    let fields = query.field.as_deref().or(query.fields.as_deref()).map(|fields| fields.split(',').map(str::trim).collect::<Vec<_>>()).unwrap_or_default();
+    let (mut parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await.map_err(|_| LookupError::InternalError)?;
+    let value = serde_json::from_slice::<Value>(&body).map_err(|_| LookupError::InternalError)?;
+
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    // Scripts only need the code of what went wrong.
+    let body = match (format, parts.status.is_success()) {
+        (Format::Text, false) => format!("{}\n", value["code"].as_str().unwrap_or_default()).into(),
+        (format, _) => format.encode(&value, kind, &fields),
+    };
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
@@ -0,0 +1,230 @@
+use crate::{client_ip::ClientIp, database::DatabaseKind, lookup_kind, resolve_ip, AppState, Locales, LookupError};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::extract::State;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+/// A GraphQL error with the message of the error object, and its code in the `code` extension.
+fn error(err: LookupError) -> async_graphql::Error {
+    let body = err.body().1;
+
+    async_graphql::Error::new(body["error"].as_str().unwrap_or_default()).extend_with(|_, extensions| extensions.set("code", body["code"].as_str().unwrap_or_default()))
+}
+
+#[derive(SimpleObject)]
+struct Name {
+    locale: String,
+    name: String,
+}
+
+fn names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Name>, D::Error> {
+    let names = BTreeMap::<String, String>::deserialize(deserializer)?;
+
+    Ok(names.into_iter().map(|(locale, name)| Name { locale, name }).collect())
+}
+
+/// A continent, country, subdivision or city. Fields a kind of place doesn't have are null.
+#[derive(Deserialize, SimpleObject)]
+struct Place {
+    geoname_id: Option<u32>,
+    /// The code of a continent, e.g. `EU`.
+    code: Option<String>,
+    /// The ISO code of a country or subdivision, e.g. `GB` or `ENG`.
+    iso_code: Option<String>,
+    is_in_european_union: Option<bool>,
+    /// The names of the place in the requested locales.
+    #[serde(default, deserialize_with = "names")]
+    names: Vec<Name>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Location {
+    accuracy_radius: Option<u16>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    metro_code: Option<u16>,
+    time_zone: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Postal {
+    code: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Traits {
+    network: Option<String>,
+    is_anonymous_proxy: Option<bool>,
+    is_satellite_provider: Option<bool>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct City {
+    city: Option<Place>,
+    continent: Option<Place>,
+    country: Option<Place>,
+    location: Option<Location>,
+    postal: Option<Postal>,
+    registered_country: Option<Place>,
+    represented_country: Option<Place>,
+    subdivisions: Option<Vec<Place>>,
+    traits: Option<Traits>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Country {
+    continent: Option<Place>,
+    country: Option<Place>,
+    registered_country: Option<Place>,
+    represented_country: Option<Place>,
+    traits: Option<Traits>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Asn {
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+    network: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct AnonymousIp {
+    is_anonymous: Option<bool>,
+    is_anonymous_vpn: Option<bool>,
+    is_hosting_provider: Option<bool>,
+    is_public_proxy: Option<bool>,
+    is_residential_proxy: Option<bool>,
+    is_tor_exit_node: Option<bool>,
+    network: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Isp {
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+    isp: Option<String>,
+    organization: Option<String>,
+    mobile_country_code: Option<String>,
+    mobile_network_code: Option<String>,
+    network: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct Domain {
+    domain: Option<String>,
+    network: Option<String>,
+}
+
+#[derive(Deserialize, SimpleObject)]
+struct ConnectionType {
+    connection_type: Option<String>,
+    network: Option<String>,
+}
+
+/// An address whose fields each look it up in a database, so a query only reads the databases it selects.
+struct Lookup {
+    ip: IpAddr,
+    locales: Locales,
+}
+
+impl Lookup {
+    /// The record of the address in the database serving `kind`, or null if it isn't in there.
+    async fn record<T: DeserializeOwned>(&self, ctx: &Context<'_>, kind: DatabaseKind) -> Result<Option<T>> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let readers = state.databases.readers(kind).ok_or_else(|| error(LookupError::DatabaseNotLoaded))?;
+        let record = match lookup_kind(kind, &readers, self.ip, state).await {
+            Ok(record) => record,
+            Err(LookupError::IpAddressNotFound) => return Ok(None),
+            Err(err) => return Err(error(err)),
+        };
+
+        let mut record: serde_json::Value = serde_json::from_slice(&record).expect("records are valid JSON");
+        self.locales.apply(&mut record);
+
+        Ok(Some(serde_json::from_value(record)?))
+    }
+}
+
+#[Object]
+impl Lookup {
+    async fn ip(&self) -> String {
+        self.ip.to_string()
+    }
+
+    /// The City record, from a City or else an Enterprise database.
+    async fn city(&self, ctx: &Context<'_>) -> Result<Option<City>> {
+        self.record(ctx, DatabaseKind::City).await
+    }
+
+    /// The Country record, from a Country database or else a City or Enterprise one.
+    async fn country(&self, ctx: &Context<'_>) -> Result<Option<Country>> {
+        self.record(ctx, DatabaseKind::Country).await
+    }
+
+    async fn asn(&self, ctx: &Context<'_>) -> Result<Option<Asn>> {
+        self.record(ctx, DatabaseKind::Asn).await
+    }
+
+    async fn anonymous_ip(&self, ctx: &Context<'_>) -> Result<Option<AnonymousIp>> {
+        self.record(ctx, DatabaseKind::AnonymousIp).await
+    }
+
+    async fn isp(&self, ctx: &Context<'_>) -> Result<Option<Isp>> {
+        self.record(ctx, DatabaseKind::Isp).await
+    }
+
+    async fn domain(&self, ctx: &Context<'_>) -> Result<Option<Domain>> {
+        self.record(ctx, DatabaseKind::Domain).await
+    }
+
+    async fn connection_type(&self, ctx: &Context<'_>) -> Result<Option<ConnectionType>> {
+        self.record(ctx, DatabaseKind::ConnectionType).await
+    }
+}
+
+/// How many more lookups a query may make, as aliases let one query look up any number of addresses.
+struct Budget(AtomicUsize);
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Looks up an address, or `me` for the address of the caller. Names are reduced to `locales`, or to those of the request if not given.
+    async fn lookup(&self, ctx: &Context<'_>, ip: String, locales: Option<Vec<String>>) -> Result<Lookup> {
+        let budget = ctx.data_unchecked::<Budget>();
+        if budget.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1)).is_err() {
+            return Err(error(LookupError::BatchTooLarge));
+        }
+
+        let ip = resolve_ip(&ip, *ctx.data_unchecked::<ClientIp>()).map_err(error)?;
+        let locales = match locales {
+            Some(locales) => Locales::parse(&locales.join(",")),
+            None => ctx.data_unchecked::<Locales>().clone(),
+        };
+
+        Ok(Lookup { ip, locales })
+    }
+}
+
+type GeoIpSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+fn schema() -> &'static GeoIpSchema {
+    static SCHEMA: OnceLock<GeoIpSchema> = OnceLock::new();
+
+    SCHEMA.get_or_init(|| Schema::build(Query, EmptyMutation, EmptySubscription).limit_depth(8).finish())
+}
+
+/// Runs a GraphQL query, which may look up as many addresses as a batch may have.
+pub async fn execute(State(state): State<Arc<AppState>>, client: ClientIp, locales: Locales, request: GraphQLRequest) -> GraphQLResponse {
+    let budget = Budget(AtomicUsize::new(state.batch_limit));
+
+    schema().execute(request.into_inner().data(state).data(client).data(locales).data(budget)).await.into()
+}
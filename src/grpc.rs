@@ -0,0 +1,242 @@
+use crate::{
+    auth::Subject,
+    check_databases,
+    client_ip::ClientIp,
+    database::DatabaseKind,
+    lookup_kind, parse_ip,
+    protobuf::{asn_record, city_record, country_record, AsnRecord, CityRecord, CountryRecord},
+    rate_limit, AppState, Locales, LookupError,
+};
+use axum::http::StatusCode;
+use serde_json::Value;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tonic::{server::NamedService, Code, Request, Response, Status};
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+pub mod proto {
+    // The generated code has helpers, like the names of enum values, that the server doesn't use.
+    #![allow(dead_code)]
+    tonic::include_proto!("geoip2.v1");
+
+    /// The descriptors of `geoip2.proto`, for server reflection.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("geoip2_descriptor");
+}
+
+use proto::{
+    batch_lookup_result::Result as BatchResult,
+    geo_ip_server::{GeoIp, GeoIpServer},
+    BatchLookupRequest, BatchLookupResult, LookupRequest,
+};
+
+impl From<LookupError> for Status {
+    /// Maps the error to the gRPC code closest to its HTTP status, keeping the code of its error object in the `geoip2-error-code` metadata.
+    fn from(err: LookupError) -> Self {
+        let (status, body) = err.body();
+        let code = match status {
+            StatusCode::BAD_REQUEST => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::NOT_IMPLEMENTED => Code::Unimplemented,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+
+        let mut grpc = Status::new(code, body["error"].as_str().unwrap_or_default());
+        if let Ok(value) = body["code"].as_str().unwrap_or_default().parse() {
+            grpc.metadata_mut().insert("geoip2-error-code", value);
+        }
+
+        grpc
+    }
+}
+
+/// The client address of a call, from its peer and, behind trusted proxies, its metadata, like for HTTP requests.
+fn client<T>(state: &AppState, request: &Request<T>) -> ClientIp {
+    ClientIp(state.client_ip.peer_client_ip(request.remote_addr(), &request.metadata().clone().into_headers()))
+}
+
+/// Rejects calls of clients `--allow-cidr` and `--deny-cidr` don't allow, to every service, health and reflection included, as the HTTP routes do.
+fn restrict(state: Arc<AppState>) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request| match !state.client_filter.is_enabled() || state.client_filter.allows(client(&state, &request).0) {
+        true => Ok(request),
+        false => Err(LookupError::ClientNotAllowed.into()),
+    }
+}
+
+/// The lookup RPCs, answered from the same databases, caches and credentials as the HTTP routes.
+pub struct GeoIpService {
+    state: Arc<AppState>,
+}
+
+impl GeoIpService {
+    /// Rejects calls without valid credentials in their metadata, which takes the same headers as HTTP requests, if any are configured, then calls over the rate limits, like the HTTP routes. A batch counts as one call, as it does over HTTP.
+    async fn admit<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let subject = match self.state.auth.is_enabled() {
+            true => self.state.auth.authenticate(&request.metadata().clone().into_headers()).await?.map(Subject),
+            false => None,
+        };
+
+        rate_limit::check(&self.state, client(&self.state, request), subject.as_ref()).map_err(|limited| {
+            let retry_after = limited.report();
+            let mut status = Status::from(LookupError::RateLimitExceeded);
+            if let Ok(value) = retry_after.parse() {
+                status.metadata_mut().insert("retry-after", value);
+            }
+
+            status
+        })
+    }
+
+    fn locales(&self, locales: &[String]) -> Locales {
+        match locales.is_empty() {
+            true => self.state.default_locales.clone(),
+            false => Locales::parse(&locales.join(",")),
+        }
+    }
+
+    /// Looks `ip` up in the database serving `kind`, returning its record with the names reduced to `locales`.
+    async fn lookup(&self, kind: DatabaseKind, ip: &str, locales: &Locales) -> Result<Value, LookupError> {
+        let ip = match ip {
+            "" => return Err(LookupError::IpAddressRequired),
+            ip => parse_ip(ip)?,
+        };
+        let readers = self.state.databases.readers(kind).ok_or(LookupError::DatabaseNotLoaded)?;
+        let record = lookup_kind(kind, &readers, ip, &self.state).await?;
+
+        let mut record: Value = serde_json::from_slice(&record).expect("records are valid JSON");
+        locales.apply(&mut record);
+
+        Ok(record)
+    }
+}
+
+#[tonic::async_trait]
+impl GeoIp for GeoIpService {
+    async fn city(&self, request: Request<LookupRequest>) -> Result<Response<CityRecord>, Status> {
+        self.admit(&request).await?;
+        let request = request.into_inner();
+        let record = self.lookup(DatabaseKind::City, &request.ip, &self.locales(&request.locales)).await?;
+
+        Ok(Response::new(city_record(&record)))
+    }
+
+    async fn country(&self, request: Request<LookupRequest>) -> Result<Response<CountryRecord>, Status> {
+        self.admit(&request).await?;
+        let request = request.into_inner();
+        let record = self.lookup(DatabaseKind::Country, &request.ip, &self.locales(&request.locales)).await?;
+
+        Ok(Response::new(country_record(&record)))
+    }
+
+    async fn asn(&self, request: Request<LookupRequest>) -> Result<Response<AsnRecord>, Status> {
+        self.admit(&request).await?;
+        let record = self.lookup(DatabaseKind::Asn, &request.into_inner().ip, &Locales::default()).await?;
+
+        Ok(Response::new(asn_record(&record)))
+    }
+
+    type BatchLookupStream = tokio_stream::wrappers::ReceiverStream<Result<BatchLookupResult, Status>>;
+
+    /// Looks up each address of the batch, sending back an error in place of the record of addresses that fail, like the HTTP batch route.
+    async fn batch_lookup(&self, request: Request<BatchLookupRequest>) -> Result<Response<Self::BatchLookupStream>, Status> {
+        self.admit(&request).await?;
+        let request = request.into_inner();
+        if request.ips.len() > self.state.batch_limit {
+            return Err(LookupError::BatchTooLarge.into());
+        }
+
+        let kind = match request.database() {
+            proto::Database::Unspecified | proto::Database::City => DatabaseKind::City,
+            proto::Database::Country => DatabaseKind::Country,
+            proto::Database::Asn => DatabaseKind::Asn,
+        };
+        let locales = self.locales(&request.locales);
+        let service = GeoIpService { state: self.state.clone() };
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+
+        tokio::spawn(async move {
+            for ip in request.ips {
+                let result = match service.lookup(kind, &ip, &locales).await {
+                    Ok(record) => match kind {
+                        DatabaseKind::Country => BatchResult::Country(country_record(&record)),
+                        DatabaseKind::Asn => BatchResult::Asn(asn_record(&record)),
+                        _ => BatchResult::City(city_record(&record)),
+                    },
+                    Err(err) => {
+                        let body = err.body().1;
+                        BatchResult::Error(proto::Error {
+                            code: body["code"].as_str().unwrap_or_default().to_owned(),
+                            message: body["error"].as_str().unwrap_or_default().to_owned(),
+                        })
+                    }
+                };
+
+                // The client hung up, so there is no one left to look addresses up for.
+                if tx.send(Ok(BatchLookupResult { ip, result: Some(result) })).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+}
+
+/// Resolves the address the gRPC server listens on, so a bad one fails at startup rather than once the server runs.
+pub async fn resolve(bind: &str, port: u16) -> anyhow::Result<SocketAddr> {
+    tokio::net::lookup_host((bind, port)).await?.next().ok_or_else(|| anyhow::anyhow!("{bind} does not resolve to an address"))
+}
+
+/// How often the health service checks the databases again.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reports the server, and the lookup service by name, as serving while it would pass `/readyz`, so gRPC probes take instances with broken or stale databases out of rotation.
+async fn report_health(state: Arc<AppState>, reporter: HealthReporter, shutdown: CancellationToken) {
+    let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+
+        let checks = check_databases(&state);
+        let status = if checks.healthy && checks.fresh { ServingStatus::Serving } else { ServingStatus::NotServing };
+        for service in ["", GeoIpServer::<GeoIpService>::NAME] {
+            reporter.set_service_status(service, status).await;
+        }
+    }
+
+    // Tell clients to go elsewhere while the in-flight calls drain.
+    for service in ["", GeoIpServer::<GeoIpService>::NAME] {
+        reporter.set_service_status(service, ServingStatus::NotServing).await;
+    }
+}
+
+/// Serves the lookup RPCs on `addr` until `shutdown` is cancelled, along with the `grpc.health.v1` health service and server reflection, so grpcurl and Kubernetes gRPC probes work without the proto file.
+pub async fn serve(state: Arc<AppState>, addr: SocketAddr, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let (reporter, health) = tonic_health::server::health_reporter();
+    tokio::spawn(report_health(state.clone(), reporter, shutdown.clone()));
+
+    let reflection = || {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+    };
+
+    tonic::transport::Server::builder()
+        .layer(tonic::service::interceptor(restrict(state.clone())))
+        .add_service(health)
+        // Older clients, grpcurl among them, only speak the alpha version of reflection.
+        .add_service(reflection().build_v1()?)
+        .add_service(reflection().build_v1alpha()?)
+        .add_service(GeoIpServer::new(GeoIpService { state }))
+        .serve_with_shutdown(addr, shutdown.cancelled())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+/// How often an unknown key ID may trigger fetching the JWKS again, so tokens with made up key IDs can't hammer the identity provider.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+}
+
+/// Validates bearer tokens against the keys published at a JWKS URL, checking their expiry and, if configured, their issuer and audience.
+pub struct Jwks {
+    http: reqwest::Client,
+    url: String,
+    issuer: Option<String>,
+    audience: Option<String>,
+    keys: ArcSwap<JwkSet>,
+    refreshed: Mutex<Instant>,
+}
+
+impl Jwks {
+    /// Fetches the keys from `url`, failing if they cannot be fetched or parsed.
+    pub async fn new(url: String, issuer: Option<String>, audience: Option<String>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::new();
+        let keys = fetch(&http, &url).await?;
+        info!("loaded {} keys from {url}", keys.keys.len());
+
+        Ok(Jwks { http, url, issuer, audience, keys: ArcSwap::from_pointee(keys), refreshed: Mutex::new(Instant::now()) })
+    }
+
+    async fn refresh(&self) -> anyhow::Result<()> {
+        *self.refreshed.lock().expect("JWKS refresh lock poisoned") = Instant::now();
+        self.keys.store(Arc::new(fetch(&self.http, &self.url).await?));
+
+        Ok(())
+    }
+
+    /// Fetches the keys again every `interval`, so rotated keys are picked up before tokens signed with them show up.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if let Err(err) = self.refresh().await {
+                error!("failed to refresh JWKS from {}: {err:#}", self.url);
+            }
+        }
+    }
+
+    /// Returns the claims of `token` if it is signed by one of the keys and valid, fetching the keys again if it was signed by a key we don't know yet.
+    pub async fn verify(&self, token: &str) -> Option<Claims> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let kid = header.kid?;
+
+        if self.keys.load().find(&kid).is_none() {
+            let stale = self.refreshed.lock().expect("JWKS refresh lock poisoned").elapsed() >= MIN_REFRESH_INTERVAL;
+            if !stale {
+                return None;
+            }
+
+            if let Err(err) = self.refresh().await {
+                error!("failed to refresh JWKS from {}: {err:#}", self.url);
+            }
+        }
+
+        let keys = self.keys.load();
+        let jwk = keys.find(&kid)?;
+        let key = DecodingKey::from_jwk(jwk).ok()?;
+
+        // Only accept the algorithm the key was published for, rather than whatever the token claims.
+        let algorithm = match jwk.common.key_algorithm {
+            Some(algorithm) => serde_json::from_value(serde_json::to_value(algorithm).ok()?).ok()?,
+            None => header.alg,
+        };
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        jsonwebtoken::decode::<Claims>(token, &key, &validation).ok().map(|data| data.claims)
+    }
+}
+
+async fn fetch(http: &reqwest::Client, url: &str) -> anyhow::Result<JwkSet> {
+    let body = http.get(url).send().await?.error_for_status()?.bytes().await.with_context(|| format!("Failed to fetch JWKS from {url}"))?;
+
+    serde_json::from_slice(&body).with_context(|| format!("Failed to parse JWKS from {url}"))
+}
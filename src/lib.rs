@@ -0,0 +1,2353 @@
+mod access_log;
+mod anonymize;
+mod auth;
+mod bench;
+mod cache;
+mod client_filter;
+mod client_ip;
+mod commands;
+mod compat;
+mod config;
+mod countries;
+mod database;
+mod diff;
+mod distance;
+mod dns;
+mod enrich;
+#[cfg(feature = "sentry")]
+mod error_reporting;
+mod etag;
+mod filter;
+mod format;
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod jwt;
+mod listener;
+mod locale;
+mod openapi;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod overrides;
+mod protobuf;
+mod proxy_protocol;
+mod rate_limit;
+mod record;
+mod request_id;
+#[cfg(feature = "redis")]
+mod redis_cache;
+mod remote;
+mod reserved;
+mod self_test;
+mod statsd;
+mod telemetry;
+mod timezone;
+mod tls;
+mod updater;
+mod upstream;
+mod watch;
+
+pub use auth::Auth;
+pub use cache::RecordCache;
+pub use client_filter::ClientFilter;
+pub use client_ip::ClientIpConfig;
+pub use compat::Compat;
+pub use database::{Database, DatabaseArg, DatabaseKind, Databases};
+pub use dns::{AddressPreference, HostnameResolver, ReverseResolver};
+pub use jwt::Jwks;
+pub use locale::Locales;
+pub use overrides::Overrides;
+pub use rate_limit::{Rate, RateLimiter};
+#[cfg(feature = "redis")]
+pub use redis_cache::SharedCache;
+pub use upstream::Upstream;
+
+use axum::{
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{DefaultBodyLimit, FromRequestParts, MatchedPath, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    BoxError, Extension, Json, Router,
+};
+use bytes::Bytes;
+use client_ip::ClientIp;
+use database::Source;
+use filter::Fields;
+use futures_util::TryStreamExt;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use ipnetwork::IpNetwork;
+use std::{
+    collections::BTreeSet,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceBuilder;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
+use tracing::{error, info, Level};
+use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LookupError {
+    IpAddressInvalid,
+    IpAddressRequired,
+    IpAddressNotFound,
+    IpAddressReserved,
+    HostnameNotResolved,
+    DatabaseNotLoaded,
+    DatabaseInvalid,
+    DatabaseReloadFailed,
+    BatchTooLarge,
+    BodyTooLarge,
+    DatabaseLookupFailed,
+    DatabaseTypeMismatch,
+    AuthorizationInvalid,
+    ClientNotAllowed,
+    RateLimitExceeded,
+    ServerOverloaded,
+    RequestTimeout,
+    RouteNotFound,
+    MethodNotAllowed,
+    FormatInvalid,
+    InternalError,
+}
+
+impl LookupError {
+    const ALL: [LookupError; 21] = [
+        LookupError::IpAddressInvalid,
+        LookupError::IpAddressRequired,
+        LookupError::IpAddressNotFound,
+        LookupError::IpAddressReserved,
+        LookupError::HostnameNotResolved,
+        LookupError::DatabaseNotLoaded,
+        LookupError::DatabaseInvalid,
+        LookupError::DatabaseReloadFailed,
+        LookupError::BatchTooLarge,
+        LookupError::BodyTooLarge,
+        LookupError::DatabaseLookupFailed,
+        LookupError::DatabaseTypeMismatch,
+        LookupError::AuthorizationInvalid,
+        LookupError::ClientNotAllowed,
+        LookupError::RateLimitExceeded,
+        LookupError::ServerOverloaded,
+        LookupError::RequestTimeout,
+        LookupError::RouteNotFound,
+        LookupError::MethodNotAllowed,
+        LookupError::FormatInvalid,
+        LookupError::InternalError,
+    ];
+
+    fn body(self) -> (StatusCode, serde_json::Value) {
+        let (status, code, msg) = match self {
+            LookupError::IpAddressInvalid => (StatusCode::BAD_REQUEST, "IP_ADDRESS_INVALID", "You have not supplied a valid IPv4 or IPv6 address."),
+            LookupError::IpAddressRequired => (StatusCode::BAD_REQUEST, "IP_ADDRESS_REQUIRED", "You have not supplied an IP address, which is a required field."),
+            LookupError::IpAddressNotFound => (StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND", "The supplied IP address is not in the database."),
+            LookupError::IpAddressReserved => (StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED", "You have supplied an IP address which belongs to a reserved or private range."),
+            LookupError::HostnameNotResolved => (StatusCode::NOT_FOUND, "HOSTNAME_NOT_RESOLVED", "The supplied hostname does not resolve to an IP address."),
+            LookupError::DatabaseNotLoaded => (StatusCode::NOT_IMPLEMENTED, "DATABASE_NOT_LOADED", "The database required by this endpoint is not loaded."),
+            LookupError::DatabaseInvalid => (StatusCode::BAD_REQUEST, "DATABASE_INVALID", "You have not supplied a valid database type."),
+            LookupError::DatabaseReloadFailed => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_RELOAD_FAILED", "The database could not be reloaded, the previous database is still being served."),
+            LookupError::BatchTooLarge => (StatusCode::BAD_REQUEST, "BATCH_TOO_LARGE", "You have supplied more IP addresses than a single batch may contain."),
+            LookupError::BodyTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "BODY_TOO_LARGE", "You have supplied a request body larger than this server accepts."),
+            LookupError::DatabaseLookupFailed => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_LOOKUP_FAILED", "The database could not be read while looking up the supplied IP address."),
+            LookupError::DatabaseTypeMismatch => (StatusCode::BAD_REQUEST, "DATABASE_TYPE_MISMATCH", "The loaded database is of a type this endpoint cannot serve."),
+            LookupError::AuthorizationInvalid => (StatusCode::UNAUTHORIZED, "AUTHORIZATION_INVALID", "You have not supplied valid credentials."),
+            LookupError::ClientNotAllowed => (StatusCode::FORBIDDEN, "CLIENT_NOT_ALLOWED", "Your address is not allowed to use this server."),
+            LookupError::RateLimitExceeded => (StatusCode::TOO_MANY_REQUESTS, "RATE_LIMIT_EXCEEDED", "You have sent too many requests, retry after the time given in the Retry-After header."),
+            LookupError::ServerOverloaded => (StatusCode::SERVICE_UNAVAILABLE, "SERVER_OVERLOADED", "The server is handling too many requests at the moment, please retry later."),
+            LookupError::RequestTimeout => (StatusCode::GATEWAY_TIMEOUT, "REQUEST_TIMEOUT", "The request could not be handled in time."),
+            LookupError::RouteNotFound => (StatusCode::NOT_FOUND, "ROUTE_NOT_FOUND", "The requested path is not served by this server."),
+            LookupError::MethodNotAllowed => (StatusCode::METHOD_NOT_ALLOWED, "METHOD_NOT_ALLOWED", "The requested path does not support this method, see the Allow header for the ones it does."),
+            LookupError::FormatInvalid => (StatusCode::BAD_REQUEST, "FORMAT_INVALID", "You have requested a response format this server does not support."),
+            LookupError::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", "The server failed to handle the request."),
+        };
+
+        (status, serde_json::json!({ "code": code, "error": msg }))
+    }
+}
+
+impl IntoResponse for LookupError {
+    fn into_response(self) -> Response {
+        let (status, mut body) = self.body();
+        if let Some(request_id) = request_id::current() {
+            body["request_id"] = serde_json::Value::String(request_id);
+        }
+
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Everything [`router`] needs to serve lookups. [`Config::new`] sets what the command line defaults to, which the fields can then be changed from.
+pub struct Config {
+    pub databases: Arc<Databases>,
+    /// Adds the network of each record to it, like MaxMind's web service.
+    pub network: bool,
+    /// Adds the flag emoji, currency and calling code of countries, and the English name of continents, to records.
+    pub enrich_countries: bool,
+    /// How many addresses a batch lookup may have.
+    pub batch_limit: usize,
+    /// Locales of names for requests without `Accept-Language`.
+    pub default_locales: Locales,
+    pub client_ip: ClientIpConfig,
+    /// Public address `/status` looks up to check the databases.
+    pub status_ip: IpAddr,
+    /// Report not ready once a database is older than this.
+    pub max_database_age: Option<Duration>,
+    pub auth: Auth,
+    /// Answers only the clients it allows, on every route.
+    pub client_filter: ClientFilter,
+    /// Run [`RateLimiter::run`] next to the router to clear idle callers.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Limits requests per client address, whether or not they have credentials.
+    pub ip_rate_limiter: Option<Arc<RateLimiter>>,
+    /// Client addresses neither rate limit applies to, e.g. internal batch jobs.
+    pub rate_limit_exempt: Vec<IpNetwork>,
+    /// Resolves hostnames in the path of lookups with `?resolve=true`.
+    pub hostnames: Option<Arc<HostnameResolver>>,
+    /// Adds the PTR record of the address to lookups with `?rdns=true`.
+    pub reverse_dns: Option<Arc<ReverseResolver>>,
+    /// The serialized record to answer lookups of reserved addresses with, instead of `IP_ADDRESS_RESERVED`.
+    pub private_response: Option<Bytes>,
+    /// Fills in City and Country lookups the databases can't answer from MaxMind's web service.
+    pub upstream: Option<Arc<Upstream>>,
+    /// Lets clients cache successful lookups for this long.
+    pub cache_max_age: Option<Duration>,
+    pub cache: Option<RecordCache>,
+    #[cfg(feature = "redis")]
+    pub redis: Option<Arc<SharedCache>>,
+    pub compression: bool,
+    pub cors: Option<CorsLayer>,
+    pub request_timeout: Duration,
+    /// Answers `503` to requests beyond this many in flight.
+    pub max_in_flight: Option<usize>,
+    /// Answers `413` to request bodies of more bytes than this, except for the line-per-address stream.
+    pub max_body_size: usize,
+    /// Serves `/metrics` from this handle, which must be of the installed recorder.
+    pub prometheus: Option<PrometheusHandle>,
+    /// Serves Swagger UI for `/openapi.json` at `/docs`.
+    pub docs: bool,
+    /// Also answers the APIs of these services.
+    pub compat: Vec<Compat>,
+}
+
+impl Config {
+    pub fn new(databases: Arc<Databases>) -> Self {
+        Config {
+            databases,
+            network: true,
+            enrich_countries: false,
+            batch_limit: 1000,
+            default_locales: Locales::default(),
+            client_ip: ClientIpConfig::default(),
+            status_ip: IpAddr::V4(std::net::Ipv4Addr::new(8, 8, 8, 8)),
+            max_database_age: None,
+            auth: Auth::default(),
+            client_filter: ClientFilter::default(),
+            rate_limiter: None,
+            ip_rate_limiter: None,
+            rate_limit_exempt: Vec::new(),
+            hostnames: None,
+            reverse_dns: None,
+            private_response: None,
+            upstream: None,
+            cache_max_age: None,
+            cache: None,
+            #[cfg(feature = "redis")]
+            redis: None,
+            compression: false,
+            cors: None,
+            request_timeout: Duration::from_secs(5),
+            max_in_flight: None,
+            max_body_size: 2 * 1024 * 1024,
+            prometheus: None,
+            docs: false,
+            compat: Vec::new(),
+        }
+    }
+}
+
+struct AppState {
+    databases: Arc<Databases>,
+    batch_limit: usize,
+    client_ip: ClientIpConfig,
+    network: bool,
+    enrich_countries: bool,
+    default_locales: Locales,
+    status_ip: IpAddr,
+    started: Instant,
+    max_database_age: Option<Duration>,
+    auth: Auth,
+    client_filter: ClientFilter,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    ip_rate_limiter: Option<Arc<RateLimiter>>,
+    rate_limit_exempt: Vec<IpNetwork>,
+    hostnames: Option<Arc<HostnameResolver>>,
+    reverse_dns: Option<Arc<ReverseResolver>>,
+    private_response: Option<Bytes>,
+    upstream: Option<Arc<Upstream>>,
+    cache_max_age: Option<Duration>,
+    cache: Option<RecordCache>,
+    #[cfg(feature = "redis")]
+    redis: Option<Arc<redis_cache::SharedCache>>,
+}
+
+/// Parses a size in bytes, with an optional `K`, `M` or `G` suffix for KiB, MiB or GiB, e.g. `512K` or `2M`.
+fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last().map(|unit| unit.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+
+    number.trim().parse::<usize>().ok().and_then(|number| number.checked_mul(multiplier)).ok_or_else(|| format!("{size} is not a size, e.g. 65536, 512K or 2M"))
+}
+
+/// Parses an address the way clients write them: IPv6 in any case, compressed or not, and in brackets or with a zone ID, e.g. `[fe80::1%eth0]`, which only means something on the host that sent it. IPv4-mapped IPv6 addresses, e.g. `::ffff:81.2.69.142`, are looked up as the IPv4 address they map.
+fn parse_ip(ip: &str) -> Result<IpAddr, LookupError> {
+    let ip = ip.trim();
+    let ip = ip.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')).unwrap_or(ip);
+    let ip = ip.split_once('%').map_or(ip, |(ip, _)| ip);
+
+    IpAddr::from_str(ip).map(|ip| ip.to_canonical()).map_err(|_| LookupError::IpAddressInvalid)
+}
+
+/// Parses the IP of a lookup path, where `me` stands for the address of the client, like in MaxMind's web service.
+fn resolve_ip(ip: &str, client: ClientIp) -> Result<IpAddr, LookupError> {
+    match ip {
+        "me" => client.0.ok_or(LookupError::IpAddressRequired),
+        ip => parse_ip(ip),
+    }
+}
+
+/// The address a lookup route is for: the `ip` of its path as [`resolve_ip`] parses it or, with `--resolve-hostnames` and `?resolve=true`, the address the hostname there resolves to.
+struct LookupIp(IpAddr);
+
+#[axum::async_trait]
+impl FromRequestParts<Arc<AppState>> for LookupIp {
+    type Rejection = LookupError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let Path(ip) = Path::<String>::from_request_parts(parts, state).await.map_err(|_| LookupError::IpAddressInvalid)?;
+        let client = ClientIp(state.client_ip.client_ip(&parts.extensions, &parts.headers));
+
+        match (resolve_ip(&ip, client), &state.hostnames) {
+            (Err(LookupError::IpAddressInvalid), Some(hostnames)) if dns::wants_resolution(&parts.uri) => Ok(LookupIp(hostnames.resolve(&ip, &rate_limit::key(parts.extensions.get(), client)).await?)),
+            (ip, _) => ip.map(LookupIp),
+        }
+    }
+}
+
+fn check_lookup(kind: DatabaseKind, maxmind: &Reader<Source>, ip: IpAddr) -> Result<(), LookupError> {
+    if !kind.serves(&maxmind.metadata.database_type) {
+        return Err(LookupError::DatabaseTypeMismatch);
+    }
+
+    if reserved::is_reserved(ip) {
+        return Err(LookupError::IpAddressReserved);
+    }
+
+    Ok(())
+}
+
+/// Decodes the record of `ip` and serializes it with its network and, if `enrich`, the fields derived from its countries, returning it and the prefix length of the network.
+fn decode<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, maxmind: &'a Reader<Source>, ip: IpAddr, network: bool, enrich: bool) -> Result<(Bytes, u8), LookupError> {
+    let start = Instant::now();
+    let record = tracing::info_span!("mmdb_lookup", database = kind.name()).in_scope(|| maxmind.lookup_prefix::<T>(ip));
+    metrics::histogram!("geoip_lookup_duration_seconds", "database" => kind.name()).record(start.elapsed());
+    let (record, prefix_len) = record.map_err(|err| match err {
+        MaxMindDBError::AddressNotFoundError(_) => LookupError::IpAddressNotFound,
+        err => {
+            error!("failed to look up {} in the {kind} database: {err}", anonymize::ip(ip));
+            LookupError::DatabaseLookupFailed
+        }
+    })?;
+
+    let network = match network {
+        true => IpNetwork::new(ip, prefix_len as u8).ok().map(|prefix| format!("{}/{prefix_len}", prefix.network())),
+        false => None,
+    };
+
+    let record = record::encode(kind, &record, network);
+    let record = match (enrich, kind) {
+        (true, DatabaseKind::City | DatabaseKind::Country | DatabaseKind::Enterprise) => countries::enrich(record),
+        _ => record,
+    };
+
+    Ok((record, prefix_len as u8))
+}
+
+/// Applies the overrides of `--overrides` to the result of looking `ip` up, and answers reserved addresses they don't cover with `--private-response`. They go on top of the caches, whose entries are for whole networks of the database.
+fn overridden(kind: DatabaseKind, ip: IpAddr, state: &AppState, record: Result<Bytes, LookupError>) -> Result<Bytes, LookupError> {
+    let record = match state.databases.overrides() {
+        Some(overrides) => overrides.apply(kind, ip, record),
+        None => record,
+    };
+
+    match (record, &state.private_response) {
+        (Err(LookupError::IpAddressReserved), Some(response)) => Ok(response.clone()),
+        (record, _) => record,
+    }
+}
+
+/// Whether the database at `position` in the chain serving `kind` settled a lookup, counting it in `geoip_lookup_source_total` if it had the record. Only addresses it has no record for are looked up in the next one.
+fn answered(kind: DatabaseKind, position: usize, record: &Result<Bytes, LookupError>) -> bool {
+    match record {
+        Ok(_) => {
+            metrics::counter!("geoip_lookup_source_total", "database" => kind.name(), "position" => position.to_string()).increment(1);
+            true
+        }
+        Err(LookupError::IpAddressNotFound) => false,
+        Err(_) => true,
+    }
+}
+
+/// Looks up `ip` in the `readers` of the chain serving `kind` in turn, until one has a record for it.
+fn lookup<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, readers: &'a [Arc<Reader<Source>>], ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    overridden(kind, ip, state, lookup_chain::<T>(kind, readers, ip, state))
+}
+
+fn lookup_chain<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, readers: &'a [Arc<Reader<Source>>], ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    let mut record = Err(LookupError::IpAddressNotFound);
+    for (position, maxmind) in readers.iter().enumerate() {
+        record = lookup_cached::<T>(kind, position, maxmind, ip, state);
+        if answered(kind, position, &record) {
+            break;
+        }
+    }
+
+    record
+}
+
+/// Looks `ip` up through the in-memory cache, if there is one, in the database at `position` in the chain of `kind`.
+fn lookup_cached<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, position: usize, maxmind: &'a Reader<Source>, ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    check_lookup(kind, maxmind, ip)?;
+
+    let build_epoch = maxmind.metadata.build_epoch;
+    if let Some(record) = state.cache.as_ref().and_then(|cache| cache.get(kind, position, build_epoch, ip)) {
+        return Ok(record);
+    }
+
+    let (record, prefix_len) = decode::<T>(kind, maxmind, ip, state.network, state.enrich_countries)?;
+    if let Some(cache) = &state.cache {
+        cache.insert(kind, position, build_epoch, ip, prefix_len, record.clone());
+    }
+
+    Ok(record)
+}
+
+/// Like [`lookup`], but with `--redis-url` also shares records with the other replicas through Redis, between the in-memory cache and the database, and with `--upstream` asks MaxMind's web service for what the databases lack.
+async fn lookup_shared<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, readers: &'a [Arc<Reader<Source>>], ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    let record = lookup_chain_shared::<T>(kind, readers, ip, state).await;
+    let record = match &state.upstream {
+        Some(upstream) => upstream.fill(kind, ip, record).await,
+        None => record,
+    };
+
+    overridden(kind, ip, state, record)
+}
+
+async fn lookup_chain_shared<'a, T: Deserialize<'a> + Serialize>(kind: DatabaseKind, readers: &'a [Arc<Reader<Source>>], ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    #[cfg(feature = "redis")]
+    if let Some(redis) = &state.redis {
+        let mut record = Err(LookupError::IpAddressNotFound);
+        for (position, maxmind) in readers.iter().enumerate() {
+            record = lookup_redis::<T>(redis, kind, position, maxmind, ip, state).await;
+            if answered(kind, position, &record) {
+                break;
+            }
+        }
+
+        return record;
+    }
+
+    lookup_chain::<T>(kind, readers, ip, state)
+}
+
+#[cfg(feature = "redis")]
+async fn lookup_redis<'a, T: Deserialize<'a> + Serialize>(redis: &Arc<redis_cache::SharedCache>, kind: DatabaseKind, position: usize, maxmind: &'a Reader<Source>, ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    check_lookup(kind, maxmind, ip)?;
+
+    let build_epoch = maxmind.metadata.build_epoch;
+    if let Some(record) = state.cache.as_ref().and_then(|cache| cache.get(kind, position, build_epoch, ip)) {
+        return Ok(record);
+    }
+
+    if let Some((prefix_len, record)) = redis.get(kind, position, build_epoch, ip).await {
+        if let Some(cache) = &state.cache {
+            cache.insert(kind, position, build_epoch, ip, prefix_len, record.clone());
+        }
+        return Ok(record);
+    }
+
+    let (record, prefix_len) = decode::<T>(kind, maxmind, ip, state.network, state.enrich_countries)?;
+    if let Some(cache) = &state.cache {
+        cache.insert(kind, position, build_epoch, ip, prefix_len, record.clone());
+    }
+
+    let (redis, shared) = (redis.clone(), record.clone());
+    tokio::spawn(async move { redis.set(kind, position, build_epoch, ip, prefix_len, shared).await });
+
+    Ok(record)
+}
+
+/// Looks up `ip` in a chain of databases of `kind`, decoding it as the record of that kind.
+async fn lookup_kind(kind: DatabaseKind, readers: &[Arc<Reader<Source>>], ip: IpAddr, state: &AppState) -> Result<Bytes, LookupError> {
+    match kind {
+        DatabaseKind::City => lookup_shared::<geoip2::City>(kind, readers, ip, state).await,
+        DatabaseKind::Country => lookup_shared::<geoip2::Country>(kind, readers, ip, state).await,
+        DatabaseKind::Enterprise => lookup_shared::<geoip2::Enterprise>(kind, readers, ip, state).await,
+        DatabaseKind::Asn => lookup_shared::<geoip2::Asn>(kind, readers, ip, state).await,
+        DatabaseKind::AnonymousIp => lookup_shared::<geoip2::AnonymousIp>(kind, readers, ip, state).await,
+        DatabaseKind::Isp => lookup_shared::<geoip2::Isp>(kind, readers, ip, state).await,
+        DatabaseKind::Domain => lookup_shared::<geoip2::Domain>(kind, readers, ip, state).await,
+        DatabaseKind::ConnectionType => lookup_shared::<geoip2::ConnectionType>(kind, readers, ip, state).await,
+        DatabaseKind::Custom => lookup_shared::<serde_json::Value>(kind, readers, ip, state).await,
+    }
+}
+
+/// Like [`lookup_kind`], but straight from the database, for the commands that run without a server.
+fn decode_kind(kind: DatabaseKind, maxmind: &Reader<Source>, ip: IpAddr, network: bool) -> Result<Bytes, LookupError> {
+    check_lookup(kind, maxmind, ip)?;
+
+    let (record, _) = match kind {
+        DatabaseKind::City => decode::<geoip2::City>(kind, maxmind, ip, network, false),
+        DatabaseKind::Country => decode::<geoip2::Country>(kind, maxmind, ip, network, false),
+        DatabaseKind::Enterprise => decode::<geoip2::Enterprise>(kind, maxmind, ip, network, false),
+        DatabaseKind::Asn => decode::<geoip2::Asn>(kind, maxmind, ip, network, false),
+        DatabaseKind::AnonymousIp => decode::<geoip2::AnonymousIp>(kind, maxmind, ip, network, false),
+        DatabaseKind::Isp => decode::<geoip2::Isp>(kind, maxmind, ip, network, false),
+        DatabaseKind::Domain => decode::<geoip2::Domain>(kind, maxmind, ip, network, false),
+        DatabaseKind::ConnectionType => decode::<geoip2::ConnectionType>(kind, maxmind, ip, network, false),
+        DatabaseKind::Custom => decode::<serde_json::Value>(kind, maxmind, ip, network, false),
+    }?;
+
+    Ok(record)
+}
+
+/// Applies the requested locales and fields to a serialized record. Records that need no shaping are returned as they are, without parsing them.
+fn shape(record: Bytes, locales: &Locales, fields: &Fields) -> Bytes {
+    if locales.is_empty() && fields.is_empty() {
+        return record;
+    }
+
+    let mut record: serde_json::Value = serde_json::from_slice(&record).expect("records are valid JSON");
+    locales.apply(&mut record);
+    fields.apply(&mut record);
+
+    serde_json::to_vec(&record).expect("records serialize to JSON").into()
+}
+
+/// Responds with a serialized record.
+fn json(record: Bytes) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], record).into_response()
+}
+
+/// Responds with the record of `ip` in a database of `kind`, along with the address, shaped as requested, noting the lookup for the access log.
+fn respond(kind: DatabaseKind, ip: IpAddr, record: Bytes, locales: &Locales, fields: &Fields) -> Response {
+    let lookup = access_log::Lookup::new(ip, &record);
+
+    (Extension(lookup), json(shape(record::with_ip(kind, record, ip), locales, fields))).into_response()
+}
+
+async fn city(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, locales: Locales, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::City).ok_or(LookupError::DatabaseNotLoaded)?;
+    let city = lookup_shared::<geoip2::City>(DatabaseKind::City, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::City, ip, timezone::add_offset(city), &locales, &fields))
+}
+
+async fn country(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, locales: Locales, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::Country).ok_or(LookupError::DatabaseNotLoaded)?;
+    let country = lookup_shared::<geoip2::Country>(DatabaseKind::Country, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::Country, ip, country, &locales, &fields))
+}
+
+async fn enterprise(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, locales: Locales, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::Enterprise).ok_or(LookupError::DatabaseNotLoaded)?;
+    let enterprise = lookup_shared::<geoip2::Enterprise>(DatabaseKind::Enterprise, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::Enterprise, ip, timezone::add_offset(enterprise), &locales, &fields))
+}
+
+async fn asn(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::Asn).ok_or(LookupError::DatabaseNotLoaded)?;
+    let asn = lookup_shared::<geoip2::Asn>(DatabaseKind::Asn, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::Asn, ip, asn, &Locales::default(), &fields))
+}
+
+async fn anonymous_ip(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::AnonymousIp).ok_or(LookupError::DatabaseNotLoaded)?;
+    let anonymous_ip = lookup_shared::<geoip2::AnonymousIp>(DatabaseKind::AnonymousIp, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::AnonymousIp, ip, anonymous_ip, &Locales::default(), &fields))
+}
+
+async fn isp(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::Isp).ok_or(LookupError::DatabaseNotLoaded)?;
+    let isp = lookup_shared::<geoip2::Isp>(DatabaseKind::Isp, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::Isp, ip, isp, &Locales::default(), &fields))
+}
+
+async fn domain(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::Domain).ok_or(LookupError::DatabaseNotLoaded)?;
+    let domain = lookup_shared::<geoip2::Domain>(DatabaseKind::Domain, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::Domain, ip, domain, &Locales::default(), &fields))
+}
+
+async fn connection_type(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::ConnectionType).ok_or(LookupError::DatabaseNotLoaded)?;
+    let connection_type = lookup_shared::<geoip2::ConnectionType>(DatabaseKind::ConnectionType, &readers, ip, &state).await?;
+
+    Ok(respond(DatabaseKind::ConnectionType, ip, connection_type, &Locales::default(), &fields))
+}
+
+#[derive(Deserialize)]
+struct RawQuery {
+    database: Option<String>,
+}
+
+/// Returns the record of any database as it is stored, for databases with a schema of their own. Looks up the custom database unless another one is picked with `?database=`.
+async fn raw(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, fields: Fields, Query(query): Query<RawQuery>) -> Result<Response, LookupError> {
+    let kind = match query.database {
+        Some(database) => DatabaseKind::from_str(&database).map_err(|_| LookupError::DatabaseInvalid)?,
+        None => DatabaseKind::Custom,
+    };
+    let readers = state.databases.readers(kind).ok_or(LookupError::DatabaseNotLoaded)?;
+    let record = lookup_shared::<serde_json::Value>(kind, &readers, ip, &state).await?;
+
+    Ok(respond(kind, ip, record, &Locales::default(), &fields))
+}
+
+/// Parses a found record, turning a missing one into `None`, for lookups whose absence is not an error.
+fn found(record: Result<Bytes, LookupError>) -> Result<Option<serde_json::Value>, LookupError> {
+    match record {
+        Ok(record) => Ok(Some(serde_json::from_slice(&record).expect("records are valid JSON"))),
+        Err(LookupError::IpAddressNotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Merges the City, ASN and Anonymous IP records of an address, whichever of those databases are loaded, into one record shaped like MaxMind's Insights response: ASN and anonymizer fields go under `traits`.
+async fn insights(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp, locales: Locales, fields: Fields) -> Result<(StatusCode, Extension<access_log::Lookup>, Json<serde_json::Value>), LookupError> {
+    let databases = &state.databases;
+
+    if [DatabaseKind::City, DatabaseKind::Asn, DatabaseKind::AnonymousIp].iter().all(|&kind| databases.get(kind).is_none()) {
+        return Err(LookupError::DatabaseNotLoaded);
+    }
+
+    let city = match databases.readers(DatabaseKind::City) {
+        Some(readers) => found(lookup_shared::<geoip2::City>(DatabaseKind::City, &readers, ip, &state).await)?,
+        None => None,
+    };
+    let asn = match databases.readers(DatabaseKind::Asn) {
+        Some(readers) => found(lookup_shared::<geoip2::Asn>(DatabaseKind::Asn, &readers, ip, &state).await)?,
+        None => None,
+    };
+    let anonymous_ip = match databases.readers(DatabaseKind::AnonymousIp) {
+        Some(readers) => found(lookup_shared::<geoip2::AnonymousIp>(DatabaseKind::AnonymousIp, &readers, ip, &state).await)?,
+        None => None,
+    };
+
+    if city.is_none() && asn.is_none() && anonymous_ip.is_none() {
+        return Err(LookupError::IpAddressNotFound);
+    }
+
+    let mut insights = city.unwrap_or_else(|| serde_json::json!({}));
+    if !insights["traits"].is_object() {
+        insights["traits"] = serde_json::json!({});
+    }
+    insights["traits"]["ip_address"] = serde_json::json!(ip.to_string());
+
+    for record in [asn, anonymous_ip].into_iter().flatten() {
+        let serde_json::Value::Object(record) = record else {
+            continue;
+        };
+
+        let traits = insights["traits"].as_object_mut().unwrap();
+        for (key, value) in record {
+            if !traits.contains_key(&key) {
+                traits.insert(key, value);
+            }
+        }
+    }
+
+    let lookup = access_log::Lookup { ip, country: insights["country"]["iso_code"].as_str().map(str::to_owned) };
+    timezone::add_offset_value(&mut insights);
+    locales.apply(&mut insights);
+    fields.apply(&mut insights);
+
+    Ok((StatusCode::OK, Extension(lookup), Json(insights)))
+}
+
+/// Shapes one record of a bulk lookup, or turns its error into the error object that takes its place.
+fn bulk_record(record: Result<Bytes, LookupError>, locales: &Locales, fields: &Fields) -> Bytes {
+    match record {
+        Ok(record) => shape(record, locales, fields),
+        Err(err) => serde_json::to_vec(&err.body().1).expect("errors serialize to JSON").into(),
+    }
+}
+
+/// Looks up each IP of the batch, replacing the records of IPs that fail with an error object so one bad address does not fail the whole batch.
+async fn city_batch(State(state): State<Arc<AppState>>, locales: Locales, fields: Fields, Json(ips): Json<Vec<String>>) -> Result<Response, LookupError> {
+    if ips.len() > state.batch_limit {
+        return Err(LookupError::BatchTooLarge);
+    }
+
+    let readers = state.databases.readers(DatabaseKind::City).ok_or(LookupError::DatabaseNotLoaded)?;
+    let mut cities = vec![b'['];
+    for (index, ip) in ips.iter().enumerate() {
+        if index > 0 {
+            cities.push(b',');
+        }
+        cities.extend_from_slice(&bulk_record(
+            parse_ip(ip).and_then(|ip| lookup::<geoip2::City>(DatabaseKind::City, &readers, ip, &state).map(|city| record::with_ip(DatabaseKind::City, timezone::add_offset(city), ip))),
+            &locales,
+            &fields,
+        ));
+    }
+    cities.push(b']');
+
+    Ok(json(cities.into()))
+}
+
+/// Looks up each line of the body as an IP and streams back one JSON record (or error object) per line. The body is read as results are sent, so memory stays bounded regardless of the size of the job.
+async fn city_stream(State(state): State<Arc<AppState>>, locales: Locales, fields: Fields, body: Body) -> Result<Response, LookupError> {
+    let readers = state.databases.readers(DatabaseKind::City).ok_or(LookupError::DatabaseNotLoaded)?;
+    let mut lines = tokio_util::io::StreamReader::new(body.into_data_stream().map_err(std::io::Error::other)).lines();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(64);
+
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            let ip = line.trim();
+            if ip.is_empty() {
+                continue;
+            }
+
+            let mut city = bulk_record(
+                parse_ip(ip).and_then(|ip| lookup::<geoip2::City>(DatabaseKind::City, &readers, ip, &state).map(|city| record::with_ip(DatabaseKind::City, timezone::add_offset(city), ip))),
+                &locales,
+                &fields,
+            )
+            .to_vec();
+            city.push(b'\n');
+
+            if tx.send(Ok(city.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx))).into_response())
+}
+
+/// Looks up each IP in every loaded type of database so their records are cached, e.g. for the hottest addresses after a database update. Chained databases are looked up through their chain, like lookups are, so each record is cached as what the chain answers.
+async fn warm_cache(State(state): State<Arc<AppState>>, Json(ips): Json<Vec<String>>) -> Result<(StatusCode, Json<serde_json::Value>), LookupError> {
+    if ips.len() > state.batch_limit {
+        return Err(LookupError::BatchTooLarge);
+    }
+
+    let kinds = state.databases.iter().map(|database| database.kind).collect::<BTreeSet<_>>();
+    let mut records = 0;
+    for ip in ips.iter().filter_map(|ip| parse_ip(ip).ok()) {
+        for &kind in &kinds {
+            let Some(readers) = state.databases.readers(kind) else {
+                continue;
+            };
+            if lookup_kind(kind, &readers, ip, &state).await.is_ok() {
+                records += 1;
+            }
+        }
+    }
+
+    Ok((StatusCode::OK, Json(serde_json::json!({ "records": records }))))
+}
+
+/// The metadata of each database, keyed by type.
+fn database_metadata(databases: &Databases) -> serde_json::Value {
+    let metadata = databases
+        .iter()
+        .map(|database| {
+            let reader = database.reader();
+            let metadata = &reader.metadata;
+            let built = humantime::format_rfc3339(std::time::UNIX_EPOCH + Duration::from_secs(metadata.build_epoch));
+            let info = serde_json::json!({
+                "path": database.path,
+                "database_type": metadata.database_type,
+                "build_epoch": built.to_string(),
+                "binary_format_version": format!("{}.{}", metadata.binary_format_major_version, metadata.binary_format_minor_version),
+                "ip_version": metadata.ip_version,
+                "node_count": metadata.node_count,
+                "record_size": metadata.record_size,
+                "languages": metadata.languages,
+                "description": metadata.description,
+            });
+            (database.name(), info)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    serde_json::Value::Object(metadata)
+}
+
+/// Returns the metadata of each loaded database, keyed by type.
+async fn metadata(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK, Json(database_metadata(&state.databases)))
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Whether a database built at `build_epoch` is older than `--max-database-age`, updating its `geoip_database_age_seconds` and `geoip_database_stale` gauges.
+fn is_stale(state: &AppState, database: &Database, build_epoch: u64) -> bool {
+    let age = unix_time().saturating_sub(build_epoch);
+    let stale = state.max_database_age.is_some_and(|max_age| age > max_age.as_secs());
+    metrics::gauge!("geoip_database_age_seconds", "database" => database.name()).set(age as f64);
+    metrics::gauge!("geoip_database_stale", "database" => database.name()).set(if stale { 1.0 } else { 0.0 });
+
+    stale
+}
+
+struct DatabaseChecks {
+    /// Every database answered the status lookup.
+    healthy: bool,
+    /// No database is older than `--max-database-age`.
+    fresh: bool,
+    databases: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Looks up `--status-ip` in every loaded database and checks their age. Custom databases may not have the address, so for them only a read error counts as a failure.
+fn check_databases(state: &AppState) -> DatabaseChecks {
+    let now = unix_time();
+    let mut healthy = !state.databases.is_empty();
+    let mut fresh = true;
+
+    let databases = state
+        .databases
+        .iter()
+        .map(|database| {
+            let reader = database.reader();
+            let build_epoch = reader.metadata.build_epoch;
+            let ok = match reader.lookup::<serde::de::IgnoredAny>(state.status_ip) {
+                Ok(_) => true,
+                Err(MaxMindDBError::AddressNotFoundError(_)) => database.kind == DatabaseKind::Custom,
+                Err(err) => {
+                    error!("status lookup of {} in the {} database failed: {err}", state.status_ip, database.name());
+                    false
+                }
+            };
+            let stale = is_stale(state, database, build_epoch);
+
+            healthy &= ok;
+            fresh &= !stale;
+
+            let info = serde_json::json!({
+                "status": if ok { "ok" } else { "error" },
+                "stale": stale,
+                "database_build_epoch": build_epoch,
+                "database_age_seconds": now.saturating_sub(build_epoch),
+                "reader": database.mode(),
+            });
+            (database.name(), info)
+        })
+        .collect();
+
+    DatabaseChecks { healthy, fresh, databases }
+}
+
+/// Reports the health of the databases, the age of the oldest one, and the uptime of the server, returning `503` if any database lookup fails.
+async fn status(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let checks = check_databases(&state);
+    let oldest = state.databases.iter().map(|database| database.reader().metadata.build_epoch).min();
+
+    let status = serde_json::json!({
+        "status": if checks.healthy { "ok" } else { "error" },
+        "database_build_epoch": oldest,
+        "database_age_seconds": oldest.map(|oldest| unix_time().saturating_sub(oldest)),
+        "uptime_seconds": state.started.elapsed().as_secs(),
+        "databases": checks.databases,
+    });
+
+    (if checks.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE }, Json(status))
+}
+
+/// Brings the age and staleness gauges of the databases up to date, as they change without anything happening.
+fn update_database_gauges(state: &AppState) {
+    for database in state.databases.iter() {
+        is_stale(state, database, database.reader().metadata.build_epoch);
+    }
+}
+
+async fn render_metrics(State(state): State<Arc<AppState>>, prometheus: PrometheusHandle) -> String {
+    update_database_gauges(&state);
+
+    prometheus.render()
+}
+
+/// What was deployed: the version of the crate, the commit and time it was built from, the compiler, and the cargo features enabled, as embedded by `build.rs`.
+async fn version() -> Json<serde_json::Value> {
+    let built = env!("GEOIP2_BUILD_EPOCH").parse::<u64>().ok().map(|epoch| humantime::format_rfc3339(std::time::UNIX_EPOCH + Duration::from_secs(epoch)).to_string());
+    let features = env!("GEOIP2_FEATURES").split(',').filter(|feature| !feature.is_empty()).collect::<Vec<_>>();
+
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": Some(env!("GEOIP2_GIT_SHA")).filter(|sha| !sha.is_empty()),
+        "build_timestamp": built,
+        "rustc_version": Some(env!("GEOIP2_RUSTC_VERSION")).filter(|rustc| !rustc.is_empty()),
+        "features": features,
+    }))
+}
+
+/// Liveness probe: the process is up and serving requests.
+async fn healthz() -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Readiness probe: every database is loaded, answers lookups, and is not older than `--max-database-age`, so the instance can take traffic.
+async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let checks = check_databases(&state);
+    let ready = checks.healthy && checks.fresh;
+    let readiness = serde_json::json!({ "status": if ready { "ok" } else { "error" }, "databases": checks.databases });
+
+    (if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE }, Json(readiness))
+}
+
+async fn reload(State(state): State<Arc<AppState>>) -> Result<(StatusCode, Json<serde_json::Value>), LookupError> {
+    let databases = &state.databases;
+    databases.reload().map_err(|_| LookupError::DatabaseReloadFailed)?;
+
+    let reloaded = databases
+        .iter()
+        .map(|database| {
+            let reader = database.reader();
+            let info = serde_json::json!({ "database_type": reader.metadata.database_type, "build_epoch": reader.metadata.build_epoch, "node_count": reader.metadata.node_count });
+            (database.name(), info)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok((StatusCode::OK, Json(serde_json::Value::Object(reloaded))))
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(databases: Arc<Databases>, tls: Option<(tls::TlsArgs, axum_server::tls_rustls::RustlsConfig)>) -> anyhow::Result<()> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        info!("received SIGHUP, reloading databases...");
+        let _ = databases.reload();
+
+        if let Some((args, config)) = &tls {
+            let _ = args.reload(config);
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns errors of the timeout and load shedding layers into responses.
+async fn middleware_error(err: BoxError) -> LookupError {
+    match err.is::<tower::timeout::error::Elapsed>() {
+        true => LookupError::RequestTimeout,
+        false => LookupError::ServerOverloaded,
+    }
+}
+
+/// Answers a request whose handler panicked, instead of dropping its connection. Runs inside the request span, so the log line says which request it was.
+fn panic_response(panic: Box<dyn std::any::Any + Send + 'static>) -> Response {
+    let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => *message,
+        (None, Some(message)) => message.as_str(),
+        (None, None) => "unknown panic",
+    };
+    error!(panic = message, "request handler panicked");
+
+    LookupError::InternalError.into_response()
+}
+
+/// Rejects bodies of more than `max` bytes with the `413` error object: by their `Content-Length` before they are read, and otherwise in place of the plain text answer of the JSON extractors once they have read that much. The line-per-address stream is read as it is answered, so it takes bodies of any size.
+async fn limit_body(max: usize, request: Request, next: Next) -> Response {
+    let streamed = request.extensions().get::<MatchedPath>().is_some_and(|route| route.as_str() == "/geoip/v2.1/city/stream");
+    let length = request.headers().get(header::CONTENT_LENGTH).and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    if !streamed && length.is_some_and(|length| length > max as u64) {
+        return LookupError::BodyTooLarge.into_response();
+    }
+
+    let response = next.run(request).await;
+    let is_json = response.headers().get(header::CONTENT_TYPE).is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    match response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        true => LookupError::BodyTooLarge.into_response(),
+        false => response,
+    }
+}
+
+async fn not_found() -> LookupError {
+    LookupError::RouteNotFound
+}
+
+/// Replaces the empty `405` axum answers a known path with an unsupported method with the same error object as every other error, keeping its `Allow` header.
+async fn method_not_allowed(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED || response.headers().contains_key(header::CONTENT_TYPE) {
+        return response;
+    }
+
+    let mut error = LookupError::MethodNotAllowed.into_response();
+    if let Some(allow) = response.headers().get(header::ALLOW) {
+        error.headers_mut().insert(header::ALLOW, allow.clone());
+    }
+
+    error
+}
+
+/// Answers unknown paths and methods with JSON errors, so clients can parse every response. Applied to the routers as they are served, since merged routers may not both have a fallback; serving [`router`] on its own, apply it too.
+pub fn json_errors(router: Router) -> Router {
+    router.fallback(not_found).layer(axum::middleware::map_response(method_not_allowed))
+}
+
+/// Builds the lookup routes and the admin routes, which the server can serve on another port, along with the state they share.
+fn routers(config: Config) -> (Router, Router, Arc<AppState>) {
+    let span_client_ip = config.client_ip.clone();
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(move |request: &Request| {
+            let client_ip = span_client_ip.client_ip(request.extensions(), request.headers());
+            let span = tracing::info_span!("request", method = %request.method(), uri = %anonymize::text(&request.uri().to_string()), route = request.extensions().get::<MatchedPath>().map(MatchedPath::as_str), version = ?request.version(), client_ip = client_ip.map(|ip| tracing::field::display(anonymize::ip(ip))), request_id = request_id::id(request), subject = tracing::field::Empty);
+            #[cfg(feature = "otlp")]
+            otlp::set_parent(&span, request.headers());
+            span
+        })
+        .on_response(access_log::on_response);
+
+    let state = Arc::new(AppState {
+        databases: config.databases,
+        batch_limit: config.batch_limit,
+        client_ip: config.client_ip,
+        network: config.network,
+        enrich_countries: config.enrich_countries,
+        default_locales: config.default_locales,
+        status_ip: config.status_ip,
+        started: Instant::now(),
+        max_database_age: config.max_database_age,
+        auth: config.auth,
+        client_filter: config.client_filter,
+        rate_limiter: config.rate_limiter,
+        ip_rate_limiter: config.ip_rate_limiter,
+        rate_limit_exempt: config.rate_limit_exempt,
+        hostnames: config.hostnames,
+        reverse_dns: config.reverse_dns,
+        private_response: config.private_response,
+        upstream: config.upstream,
+        cache_max_age: config.cache_max_age,
+        cache: config.cache,
+        #[cfg(feature = "redis")]
+        redis: config.redis,
+    });
+
+    let api = Router::new()
+        .route("/geoip/v2.1/city", post(city_batch))
+        .route("/geoip/v2.1/city/stream", post(city_stream))
+        .route("/geoip/v2.1/city/:ip", get(city))
+        .route("/geoip/v2.1/country/:ip", get(country))
+        .route("/geoip/v2.1/enterprise/:ip", get(enterprise))
+        .route("/geoip/v2.1/asn/:ip", get(asn))
+        .route("/geoip/v2.1/anonymous-ip/:ip", get(anonymous_ip))
+        .route("/geoip/v2.1/isp/:ip", get(isp))
+        .route("/geoip/v2.1/domain/:ip", get(domain))
+        .route("/geoip/v2.1/connection-type/:ip", get(connection_type))
+        .route("/geoip/v2.1/insights/:ip", get(insights))
+        .route("/geoip/v2.1/timezone/:ip", get(timezone::lookup))
+        .route("/geoip/v2.1/distance/:ip_a/:ip_b", get(distance::between))
+        .route("/geoip/v2.1/metadata", get(metadata))
+        .route("/lookup/:ip", get(raw))
+        .route("/graphql", post(graphql::execute))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), dns::add_hostname))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), etag::conditional));
+    // Merged past the ETags, which only know about the `me` paths of the lookup routes.
+    let api = config.compat.iter().fold(api, |api, compat| api.merge(compat.router()));
+    let api = api
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::require))
+        .route_layer(axum::middleware::from_fn(format::negotiate))
+        .route("/openapi.json", get(openapi::json))
+        .route("/schema.proto", get(protobuf::schema));
+    let api = match config.docs {
+        true => api.route("/docs", get(openapi::docs)),
+        false => api,
+    };
+
+    let max_body_size = config.max_body_size;
+    let body_limit = move || {
+        ServiceBuilder::new()
+            .layer(DefaultBodyLimit::max(max_body_size))
+            .layer(axum::middleware::from_fn(move |request: Request, next: Next| limit_body(max_body_size, request, next)))
+    };
+    let api = api.layer(body_limit());
+
+    // Shed load rather than queueing requests unboundedly, so a spike doesn't raise the latency for everyone.
+    let overload = config.max_in_flight.map(|max_in_flight| ServiceBuilder::new().load_shed().concurrency_limit(max_in_flight).into_inner());
+    let api = api.layer(ServiceBuilder::new().layer(HandleErrorLayer::new(middleware_error)).timeout(config.request_timeout).option_layer(overload));
+
+    let api = match config.compression {
+        true => api.layer(CompressionLayer::new()),
+        false => api,
+    };
+    let api = match config.cors {
+        Some(cors) => api.layer(cors),
+        None => api,
+    };
+    // Outside of everything but the panic and tracing layers, so disallowed clients get nothing else out of the server.
+    let restrict = state.client_filter.is_enabled().then(|| axum::middleware::from_fn_with_state(state.clone(), client_filter::restrict));
+    let api = match restrict.clone() {
+        Some(restrict) => api.layer(restrict),
+        None => api,
+    };
+    let api = api.layer(CatchPanicLayer::custom(panic_response)).layer(trace.clone());
+    #[cfg(feature = "sentry")]
+    let api = api.layer(axum::middleware::from_fn(error_reporting::capture));
+    let api = api.with_state(state.clone());
+
+    let admin = Router::new()
+        .route("/admin/reload", post(reload))
+        .route("/admin/cache/warm", post(warm_cache))
+        .layer(trace)
+        .route("/status", get(status))
+        .route("/version", get(version))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    let admin = match config.prometheus {
+        Some(prometheus) => admin.route("/metrics", get(move |state: State<Arc<AppState>>| render_metrics(state, prometheus.clone()))),
+        None => admin,
+    };
+    let admin = admin.layer(body_limit());
+    let admin = match restrict {
+        Some(restrict) => admin.layer(restrict),
+        None => admin,
+    };
+    let admin = admin.layer(CatchPanicLayer::custom(panic_response));
+    #[cfg(feature = "sentry")]
+    let admin = admin.layer(axum::middleware::from_fn(error_reporting::capture));
+    let admin = admin.with_state(state.clone());
+
+    (api, admin, state)
+}
+
+/// The lookup and admin routes of the server, for mounting in another axum application or calling directly, e.g. with `tower::ServiceExt::oneshot`. Unlike the server's, unknown paths are left to the fallback of the application.
+pub fn router(config: Config) -> Router {
+    let (api, admin, _) = routers(config);
+
+    request_id::propagate(api.merge(admin))
+}
+
+/// Resolves once the process receives SIGINT or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for SIGINT: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                error!("failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+fn cli() -> clap::Command {
+    clap::Command::new("geoip2-server")
+        .bin_name("geoip2-server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .propagate_version(true)
+        .arg(clap::Arg::new("bind").value_name("BIND").help("Address to listen on, or unix:/path/to.sock to listen on a unix socket").env("GEOIP2_BIND").long("bind").short('b').global(true).default_value("0.0.0.0"))
+        .arg(
+            clap::Arg::new("socket-mode")
+                .value_name("MODE")
+                .help("Octal permissions of the unix socket")
+                .env("GEOIP2_SOCKET_MODE")
+                .long("socket-mode")
+                .global(true)
+                .default_value("660")
+                .value_parser(|mode: &str| u32::from_str_radix(mode, 8)),
+        )
+        .arg(
+            clap::Arg::new("reuse-port")
+                .value_name("N")
+                .help("Bind N listeners to the port with SO_REUSEPORT, each with its own accept loop")
+                .env("GEOIP2_REUSE_PORT")
+                .long("reuse-port")
+                .global(true)
+                .value_parser(clap::value_parser!(u16).range(1..).map(usize::from)),
+        )
+        .arg(clap::Arg::new("port").value_name("PORT").env("GEOIP2_PORT").long("port").short('p').global(true).default_value("3000").value_parser(clap::value_parser!(u16)))
+        .arg(
+            clap::Arg::new("admin-port")
+                .value_name("ADMIN_PORT")
+                .help("Serve /metrics, /status, the health probes and /admin/* on this port instead of the main one")
+                .env("GEOIP2_ADMIN_PORT")
+                .long("admin-port")
+                .global(true)
+                .value_parser(clap::value_parser!(u16)),
+        )
+        .arg(
+            clap::Arg::new("grpc-port")
+                .value_name("GRPC_PORT")
+                .help("Also serve the City, Country, Asn and BatchLookup RPCs of proto/geoip2.proto over gRPC on this port (requires the `grpc` feature)")
+                .env("GEOIP2_GRPC_PORT")
+                .long("grpc-port")
+                .global(true)
+                .value_parser(clap::value_parser!(u16)),
+        )
+        .subcommand(clap::Command::new("serve").about("Serve lookups over HTTP, the default when no command is given"))
+        .subcommand(
+            clap::Command::new("lookup")
+                .about("Look up an address in every database and print the records as JSON")
+                .arg(clap::Arg::new("ip").value_name("IP").help("The address to look up").required(true).value_parser(clap::value_parser!(IpAddr))),
+        )
+        .subcommand(clap::Command::new("inspect").about("Print the metadata of every database as JSON"))
+        .subcommand(
+            clap::Command::new("enrich")
+                .about("Copy a CSV or NDJSON file, adding fields of the record of the address in each row")
+                .arg(clap::Arg::new("input").value_name("PATH").help("CSV file, or NDJSON file if it ends in .ndjson or .jsonl").long("input").short('i').required(true).value_parser(clap::value_parser!(PathBuf)))
+                .arg(clap::Arg::new("column").value_name("NAME").help("Column or key holding the address").long("column").default_value("ip"))
+                .arg(clap::Arg::new("output").value_name("PATH").help("Where to write the enriched file instead of stdout").long("output").short('o').value_parser(clap::value_parser!(PathBuf)))
+                .arg(
+                    clap::Arg::new("fields")
+                        .value_name("FIELDS")
+                        .help("Comma-separated fields to add, taken from the first database whose record has them")
+                        .long("fields")
+                        .value_delimiter(',')
+                        .default_value("country.iso_code,subdivisions.0.iso_code,city.names.en,location.latitude,location.longitude,autonomous_system_number,autonomous_system_organization"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("verify")
+                .about("Check that every record of a database can be read, e.g. after downloading it")
+                .arg(clap::Arg::new("file").value_name("FILE").help("The .mmdb file to check").required(true).value_parser(clap::value_parser!(PathBuf))),
+        )
+        .subcommand(
+            clap::Command::new("diff")
+                .about("Report how many networks changed country, city or ASN between two builds of a database")
+                .arg(clap::Arg::new("old").value_name("OLD").help("The .mmdb file in use").required(true).value_parser(clap::value_parser!(PathBuf)))
+                .arg(clap::Arg::new("new").value_name("NEW").help("The .mmdb file to compare it with").required(true).value_parser(clap::value_parser!(PathBuf)))
+                .arg(
+                    clap::Arg::new("sample")
+                        .value_name("COUNT")
+                        .help("How many networks of the old build to compare, spread evenly over it")
+                        .long("sample")
+                        .default_value("10000")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    clap::Arg::new("networks")
+                        .value_name("PATH")
+                        .help("Compare the networks or addresses in this file, one per line, instead of a sample")
+                        .long("networks")
+                        .conflicts_with("sample")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("bench")
+                .about("Measure the latency and throughput of lookups in-process, without HTTP, e.g. to compare --in-memory or cache settings")
+                .arg(
+                    clap::Arg::new("requests")
+                        .value_name("COUNT")
+                        .help("How many requests to make, each looking up an address in every database, e.g. 1M")
+                        .long("requests")
+                        .default_value("1M")
+                        .value_parser(bench::parse_count),
+                )
+                .arg(
+                    clap::Arg::new("concurrency")
+                        .value_name("TASKS")
+                        .help("How many requests to make at a time")
+                        .long("concurrency")
+                        .default_value("64")
+                        .value_parser(bench::parse_count),
+                )
+                .arg(
+                    clap::Arg::new("addresses")
+                        .value_name("COUNT")
+                        .help("How many distinct random addresses to look up, fewer means more cache hits")
+                        .long("addresses")
+                        .default_value("100k")
+                        .value_parser(bench::parse_count),
+                ),
+        )
+        .arg(clap::Arg::new("config").value_name("PATH").help("TOML or YAML file with defaults for these flags, e.g. port = 3000 or size under [cache] for --cache-size").env("GEOIP2_CONFIG").long("config").global(true).value_parser(clap::value_parser!(PathBuf)))
+        .arg(
+            clap::Arg::new("db")
+                .value_name("[TYPE=]DB")
+                .help("Database path or http(s):// or s3:// URL to serve, optionally prefixed with its type (city, country, enterprise, asn, anonymous-ip, isp, domain, connection-type, custom); may be repeated")
+                .env("GEOIP2_DB")
+                .long("database")
+                .short('d')
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(DatabaseArg)),
+        )
+        .arg(
+            clap::Arg::new("batch-limit")
+                .value_name("BATCH_LIMIT")
+                .help("Maximum number of IP addresses in a single batch lookup")
+                .env("GEOIP2_BATCH_LIMIT")
+                .long("batch-limit")
+                .global(true)
+                .default_value("1000")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            clap::Arg::new("trusted-proxies")
+                .value_name("CIDR")
+                .help("Proxies whose Forwarded/X-Forwarded-For headers are trusted to carry the client address")
+                .env("GEOIP2_TRUSTED_PROXIES")
+                .long("trusted-proxies")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(IpNetwork)),
+        )
+        .arg(
+            clap::Arg::new("allow-cidr")
+                .value_name("CIDR")
+                .help("Only answer clients in these networks, e.g. the range of the VPC")
+                .env("GEOIP2_ALLOW_CIDR")
+                .long("allow-cidr")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(IpNetwork)),
+        )
+        .arg(
+            clap::Arg::new("deny-cidr")
+                .value_name("CIDR")
+                .help("Never answer clients in these networks, even if --allow-cidr allows them")
+                .env("GEOIP2_DENY_CIDR")
+                .long("deny-cidr")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(IpNetwork)),
+        )
+        .arg(
+            clap::Arg::new("api-keys-file")
+                .value_name("PATH")
+                .help("Require one of the API keys in this file, one per line, in X-API-Key or Authorization: Bearer on lookups")
+                .env("GEOIP2_API_KEYS_FILE")
+                .long("api-keys-file")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            clap::Arg::new("accounts-file")
+                .value_name("PATH")
+                .help("Accept Basic auth with the account_id:license_key pairs in this file, one per line, like MaxMind's web service")
+                .env("GEOIP2_ACCOUNTS_FILE")
+                .long("accounts-file")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(clap::Arg::new("jwks-url").value_name("URL").help("Accept JWT bearer tokens signed by the keys published at this JWKS URL").env("GEOIP2_JWKS_URL").long("jwks-url").global(true))
+        .arg(clap::Arg::new("jwt-issuer").value_name("ISSUER").help("Only accept JWTs issued by this issuer").env("GEOIP2_JWT_ISSUER").long("jwt-issuer").global(true).requires("jwks-url"))
+        .arg(clap::Arg::new("jwt-audience").value_name("AUDIENCE").help("Only accept JWTs for this audience").env("GEOIP2_JWT_AUDIENCE").long("jwt-audience").global(true).requires("jwks-url"))
+        .arg(
+            clap::Arg::new("jwks-refresh-interval")
+                .value_name("DURATION")
+                .help("How often to fetch the JWKS again")
+                .env("GEOIP2_JWKS_REFRESH_INTERVAL")
+                .long("jwks-refresh-interval")
+                .global(true)
+                .default_value("1h")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("rate-limit")
+                .value_name("RATE")
+                .help("Limit lookups per API key, or per client address without one, e.g. 100/s")
+                .env("GEOIP2_RATE_LIMIT")
+                .long("rate-limit")
+                .global(true)
+                .value_parser(clap::value_parser!(Rate)),
+        )
+        .arg(
+            clap::Arg::new("rate-limit-burst")
+                .value_name("REQUESTS")
+                .help("How many requests over --rate-limit may arrive at once")
+                .env("GEOIP2_RATE_LIMIT_BURST")
+                .long("rate-limit-burst")
+                .global(true)
+                .requires("rate-limit")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            clap::Arg::new("ip-rate-limit")
+                .value_name("RATE")
+                .help("Limit lookups per client address, whether or not they have credentials, e.g. 20/s")
+                .env("GEOIP2_IP_RATE_LIMIT")
+                .long("ip-rate-limit")
+                .global(true)
+                .value_parser(clap::value_parser!(Rate)),
+        )
+        .arg(
+            clap::Arg::new("ip-rate-limit-burst")
+                .value_name("REQUESTS")
+                .help("How many requests over --ip-rate-limit may arrive at once")
+                .env("GEOIP2_IP_RATE_LIMIT_BURST")
+                .long("ip-rate-limit-burst")
+                .global(true)
+                .requires("ip-rate-limit")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            clap::Arg::new("rate-limit-exempt")
+                .value_name("CIDR")
+                .help("Client networks neither --rate-limit nor --ip-rate-limit applies to, e.g. 10.0.0.0/8 for internal batch jobs")
+                .env("GEOIP2_RATE_LIMIT_EXEMPT")
+                .long("rate-limit-exempt")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(IpNetwork)),
+        )
+        .arg(
+            clap::Arg::new("resolve-hostnames")
+                .help("Look up the address a hostname in the path resolves to for lookups with ?resolve=true, e.g. /geoip/v2.1/city/example.com?resolve=true")
+                .env("GEOIP2_RESOLVE_HOSTNAMES")
+                .long("resolve-hostnames")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("resolve-prefer")
+                .value_name("FAMILY")
+                .help("Which address of a hostname with both to look up, ipv4 or ipv6")
+                .env("GEOIP2_RESOLVE_PREFER")
+                .long("resolve-prefer")
+                .global(true)
+                .default_value("ipv4")
+                .value_parser(clap::value_parser!(AddressPreference)),
+        )
+        .arg(
+            clap::Arg::new("resolve-rate-limit")
+                .value_name("RATE")
+                .help("Limit hostname resolutions per API key, or per client address without one, e.g. 10/s")
+                .env("GEOIP2_RESOLVE_RATE_LIMIT")
+                .long("resolve-rate-limit")
+                .global(true)
+                .default_value("10/s")
+                .value_parser(clap::value_parser!(Rate)),
+        )
+        .arg(
+            clap::Arg::new("reverse-dns")
+                .help("Add the PTR record of the address to lookups with ?rdns=true")
+                .env("GEOIP2_REVERSE_DNS")
+                .long("reverse-dns")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("reverse-dns-timeout")
+                .value_name("DURATION")
+                .help("Leave the hostname out of lookups whose PTR record takes longer than this")
+                .env("GEOIP2_REVERSE_DNS_TIMEOUT")
+                .long("reverse-dns-timeout")
+                .global(true)
+                .default_value("500ms")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("cache-max-age")
+                .value_name("DURATION")
+                .help("Let clients and CDNs cache lookups for this long with a Cache-Control header")
+                .env("GEOIP2_CACHE_MAX_AGE")
+                .long("cache-max-age")
+                .global(true)
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(clap::Arg::new("cache-size").value_name("RECORDS").help("Cache up to this many looked up records in memory").env("GEOIP2_CACHE_SIZE").long("cache-size").global(true).value_parser(clap::value_parser!(u64)))
+        .arg(
+            clap::Arg::new("cache-ttl")
+                .value_name("DURATION")
+                .help("How long to keep records in the cache")
+                .env("GEOIP2_CACHE_TTL")
+                .long("cache-ttl")
+                .global(true)
+                .default_value("1h")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(clap::Arg::new("redis-url").value_name("URL").help("Share looked up records with other replicas through this Redis, e.g. redis://cache:6379 (requires the `redis` feature)").env("GEOIP2_REDIS_URL").long("redis-url").global(true))
+        .arg(
+            clap::Arg::new("redis-ttl")
+                .value_name("DURATION")
+                .help("How long to keep records in Redis")
+                .env("GEOIP2_REDIS_TTL")
+                .long("redis-ttl")
+                .global(true)
+                .default_value("24h")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(clap::Arg::new("compression").help("Compress lookup responses with gzip, brotli or zstd if the client accepts it").env("GEOIP2_COMPRESSION").long("compression").global(true).action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("docs")
+                .help("Serve Swagger UI for the OpenAPI document at /openapi.json at /docs")
+                .env("GEOIP2_DOCS")
+                .long("docs")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("compat")
+                .value_name("MODES")
+                .help("Also answer the API of these services, so their clients can switch over unchanged: freegeoip or ip-api (/json/:ip), or ipinfo (/:ip)")
+                .env("GEOIP2_COMPAT")
+                .long("compat")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(Compat)),
+        )
+        .arg(
+            clap::Arg::new("cors-origins")
+                .value_name("ORIGINS")
+                .help("Allow browsers on these origins to call the lookup endpoints, e.g. https://dashboard.example.com")
+                .env("GEOIP2_CORS_ORIGINS")
+                .long("cors-origins")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(|origin: &str| HeaderValue::from_str(origin)),
+        )
+        .arg(
+            clap::Arg::new("cors-methods")
+                .value_name("METHODS")
+                .help("Methods allowed from --cors-origins")
+                .env("GEOIP2_CORS_METHODS")
+                .long("cors-methods")
+                .global(true)
+                .requires("cors-origins")
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .default_value("GET,POST")
+                .value_parser(|method: &str| Method::from_str(method)),
+        )
+        .arg(clap::Arg::new("cors-allow-credentials").help("Allow requests from --cors-origins to include credentials").env("GEOIP2_CORS_ALLOW_CREDENTIALS").long("cors-allow-credentials").global(true).requires("cors-origins").action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("cors-any").help("Allow any origin, method and header, for development").env("GEOIP2_CORS_ANY").long("cors-any").global(true).conflicts_with("cors-origins").action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("request-timeout")
+                .value_name("DURATION")
+                .help("Respond with 504 to lookups that take longer than this")
+                .env("GEOIP2_REQUEST_TIMEOUT")
+                .long("request-timeout")
+                .global(true)
+                .default_value("5s")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("max-in-flight")
+                .value_name("REQUESTS")
+                .help("Reject lookups with 503 while this many are already being handled")
+                .env("GEOIP2_MAX_IN_FLIGHT")
+                .long("max-in-flight")
+                .global(true)
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            clap::Arg::new("max-body-size")
+                .value_name("BYTES")
+                .help("Reject request bodies larger than this with 413, e.g. 512K or 4M, except for the line-per-address stream")
+                .env("GEOIP2_MAX_BODY_SIZE")
+                .long("max-body-size")
+                .global(true)
+                .default_value("2M")
+                .value_parser(parse_size),
+        )
+        .arg(
+            clap::Arg::new("proxy-protocol")
+                .help("Expect a PROXY protocol v1 or v2 header on every connection to the main port, as sent by AWS NLBs or HAProxy in TCP mode, and use its client address")
+                .env("GEOIP2_PROXY_PROTOCOL")
+                .long("proxy-protocol")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("real-ip-header")
+                .value_name("HEADER")
+                .help("Header trusted proxies put the client address in, instead of Forwarded/X-Forwarded-For")
+                .env("GEOIP2_REAL_IP_HEADER")
+                .long("real-ip-header")
+                .global(true)
+                .value_parser(clap::value_parser!(HeaderName)),
+        )
+        .arg(clap::Arg::new("account-id").value_name("ACCOUNT_ID").help("MaxMind account ID used to download database updates").env("GEOIP2_MAXMIND_ACCOUNT_ID").long("account-id").global(true).requires("license-key"))
+        .arg(clap::Arg::new("license-key").value_name("LICENSE_KEY").help("MaxMind license key used to download database updates").env("GEOIP2_MAXMIND_LICENSE_KEY").long("license-key").global(true).requires("account-id").hide_env_values(true))
+        .arg(
+            clap::Arg::new("upstream")
+                .value_name("URL")
+                .help("Ask MaxMind's web service at this URL, e.g. https://geoip.maxmind.com, for City and Country lookups the databases have no record, or no city, for")
+                .env("GEOIP2_UPSTREAM")
+                .long("upstream")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::new("upstream-account-id")
+                .value_name("ACCOUNT_ID")
+                .help("MaxMind account ID for the web service, --account-id by default")
+                .env("GEOIP2_UPSTREAM_ACCOUNT_ID")
+                .long("upstream-account-id")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::new("upstream-license-key")
+                .value_name("LICENSE_KEY")
+                .help("MaxMind license key for the web service, --license-key by default")
+                .env("GEOIP2_UPSTREAM_LICENSE_KEY")
+                .long("upstream-license-key")
+                .global(true)
+                .hide_env_values(true),
+        )
+        .arg(
+            clap::Arg::new("upstream-budget")
+                .value_name("REQUESTS")
+                .help("How many requests a minute to send to the web service at most")
+                .env("GEOIP2_UPSTREAM_BUDGET")
+                .long("upstream-budget")
+                .global(true)
+                .default_value("60")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+        .arg(
+            clap::Arg::new("upstream-cache-ttl")
+                .value_name("DURATION")
+                .help("How long to keep the answers of the web service")
+                .env("GEOIP2_UPSTREAM_CACHE_TTL")
+                .long("upstream-cache-ttl")
+                .global(true)
+                .default_value("1d")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("update-interval")
+                .value_name("INTERVAL")
+                .help("How often to download database updates from MaxMind")
+                .env("GEOIP2_UPDATE_INTERVAL")
+                .long("update-interval")
+                .global(true)
+                .default_value("24h")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("refresh-interval")
+                .value_name("INTERVAL")
+                .help("How often to check databases given by URL for changes")
+                .env("GEOIP2_REFRESH_INTERVAL")
+                .long("refresh-interval")
+                .global(true)
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("status-ip")
+                .value_name("IP")
+                .help("Public IP address /status and the self-test look up to check the databases")
+                .env("GEOIP2_STATUS_IP")
+                .long("status-ip")
+                .global(true)
+                .default_value("8.8.8.8")
+                .value_parser(clap::value_parser!(IpAddr)),
+        )
+        .arg(
+            clap::Arg::new("check-config")
+                .help("Check the settings, local databases, overrides, TLS material and credential files, print the effective settings and exit")
+                .env("GEOIP2_CHECK_CONFIG")
+                .long("check-config")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("self-test")
+                .help("Check the databases like on startup, print the results and exit, failing if any check failed")
+                .env("GEOIP2_SELF_TEST")
+                .long("self-test")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("max-database-age")
+                .value_name("AGE")
+                .help("Report not ready once a database is older than this, e.g. 35d")
+                .env("GEOIP2_MAX_DATABASE_AGE")
+                .long("max-database-age")
+                .global(true)
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(
+            clap::Arg::new("overrides")
+                .value_name("FILE")
+                .help("A YAML list of networks and the records, or fields of them, to answer their lookups with, reloaded with the databases")
+                .env("GEOIP2_OVERRIDES")
+                .long("overrides")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            clap::Arg::new("private-response")
+                .value_name("JSON")
+                .help(r#"Answer lookups of private and other reserved addresses with this record instead of a 400, e.g. {"country":{"iso_code":"ZZ"}}"#)
+                .env("GEOIP2_PRIVATE_RESPONSE")
+                .long("private-response")
+                .global(true)
+                .value_parser(reserved::parse_response),
+        )
+        .arg(clap::Arg::new("in-memory").help("Read databases into memory instead of mapping them, so lookups never wait on disk").env("GEOIP2_IN_MEMORY").long("in-memory").global(true).action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("default-locale").value_name("LOCALES").help("Locales to keep in names when the request asks for none, e.g. en,de").env("GEOIP2_DEFAULT_LOCALE").long("default-locale").global(true))
+        .arg(clap::Arg::new("no-network").help("Do not include the network of the matched record in responses").env("GEOIP2_NO_NETWORK").long("no-network").global(true).action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("enrich-countries")
+                .help("Add the flag emoji, currency and calling code of countries, and the English name of continents, to records")
+                .env("GEOIP2_ENRICH_COUNTRIES")
+                .long("enrich-countries")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("tls-cert")
+                .value_name("PATH")
+                .help("Serve HTTPS using this PEM certificate chain")
+                .env("GEOIP2_TLS_CERT")
+                .long("tls-cert")
+                .global(true)
+                .requires("tls-key")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(clap::Arg::new("tls-key").value_name("PATH").help("PEM private key of --tls-cert").env("GEOIP2_TLS_KEY").long("tls-key").global(true).requires("tls-cert").value_parser(clap::value_parser!(PathBuf)))
+        .arg(
+            clap::Arg::new("tls-client-ca")
+                .value_name("PATH")
+                .help("Require clients to present a certificate signed by one of the CAs in this PEM file")
+                .env("GEOIP2_TLS_CLIENT_CA")
+                .long("tls-client-ca")
+                .global(true)
+                .requires("tls-cert")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            clap::Arg::new("acme-domain")
+                .value_name("DOMAIN")
+                .help("Serve HTTPS with certificates for these domains obtained from Let's Encrypt (requires the `acme` feature)")
+                .env("GEOIP2_ACME_DOMAIN")
+                .long("acme-domain")
+                .global(true)
+                .conflicts_with("tls-cert")
+                .action(clap::ArgAction::Append)
+                .value_delimiter(','),
+        )
+        .arg(clap::Arg::new("acme-contact").value_name("EMAIL").help("Contact email of the Let's Encrypt account").env("GEOIP2_ACME_CONTACT").long("acme-contact").global(true).requires("acme-domain"))
+        .arg(
+            clap::Arg::new("acme-cache-dir")
+                .value_name("PATH")
+                .help("Directory to store the ACME account and certificates in")
+                .env("GEOIP2_ACME_CACHE_DIR")
+                .long("acme-cache-dir")
+                .global(true)
+                .default_value("acme")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(clap::Arg::new("acme-staging").help("Use the Let's Encrypt staging environment").env("GEOIP2_ACME_STAGING").long("acme-staging").global(true).action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("shutdown-timeout")
+                .value_name("DURATION")
+                .help("How long to let in-flight requests finish after SIGTERM or SIGINT before exiting")
+                .env("GEOIP2_SHUTDOWN_TIMEOUT")
+                .long("shutdown-timeout")
+                .global(true)
+                .default_value("30s")
+                .value_parser(humantime::parse_duration),
+        )
+        .arg(clap::Arg::new("watch").help("Reload databases automatically when their files change").env("GEOIP2_WATCH").long("watch").global(true).action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("log-format")
+                .value_name("FORMAT")
+                .help("How to write logs: json lines, or pretty or compact text for reading them in a terminal")
+                .env("GEOIP2_LOG_FORMAT")
+                .long("log-format")
+                .global(true)
+                .default_value("json")
+                .value_parser(["json", "pretty", "compact"]),
+        )
+        .arg(clap::Arg::new("log-level").value_name("LEVEL").help("Log events of this level and above").env("GEOIP2_LOG_LEVEL").long("log-level").global(true).default_value("info").value_parser(clap::value_parser!(Level)))
+        .arg(clap::Arg::new("log-filter").value_name("DIRECTIVES").help("Log levels per target, in the format of RUST_LOG, e.g. info,geoip2_server=debug,hyper=warn").env("GEOIP2_LOG_FILTER").long("log-filter").global(true))
+        .arg(
+            clap::Arg::new("log-anonymize-ips")
+                .help("Zero the last octet of IPv4 and the last 80 bits of IPv6 addresses written to logs and traces")
+                .env("GEOIP2_LOG_ANONYMIZE_IPS")
+                .long("log-anonymize-ips")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(clap::Arg::new("otlp-endpoint").value_name("URL").help("Export traces to this OTLP/gRPC collector, e.g. http://localhost:4317").env("GEOIP2_OTLP_ENDPOINT").long("otlp-endpoint").global(true))
+        .arg(
+            clap::Arg::new("sentry-dsn")
+                .value_name("DSN")
+                .help("Report panics, server errors and failed reloads to this Sentry project (requires the `sentry` feature)")
+                .env("GEOIP2_SENTRY_DSN")
+                .long("sentry-dsn")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::new("statsd-host")
+                .value_name("HOST:PORT")
+                .help("Send the metrics to this DogStatsD agent, e.g. localhost:8125")
+                .env("GEOIP2_STATSD_HOST")
+                .long("statsd-host")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::new("statsd-prefix")
+                .value_name("PREFIX")
+                .help("Prefix the names of the metrics sent to StatsD with this and a dot")
+                .env("GEOIP2_STATSD_PREFIX")
+                .long("statsd-prefix")
+                .global(true)
+                .requires("statsd-host"),
+        )
+        .arg(
+            clap::Arg::new("statsd-tags")
+                .value_name("TAGS")
+                .help("Add these tags to every metric sent to StatsD, e.g. env:production,region:eu")
+                .env("GEOIP2_STATSD_TAGS")
+                .long("statsd-tags")
+                .global(true)
+                .requires("statsd-host")
+                .action(clap::ArgAction::Append)
+                .value_delimiter(','),
+        )
+        .arg(
+            clap::Arg::new("no-prometheus")
+                .help("Don't serve /metrics, for deployments sending the metrics to StatsD instead")
+                .env("GEOIP2_NO_PROMETHEUS")
+                .long("no-prometheus")
+                .global(true)
+                .requires("statsd-host")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(clap::Arg::new("worker-threads").value_name("N").help("Threads running requests, one per CPU by default").env("GEOIP2_WORKER_THREADS").long("worker-threads").global(true).value_parser(clap::value_parser!(u16).range(1..).map(usize::from)))
+        .arg(
+            clap::Arg::new("max-blocking-threads")
+                .value_name("N")
+                .help("Threads for blocking work such as reading files, 512 by default")
+                .env("GEOIP2_MAX_BLOCKING_THREADS")
+                .long("max-blocking-threads")
+                .global(true)
+                .value_parser(clap::value_parser!(u16).range(1..).map(usize::from)),
+        )
+        .arg(
+            clap::Arg::new("event-interval")
+                .value_name("TICKS")
+                .help("How many tasks a worker runs between checks for new I/O and timer events, 61 by default")
+                .env("GEOIP2_EVENT_INTERVAL")
+                .long("event-interval")
+                .global(true)
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+}
+
+/// The settings of the Tokio runtime, with the defaults filled in so the effective ones can be logged at startup.
+struct RuntimeConfig {
+    worker_threads: usize,
+    max_blocking_threads: usize,
+    event_interval: u32,
+}
+
+impl RuntimeConfig {
+    fn from_args(args: &clap::ArgMatches) -> Self {
+        RuntimeConfig {
+            worker_threads: args.get_one::<usize>("worker-threads").copied().unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get())),
+            max_blocking_threads: args.get_one::<usize>("max-blocking-threads").copied().unwrap_or(512),
+            event_interval: args.get_one::<u32>("event-interval").copied().unwrap_or(61),
+        }
+    }
+
+    fn build(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        tokio::runtime::Builder::new_multi_thread().worker_threads(self.worker_threads).max_blocking_threads(self.max_blocking_threads).event_interval(self.event_interval).enable_all().build()
+    }
+}
+
+/// The databases given with `--database`, `GEOIP2_DB` or `GEOIP2_DB_<TYPE>`.
+fn database_args(args: &clap::ArgMatches) -> anyhow::Result<Vec<DatabaseArg>> {
+    let mut db = args.get_many::<DatabaseArg>("db").unwrap_or_default().cloned().collect::<Vec<_>>();
+    db.extend(database::from_env().map_err(anyhow::Error::msg)?);
+    if db.is_empty() {
+        anyhow::bail!("No database given, pass one with --database, GEOIP2_DB or GEOIP2_DB_<TYPE>");
+    }
+
+    Ok(db)
+}
+
+/// Runs the command given on the command line, serving lookups by default.
+pub fn run() -> anyhow::Result<()> {
+    // Local development settings, never meant for deployments.
+    #[cfg(debug_assertions)]
+    let _ = dotenvy::dotenv();
+
+    let args = std::env::args_os().collect::<Vec<_>>();
+    let mut args = config::apply(cli(), &args)?.get_matches_from(args);
+    let (command, args) = args.remove_subcommand().unwrap_or_else(|| (String::from("serve"), args));
+    let runtime = RuntimeConfig::from_args(&args);
+
+    runtime.build()?.block_on(async {
+        if args.get_flag("check-config") {
+            return commands::check_config(&args);
+        }
+
+        match command.as_str() {
+            "lookup" => commands::lookup(&args).await,
+            "inspect" => commands::inspect(&args).await,
+            "enrich" => commands::enrich(&args).await,
+            "verify" => commands::verify(&args),
+            "diff" => commands::diff(&args),
+            "bench" => commands::bench(&args).await,
+            _ => serve(args, runtime).await,
+        }
+    })
+}
+
+/// Serves lookups until the process is told to shut down.
+async fn serve(args: clap::ArgMatches, runtime: RuntimeConfig) -> anyhow::Result<()> {
+    let bind = args.get_one::<String>("bind").expect("No valid bind address set!");
+    let port = args.get_one::<u16>("port").expect("No valid port set!");
+    let socket_mode = *args.get_one::<u32>("socket-mode").expect("No valid socket mode set!");
+    let reuse_port = args.get_one::<usize>("reuse-port");
+    let admin_port = args.get_one::<u16>("admin-port");
+    let grpc_port = args.get_one::<u16>("grpc-port");
+    let db = database_args(&args)?;
+    let watch = args.get_flag("watch");
+    let network = !args.get_flag("no-network");
+    let enrich_countries = args.get_flag("enrich-countries");
+    let status_ip = args.get_one::<IpAddr>("status-ip").expect("No valid status IP set!");
+    let self_test = args.get_flag("self-test");
+    let max_database_age = args.get_one::<Duration>("max-database-age").copied();
+    let in_memory = args.get_flag("in-memory");
+    let default_locales = args.get_one::<String>("default-locale").map(|locales| Locales::parse(locales)).unwrap_or_default();
+    let batch_limit = args.get_one::<usize>("batch-limit").expect("No valid batch limit set!");
+    let trusted_proxies = args.get_many::<IpNetwork>("trusted-proxies").unwrap_or_default().copied().collect::<Vec<_>>();
+    let client_filter = ClientFilter {
+        allow: args.get_many::<IpNetwork>("allow-cidr").unwrap_or_default().copied().collect(),
+        deny: args.get_many::<IpNetwork>("deny-cidr").unwrap_or_default().copied().collect(),
+    };
+    let proxy_protocol = args.get_flag("proxy-protocol");
+    let api_keys_file = args.get_one::<PathBuf>("api-keys-file");
+    let accounts_file = args.get_one::<PathBuf>("accounts-file");
+    let compression = args.get_flag("compression");
+    let docs = args.get_flag("docs");
+    let mut compat = Vec::<Compat>::new();
+    for mode in args.get_many::<Compat>("compat").unwrap_or_default().copied() {
+        if let Some(other) = compat.iter().find(|other| other.prefix() == mode.prefix() && **other != mode) {
+            anyhow::bail!("--compat {} and {} both serve {}, enable only one of them", other.name(), mode.name(), mode.prefix());
+        }
+        if !compat.contains(&mode) {
+            compat.push(mode);
+        }
+    }
+    let cache_max_age = args.get_one::<Duration>("cache-max-age").copied();
+    let cache_size = args.get_one::<u64>("cache-size").copied();
+    let cache_ttl = *args.get_one::<Duration>("cache-ttl").expect("No valid cache TTL set!");
+    let redis_url = args.get_one::<String>("redis-url");
+    let redis_ttl = *args.get_one::<Duration>("redis-ttl").expect("No valid Redis TTL set!");
+    let cors_any = args.get_flag("cors-any");
+    let cors_origins = args.get_many::<HeaderValue>("cors-origins").unwrap_or_default().cloned().collect::<Vec<_>>();
+    let cors_methods = args.get_many::<Method>("cors-methods").unwrap_or_default().cloned().collect::<Vec<_>>();
+    let cors_allow_credentials = args.get_flag("cors-allow-credentials");
+    let request_timeout = *args.get_one::<Duration>("request-timeout").expect("No valid request timeout set!");
+    let max_in_flight = args.get_one::<usize>("max-in-flight").copied();
+    let max_body_size = *args.get_one::<usize>("max-body-size").expect("No valid maximum body size set!");
+    let rate_limit = args.get_one::<Rate>("rate-limit");
+    let rate_limit_burst = args.get_one::<u32>("rate-limit-burst").copied();
+    let ip_rate_limit = args.get_one::<Rate>("ip-rate-limit");
+    let ip_rate_limit_burst = args.get_one::<u32>("ip-rate-limit-burst").copied();
+    let rate_limit_exempt = args.get_many::<IpNetwork>("rate-limit-exempt").unwrap_or_default().copied().collect::<Vec<_>>();
+    let resolve_hostnames = args.get_flag("resolve-hostnames");
+    let resolve_prefer = *args.get_one::<AddressPreference>("resolve-prefer").expect("No valid address preference set!");
+    let resolve_rate_limit = *args.get_one::<Rate>("resolve-rate-limit").expect("No valid resolution rate limit set!");
+    let reverse_dns = args.get_flag("reverse-dns");
+    let reverse_dns_timeout = *args.get_one::<Duration>("reverse-dns-timeout").expect("No valid reverse DNS timeout set!");
+    let private_response = args.get_one::<Bytes>("private-response").cloned();
+    let upstream = match args.get_one::<String>("upstream") {
+        Some(url) => {
+            let account_id = args.get_one::<String>("upstream-account-id").or(args.get_one::<String>("account-id"));
+            let license_key = args.get_one::<String>("upstream-license-key").or(args.get_one::<String>("license-key"));
+            let (Some(account_id), Some(license_key)) = (account_id, license_key) else {
+                anyhow::bail!("--upstream needs the credentials of a MaxMind account, pass them with --upstream-account-id and --upstream-license-key");
+            };
+            let budget = *args.get_one::<u32>("upstream-budget").expect("No valid upstream budget set!");
+            let ttl = *args.get_one::<Duration>("upstream-cache-ttl").expect("No valid upstream cache TTL set!");
+            Some(Arc::new(Upstream::new(url.clone(), account_id.clone(), license_key.clone(), budget, ttl)))
+        }
+        None => None,
+    };
+    let jwks_url = args.get_one::<String>("jwks-url");
+    let jwks_refresh_interval = *args.get_one::<Duration>("jwks-refresh-interval").expect("No valid JWKS refresh interval set!");
+    let real_ip_header = args.get_one::<HeaderName>("real-ip-header").cloned();
+    let account_id = args.get_one::<String>("account-id");
+    let license_key = args.get_one::<String>("license-key");
+    let update_interval = args.get_one::<Duration>("update-interval").expect("No valid update interval set!");
+    let refresh_interval = args.get_one::<Duration>("refresh-interval");
+    let tls = match (args.get_one::<PathBuf>("tls-cert"), args.get_one::<PathBuf>("tls-key")) {
+        (Some(cert), Some(key)) => Some(tls::TlsArgs { cert: cert.clone(), key: key.clone(), client_ca: args.get_one::<PathBuf>("tls-client-ca").cloned() }),
+        _ => None,
+    };
+    let acme_domains = args.get_many::<String>("acme-domain").unwrap_or_default().cloned().collect::<Vec<_>>();
+    let shutdown_timeout = *args.get_one::<Duration>("shutdown-timeout").expect("No valid shutdown timeout set!");
+    let otlp_endpoint = args.get_one::<String>("otlp-endpoint");
+    let sentry_dsn = args.get_one::<String>("sentry-dsn");
+    let statsd_host = args.get_one::<String>("statsd-host");
+    let statsd_prefix = args.get_one::<String>("statsd-prefix").cloned();
+    let statsd_tags = args.get_many::<String>("statsd-tags").unwrap_or_default().cloned().collect::<Vec<_>>();
+    let prometheus = !args.get_flag("no-prometheus");
+    let log_format = args.get_one::<String>("log-format").expect("No valid log format set!");
+    let log_level = *args.get_one::<Level>("log-level").expect("No valid log level set!");
+    let log_filter = args.get_one::<String>("log-filter");
+    if args.get_flag("log-anonymize-ips") {
+        anonymize::enable();
+    }
+
+    #[cfg(feature = "otlp")]
+    let otlp = otlp_endpoint.map(|endpoint| otlp::layer(endpoint)).transpose()?;
+    #[cfg(not(feature = "otlp"))]
+    let otlp = match otlp_endpoint {
+        Some(_) => anyhow::bail!("This build does not support --otlp-endpoint, enable the `otlp` feature"),
+        None => None::<tracing_subscriber::layer::Identity>,
+    };
+
+    // Initialized before anything is reported, and kept until the end so queued events are flushed on shutdown.
+    #[cfg(feature = "sentry")]
+    let _sentry = sentry_dsn.map(|dsn| error_reporting::init(dsn)).transpose()?;
+    #[cfg(feature = "sentry")]
+    let sentry = sentry_dsn.map(|_| error_reporting::layer());
+    #[cfg(not(feature = "sentry"))]
+    let sentry = match sentry_dsn {
+        Some(_) => anyhow::bail!("This build does not support --sentry-dsn, enable the `sentry` feature"),
+        None => None::<tracing_subscriber::layer::Identity>,
+    };
+
+    let log = match log_format.as_str() {
+        "pretty" => tracing_subscriber::fmt::layer().pretty().boxed(),
+        "compact" => tracing_subscriber::fmt::layer().compact().boxed(),
+        _ => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+    let log_filter = EnvFilter::builder().with_default_directive(tracing_subscriber::filter::LevelFilter::from_level(log_level).into()).parse(log_filter.map(String::as_str).unwrap_or_default())?;
+
+    tracing_subscriber::registry().with(log).with(otlp).with(sentry).with(log_filter).init();
+    let statsd = match statsd_host {
+        Some(host) => Some(statsd::StatsdRecorder::new(host, statsd_prefix, statsd_tags).map_err(|err| anyhow::anyhow!("Failed to connect to StatsD at {host}: {err}"))?),
+        None => None,
+    };
+    let pushes_metrics = statsd.is_some();
+    let prometheus = telemetry::install(prometheus, statsd)?;
+    info!(worker_threads = runtime.worker_threads, max_blocking_threads = runtime.max_blocking_threads, event_interval = runtime.event_interval, "started Tokio runtime");
+
+    let mut remote = remote::Remote::new(&db).await;
+    remote.fetch(&db).await?;
+
+    let updater = match (account_id, license_key) {
+        (Some(account_id), Some(license_key)) => Some(updater::Updater::new(account_id.clone(), license_key.clone())),
+        _ => None,
+    };
+
+    if let Some(updater) = &updater {
+        updater.bootstrap(&db).await?;
+    }
+
+    let databases = Databases::open(&db, in_memory)?;
+    let databases = match args.get_one::<PathBuf>("overrides") {
+        Some(path) => databases.with_overrides(Overrides::open(path.clone())?),
+        None => databases,
+    };
+    let databases = Arc::new(databases);
+    for database in databases.iter() {
+        info!("loaded {} database from {}", database.name(), database.path.display());
+    }
+    if let Some(overrides) = databases.overrides() {
+        info!("loaded overrides from {}", overrides.path.display());
+    }
+
+    let checks = self_test::run(&databases, *status_ip, network);
+    if self_test {
+        print!("{}", self_test::table(&checks, *status_ip));
+        let failed = checks.iter().filter(|check| !check.ok()).count();
+        if failed > 0 {
+            anyhow::bail!("Self-test failed for {failed} of {} database(s)", checks.len());
+        }
+        return Ok(());
+    }
+    self_test::log(&checks, *status_ip);
+
+    for kind in DatabaseKind::ALL {
+        for database in databases.chain(kind) {
+            info!("serving {} from {} ({})", kind.endpoint(), database.path.display(), database.reader().metadata.database_type);
+        }
+    }
+
+    let tls = match tls {
+        Some(args) => {
+            let config = args.config()?;
+            tokio::spawn({
+                let (args, config) = (args.clone(), config.clone());
+                async move {
+                    if let Err(err) = tls::watch(args, config).await {
+                        error!("TLS certificate watcher stopped: {err:#}");
+                    }
+                }
+            });
+            Some((args, config))
+        }
+        None => None,
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(databases.clone(), tls.clone()));
+
+    let tls = match acme_domains.is_empty() {
+        true => tls.map(|(_, config)| tls::Acceptor::Rustls(config)),
+        #[cfg(feature = "acme")]
+        false => {
+            let cache = args.get_one::<PathBuf>("acme-cache-dir").expect("No valid ACME cache directory set!").clone();
+            Some(tls::acme(&acme_domains, args.get_one::<String>("acme-contact").map(String::as_str), cache, !args.get_flag("acme-staging")))
+        }
+        #[cfg(not(feature = "acme"))]
+        false => anyhow::bail!("This build does not support --acme-domain, enable the `acme` feature"),
+    };
+
+    if let Some(refresh_interval) = refresh_interval {
+        tokio::spawn(remote.refresh(db.clone(), databases.clone(), *refresh_interval));
+    }
+
+    if let Some(updater) = updater {
+        tokio::spawn(updater.run(databases.clone(), *update_interval));
+    }
+
+    if watch {
+        let databases = databases.clone();
+        tokio::spawn(async move {
+            if let Err(err) = watch::watch(databases).await {
+                error!("database watcher stopped: {err:#}");
+            }
+        });
+    }
+
+    let client_ip = ClientIpConfig { trusted_proxies, header: real_ip_header };
+
+    let mut auth = Auth::default();
+    if let Some(api_keys_file) = api_keys_file {
+        auth.load_api_keys(api_keys_file)?;
+    }
+    if let Some(accounts_file) = accounts_file {
+        auth.load_accounts(accounts_file)?;
+    }
+    if let Some(jwks_url) = jwks_url {
+        let jwks = Arc::new(jwt::Jwks::new(jwks_url.clone(), args.get_one::<String>("jwt-issuer").cloned(), args.get_one::<String>("jwt-audience").cloned()).await?);
+        tokio::spawn(jwks.clone().run(jwks_refresh_interval));
+        auth.jwks = Some(jwks);
+    }
+
+    let rate_limiter = rate_limit.map(|rate| Arc::new(RateLimiter::new(*rate, rate_limit_burst)));
+    let ip_rate_limiter = ip_rate_limit.map(|rate| Arc::new(RateLimiter::new(*rate, ip_rate_limit_burst)));
+    for rate_limiter in rate_limiter.iter().chain(&ip_rate_limiter) {
+        tokio::spawn(rate_limiter.clone().run());
+    }
+
+    let hostnames = resolve_hostnames.then(|| {
+        let limiter = Arc::new(RateLimiter::new(resolve_rate_limit, None));
+        tokio::spawn(limiter.clone().run());
+        Arc::new(HostnameResolver::new(resolve_prefer, Some(limiter)))
+    });
+    let reverse_dns = reverse_dns.then(|| Arc::new(ReverseResolver::new(reverse_dns_timeout)));
+
+    #[cfg(feature = "redis")]
+    let redis = match redis_url {
+        Some(redis_url) => Some(Arc::new(redis_cache::SharedCache::new(redis_url, redis_ttl)?)),
+        None => None,
+    };
+    #[cfg(not(feature = "redis"))]
+    if redis_url.is_some() {
+        let _ = redis_ttl;
+        anyhow::bail!("This build does not support --redis-url, enable the `redis` feature");
+    }
+
+    let cors = match (cors_any, cors_origins.is_empty()) {
+        (true, _) => Some(CorsLayer::permissive()),
+        (false, false) => Some(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::list(cors_origins))
+                .allow_methods(cors_methods)
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static("x-api-key")])
+                .allow_credentials(cors_allow_credentials),
+        ),
+        (false, true) => None,
+    };
+
+    #[cfg(not(feature = "grpc"))]
+    if grpc_port.is_some() {
+        anyhow::bail!("This build does not support --grpc-port, enable the `grpc` feature");
+    }
+
+    let (api, admin, state) = routers(Config {
+        databases,
+        network,
+        enrich_countries,
+        batch_limit: *batch_limit,
+        default_locales,
+        client_ip,
+        status_ip: *status_ip,
+        max_database_age,
+        auth,
+        client_filter,
+        rate_limiter,
+        ip_rate_limiter,
+        rate_limit_exempt,
+        hostnames,
+        reverse_dns,
+        private_response,
+        upstream,
+        cache_max_age,
+        cache: cache_size.map(|size| RecordCache::new(size, cache_ttl)),
+        #[cfg(feature = "redis")]
+        redis,
+        compression,
+        cors,
+        request_timeout,
+        max_in_flight,
+        max_body_size,
+        prometheus,
+        docs,
+        compat,
+    });
+
+    if pushes_metrics {
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                update_database_gauges(&state);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        });
+    }
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            info!("shutting down, draining in-flight requests for up to {}...", humantime::format_duration(shutdown_timeout));
+            shutdown.cancel();
+        }
+    });
+
+    let listeners = match reuse_port {
+        Some(count) => listener::Listener::bind_reuse_port(bind, *port, *count).await?,
+        None => vec![listener::Listener::bind(bind, *port, socket_mode).await?],
+    };
+    let listener = &listeners[0];
+    if listener.is_unix() && tls.is_some() {
+        anyhow::bail!("TLS is not supported on unix sockets");
+    }
+    if listener.is_unix() && proxy_protocol {
+        anyhow::bail!("--proxy-protocol is not supported on unix sockets");
+    }
+    info!("listening on {listener}{}{}...", if tls.is_some() { " with TLS" } else { "" }, if listeners.len() > 1 { format!(" with {} accept loops", listeners.len()) } else { String::new() });
+
+    // The other ports need an address, which a unix socket doesn't give them, so they are only reachable from the host rather than from everywhere.
+    let (port_bind, loopback_only) = match listener.is_unix() {
+        true => ("127.0.0.1", " (loopback only, as --bind is a unix socket)"),
+        false => (bind.as_str(), ""),
+    };
+
+    let admin_listener = match admin_port {
+        Some(admin_port) => {
+            let admin_listener = listener::Listener::bind(port_bind, *admin_port, socket_mode).await?;
+            info!("serving admin endpoints on {admin_listener}{loopback_only}...");
+            Some(admin_listener)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "grpc")]
+    let grpc = match grpc_port {
+        Some(grpc_port) => {
+            let addr = grpc::resolve(port_bind, *grpc_port).await?;
+            info!("serving gRPC on {addr}{loopback_only}...");
+            Some(addr)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "grpc"))]
+    let _ = state;
+
+    let http = async {
+        let Some(admin_listener) = admin_listener else {
+            return listener::serve_all(listeners, request_id::propagate(json_errors(api.merge(admin))), tls.clone(), proxy_protocol, shutdown.clone()).await;
+        };
+
+        tokio::try_join!(listener::serve_all(listeners, request_id::propagate(json_errors(api)), tls.clone(), proxy_protocol, shutdown.clone()), admin_listener.serve(request_id::propagate(json_errors(admin)), tls.clone(), false, shutdown.clone()))?;
+
+        Ok(())
+    };
+
+    #[cfg(feature = "grpc")]
+    let server = async {
+        let Some(grpc) = grpc else {
+            return http.await;
+        };
+
+        tokio::try_join!(http, grpc::serve(state, grpc, shutdown.clone()))?;
+
+        Ok(())
+    };
+    #[cfg(not(feature = "grpc"))]
+    let server = http;
+
+    tokio::select! {
+        result = server => result?,
+        _ = async { shutdown.cancelled().await; tokio::time::sleep(shutdown_timeout).await } => {
+            error!("requests still in flight after {}, exiting anyway", humantime::format_duration(shutdown_timeout));
+        }
+    }
+
+    #[cfg(feature = "otlp")]
+    tokio::task::spawn_blocking(otlp::shutdown).await?;
+
+    info!("shut down");
+
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use crate::{proxy_protocol::ProxyProtocolAcceptor, telemetry, tls};
+use axum::Router;
+use axum_server::{accept::DefaultAcceptor, tls_rustls::RustlsAcceptor};
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{fmt, net::SocketAddr};
+use tokio::{net::TcpListener, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+/// A socket the server accepts connections on: a TCP port, or a unix socket when the bind address is `unix:/path`.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(unix::Socket),
+}
+
+impl Listener {
+    /// Binds `address:port`, or the unix socket at `path` with permissions `mode` if `address` is `unix:path`.
+    pub async fn bind(address: &str, port: u16, mode: u32) -> anyhow::Result<Self> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Listener::Unix(unix::Socket::bind(path.into(), mode)?));
+            #[cfg(not(unix))]
+            anyhow::bail!("Cannot bind {path}, unix sockets are not supported on this platform");
+        }
+
+        let _ = mode;
+        Ok(Listener::Tcp(TcpListener::bind(format!("{address}:{port}")).await?))
+    }
+
+    /// Binds `count` listeners to `address:port` with `SO_REUSEPORT`, so the kernel spreads incoming connections over their accept loops.
+    pub async fn bind_reuse_port(address: &str, port: u16, count: usize) -> anyhow::Result<Vec<Self>> {
+        if address.starts_with("unix:") {
+            anyhow::bail!("Cannot bind {address} with --reuse-port, it only applies to TCP ports");
+        }
+
+        let addr = tokio::net::lookup_host(format!("{address}:{port}")).await?.next().with_context(|| format!("Cannot resolve {address}"))?;
+
+        (0..count).map(|_| Ok(Listener::Tcp(reuse_port(addr).with_context(|| format!("Failed to bind {addr} with SO_REUSEPORT"))?))).collect()
+    }
+
+    pub fn is_unix(&self) -> bool {
+        !matches!(self, Listener::Tcp(_))
+    }
+
+    /// Serves `app` over TLS if configured, until `shutdown` is cancelled and every open connection is done. With `proxy_protocol`, TCP connections must start with a PROXY header, whose client address is used as the peer address.
+    pub async fn serve(self, app: Router, tls: Option<tls::Acceptor>, proxy_protocol: bool, shutdown: CancellationToken) -> std::io::Result<()> {
+        let app = app.layer(axum::middleware::from_fn(telemetry::track));
+
+        let listener = match self {
+            Listener::Tcp(listener) => listener,
+            #[cfg(unix)]
+            Listener::Unix(socket) => return socket.serve(app, shutdown).await,
+        };
+
+        if tls.is_none() && !proxy_protocol {
+            return axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).with_graceful_shutdown(shutdown.cancelled_owned()).await;
+        }
+
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown.cancelled().await;
+                handle.graceful_shutdown(None);
+            }
+        });
+
+        let server = axum_server::from_tcp(listener.into_std()?).handle(handle);
+        let connect_info = app.clone().into_make_service_with_connect_info::<SocketAddr>();
+
+        match (tls, proxy_protocol) {
+            (None, _) => server.acceptor(ProxyProtocolAcceptor::new(DefaultAcceptor::new())).serve(app.into_make_service()).await,
+            (Some(tls::Acceptor::Rustls(config)), false) => server.acceptor(RustlsAcceptor::new(config)).serve(connect_info).await,
+            (Some(tls::Acceptor::Rustls(config)), true) => server.acceptor(ProxyProtocolAcceptor::new(RustlsAcceptor::new(config))).serve(app.into_make_service()).await,
+            #[cfg(feature = "acme")]
+            (Some(tls::Acceptor::Acme(acceptor)), false) => server.acceptor(acceptor).serve(connect_info).await,
+            #[cfg(feature = "acme")]
+            (Some(tls::Acceptor::Acme(acceptor)), true) => server.acceptor(ProxyProtocolAcceptor::new(acceptor)).serve(app.into_make_service()).await,
+        }
+    }
+}
+
+fn reuse_port(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves `app` on every listener, each accept loop on a task of its own so they can run on different worker threads. Returns once all of them are done, or the first error.
+pub async fn serve_all(listeners: Vec<Listener>, app: Router, tls: Option<tls::Acceptor>, proxy_protocol: bool, shutdown: CancellationToken) -> std::io::Result<()> {
+    let mut accept_loops = JoinSet::new();
+    for listener in listeners {
+        accept_loops.spawn(listener.serve(app.clone(), tls.clone(), proxy_protocol, shutdown.clone()));
+    }
+
+    while let Some(result) = accept_loops.join_next().await {
+        result.map_err(std::io::Error::other)??;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => write!(f, "tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(socket) => write!(f, "unix:{}", socket.path.display()),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub mod unix {
+    use anyhow::Context;
+    use axum::Router;
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+    use std::{
+        os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        path::PathBuf,
+        time::Duration,
+    };
+    use tokio::net::UnixListener;
+    use tokio_util::sync::CancellationToken;
+    use tracing::{debug, error};
+
+    /// How long to wait after failing to accept a connection before trying again, like the TCP accept loop of axum.
+    const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+    /// A bound unix socket, whose file is removed again when it is dropped.
+    pub struct Socket {
+        listener: UnixListener,
+        pub path: PathBuf,
+    }
+
+    impl Socket {
+        /// Binds the socket at `path`, replacing a socket left behind by a previous run, and sets its permissions to `mode`.
+        pub fn bind(path: PathBuf, mode: u32) -> anyhow::Result<Self> {
+            match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path).with_context(|| format!("Failed to remove stale socket {}", path.display()))?,
+                Ok(_) => anyhow::bail!("Cannot bind {}, a file that is not a socket exists there", path.display()),
+                Err(_) => {}
+            }
+
+            // Bound in a directory only the server can enter, and moved into place once it has its permissions, so no one `mode` doesn't allow gets to connect in between.
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let staging = path.with_file_name(format!(".{name}.{}", std::process::id()));
+            std::fs::DirBuilder::new().mode(0o700).create(&staging).with_context(|| format!("Failed to create {}", staging.display()))?;
+            let staged = staging.join("socket");
+
+            let listener = UnixListener::bind(&staged).with_context(|| format!("Failed to bind {}", path.display())).and_then(|listener| {
+                std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode)).with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+                std::fs::rename(&staged, &path).with_context(|| format!("Failed to move socket to {}", path.display()))?;
+                Ok(listener)
+            });
+            let _ = std::fs::remove_file(&staged);
+            let _ = std::fs::remove_dir(&staging);
+
+            Ok(Socket { listener: listener?, path })
+        }
+
+        /// Accepts connections until `shutdown` is cancelled, then waits for the open ones to finish their requests.
+        pub async fn serve(self, app: Router, shutdown: CancellationToken) -> std::io::Result<()> {
+            let (close_tx, close_rx) = tokio::sync::watch::channel(());
+
+            loop {
+                let stream = tokio::select! {
+                    accepted = self.listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        // Like running out of file descriptors, which doesn't go away by retrying right away.
+                        Err(err) => {
+                            error!("failed to accept connection on {}: {err}", self.path.display());
+                            tokio::time::sleep(ACCEPT_BACKOFF).await;
+                            continue;
+                        }
+                    },
+                    _ = shutdown.cancelled() => break,
+                };
+
+                let (app, shutdown, close_rx) = (app.clone(), shutdown.clone(), close_rx.clone());
+                tokio::spawn(async move {
+                    let builder = auto::Builder::new(TokioExecutor::new());
+                    let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(app));
+                    tokio::pin!(connection);
+
+                    let result = tokio::select! {
+                        result = connection.as_mut() => result,
+                        _ = shutdown.cancelled() => {
+                            connection.as_mut().graceful_shutdown();
+                            connection.await
+                        }
+                    };
+
+                    if let Err(err) = result {
+                        debug!("connection error: {err}");
+                    }
+                    drop(close_rx);
+                });
+            }
+
+            drop(close_rx);
+            close_tx.closed().await;
+
+            Ok(())
+        }
+    }
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
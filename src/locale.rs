@@ -0,0 +1,98 @@
+use crate::AppState;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts},
+};
+use serde::Deserialize;
+use std::{convert::Infallible, sync::Arc};
+
+#[derive(Deserialize)]
+struct LocaleQuery {
+    locale: Option<String>,
+}
+
+/// The locales whose `names` a client wants, from `?locale=en,de`, then `Accept-Language`, then `--default-locale`. Empty means all names are kept.
+#[derive(Clone, Debug, Default)]
+pub struct Locales(pub Vec<String>);
+
+impl Locales {
+    /// Parses a comma-separated list of locales, adding the primary language of regional tags (`en` for `en-US`) since databases mostly name things by language only.
+    pub fn parse(list: &str) -> Self {
+        Self::from_tags(list.split(','))
+    }
+
+    fn from_tags<'a>(tags: impl Iterator<Item = &'a str>) -> Self {
+        let mut locales = Vec::new();
+
+        for tag in tags.map(str::trim).filter(|tag| !tag.is_empty() && *tag != "*") {
+            for locale in [Some(tag), tag.split_once('-').map(|(language, _)| language)].into_iter().flatten() {
+                if !locales.iter().any(|known: &String| known.eq_ignore_ascii_case(locale)) {
+                    locales.push(locale.to_owned());
+                }
+            }
+        }
+
+        Locales(locales)
+    }
+
+    /// Parses an `Accept-Language` header, ordering the locales by their quality and dropping those with `q=0`.
+    fn from_accept_language(header: &str) -> Self {
+        let mut tags = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts.find_map(|param| param.trim().strip_prefix("q=")).map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (quality > 0.0).then_some((tag, quality))
+            })
+            .collect::<Vec<_>>();
+        tags.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        Self::from_tags(tags.into_iter().map(|(tag, _)| tag))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Strips every `names` map of `record` down to the requested locales.
+    pub fn apply(&self, record: &mut serde_json::Value) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        match record {
+            serde_json::Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    match value {
+                        serde_json::Value::Object(names) if key == "names" => names.retain(|locale, _| self.0.iter().any(|wanted| wanted.eq_ignore_ascii_case(locale))),
+                        value => self.apply(value),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|value| self.apply(value)),
+            _ => {}
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Locales {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if let Ok(Query(LocaleQuery { locale: Some(locale) })) = Query::<LocaleQuery>::try_from_uri(&parts.uri) {
+            return Ok(Locales::parse(&locale));
+        }
+
+        if let Some(accept_language) = parts.headers.get(header::ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok()) {
+            let locales = Locales::from_accept_language(accept_language);
+            if !locales.0.is_empty() {
+                return Ok(locales);
+            }
+        }
+
+        Ok(state.default_locales.clone())
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{AppState, LookupError};
+use axum::{extract::State, response::Html, Json};
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// The lookup routes by path segment, with the schema of their records and what they return.
+const LOOKUPS: [(&str, &str, &str); 10] = [
+    ("city", "City", "Looks up the City record of an address, like MaxMind's GeoIP2 City web service."),
+    ("country", "Country", "Looks up the Country record of an address, from a Country database or else a City or Enterprise one."),
+    ("enterprise", "City", "Looks up the Enterprise record of an address, which has the fields of a City record and more traits."),
+    ("asn", "Asn", "Looks up the autonomous system an address belongs to."),
+    ("anonymous-ip", "AnonymousIp", "Looks up whether an address belongs to a VPN, hosting provider, Tor exit node or public proxy."),
+    ("isp", "Isp", "Looks up the ISP and organization of an address."),
+    ("domain", "Domain", "Looks up the second level domain of an address."),
+    ("connection-type", "ConnectionType", "Looks up the connection type of an address."),
+    (
+        "insights",
+        "City",
+        "Merges the City, ASN and Anonymous IP records of an address, whichever of those databases are loaded, like MaxMind's Insights web service.",
+    ),
+    ("timezone", "TimeZone", "Looks up the time zone of an address in the City database, with its current offset from UTC."),
+];
+
+fn names() -> Value {
+    json!({ "type": "object", "additionalProperties": { "type": "string" }, "description": "Names by locale, e.g. `en`, reduced to the requested locales." })
+}
+
+fn place(extra: Value) -> Value {
+    let mut place = json!({ "type": "object", "properties": { "geoname_id": { "type": "integer" }, "names": names() } });
+    if let (Some(properties), Value::Object(extra)) = (place["properties"].as_object_mut(), extra) {
+        properties.extend(extra);
+    }
+
+    place
+}
+
+fn schemas() -> Value {
+    let country = place(json!({
+        "iso_code": { "type": "string" },
+        "is_in_european_union": { "type": "boolean" },
+        "flag": { "type": "string", "example": "🇬🇧", "description": "With `--enrich-countries`." },
+        "currency_code": { "type": "string", "example": "GBP", "description": "With `--enrich-countries`." },
+        "calling_code": { "type": "string", "example": "+44", "description": "With `--enrich-countries`." },
+    }));
+    let codes = LookupError::ALL.iter().map(|err| err.body().1["code"].clone()).collect::<Vec<_>>();
+
+    json!({
+        "Error": {
+            "type": "object",
+            "required": ["code", "error"],
+            "properties": {
+                "code": { "type": "string", "enum": codes },
+                "error": { "type": "string", "description": "What went wrong, for humans." },
+                "request_id": { "type": "string", "description": "The `X-Request-Id` of the request." },
+            },
+        },
+        "Country": {
+            "type": "object",
+            "properties": {
+                "continent": place(json!({ "code": { "type": "string" }, "name": { "type": "string", "example": "Europe", "description": "The English name, with `--enrich-countries`." } })),
+                "country": country.clone(),
+                "registered_country": country,
+                "represented_country": place(json!({ "iso_code": { "type": "string" }, "type": { "type": "string" } })),
+                "traits": { "type": "object", "properties": { "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string", "example": "81.2.69.0/24" } } },
+            },
+        },
+        "City": {
+            "allOf": [
+                { "$ref": "#/components/schemas/Country" },
+                {
+                    "type": "object",
+                    "properties": {
+                        "city": place(json!({})),
+                        "location": {
+                            "type": "object",
+                            "properties": {
+                                "accuracy_radius": { "type": "integer" },
+                                "latitude": { "type": "number" },
+                                "longitude": { "type": "number" },
+                                "metro_code": { "type": "integer" },
+                                "time_zone": { "type": "string" },
+                                "utc_offset": { "type": "string", "example": "+01:00", "description": "The current offset of `time_zone` from UTC." },
+                            },
+                        },
+                        "postal": { "type": "object", "properties": { "code": { "type": "string" } } },
+                        "subdivisions": { "type": "array", "items": place(json!({ "iso_code": { "type": "string" } })) },
+                    },
+                },
+            ],
+        },
+        "Asn": {
+            "type": "object",
+            "properties": {
+                "autonomous_system_number": { "type": "integer" },
+                "autonomous_system_organization": { "type": "string" },
+                "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string" },
+            },
+        },
+        "AnonymousIp": {
+            "type": "object",
+            "properties": {
+                "is_anonymous": { "type": "boolean" },
+                "is_anonymous_vpn": { "type": "boolean" },
+                "is_hosting_provider": { "type": "boolean" },
+                "is_public_proxy": { "type": "boolean" },
+                "is_residential_proxy": { "type": "boolean" },
+                "is_tor_exit_node": { "type": "boolean" },
+                "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string" },
+            },
+        },
+        "Isp": {
+            "type": "object",
+            "properties": {
+                "autonomous_system_number": { "type": "integer" },
+                "autonomous_system_organization": { "type": "string" },
+                "isp": { "type": "string" },
+                "organization": { "type": "string" },
+                "mobile_country_code": { "type": "string" },
+                "mobile_network_code": { "type": "string" },
+                "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string" },
+            },
+        },
+        "Domain": { "type": "object", "properties": { "domain": { "type": "string" }, "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string" } } },
+        "ConnectionType": { "type": "object", "properties": { "connection_type": { "type": "string", "example": "Cable/DSL" }, "ip_address": { "type": "string", "description": "The address that was looked up." }, "network": { "type": "string" } } },
+        "TimeZone": {
+            "type": "object",
+            "properties": {
+                "time_zone": { "type": "string", "example": "Europe/London" },
+                "utc_offset": { "type": "string", "example": "+01:00" },
+                "utc_offset_seconds": { "type": "integer", "example": 3600 },
+                "is_dst": { "type": "boolean" },
+                "abbreviation": { "type": "string", "example": "BST" },
+            },
+        },
+        "Distance": {
+            "type": "object",
+            "properties": {
+                "distance_km": { "type": "number" },
+                "distance_miles": { "type": "number" },
+                "from": { "$ref": "#/components/schemas/Coordinates" },
+                "to": { "$ref": "#/components/schemas/Coordinates" },
+            },
+        },
+        "Coordinates": {
+            "type": "object",
+            "properties": {
+                "ip": { "type": "string" },
+                "latitude": { "type": "number" },
+                "longitude": { "type": "number" },
+                "accuracy_radius": { "type": "integer", "description": "How far off the coordinates may be, in kilometers." },
+            },
+        },
+        "Record": { "type": "object", "description": "A record as it is stored in the database.", "additionalProperties": true },
+    })
+}
+
+/// The error responses every route may return, by status, listing their codes.
+fn errors() -> Map<String, Value> {
+    let mut codes = BTreeMap::<u16, Vec<String>>::new();
+    for err in LookupError::ALL {
+        let (status, body) = err.body();
+        codes.entry(status.as_u16()).or_default().push(body["code"].as_str().unwrap_or_default().to_owned());
+    }
+
+    codes
+        .into_iter()
+        .map(|(status, codes)| {
+            let response = json!({ "description": codes.join(", "), "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } } });
+            (status.to_string(), response)
+        })
+        .collect()
+}
+
+fn responses(schema: &str, description: &str) -> Value {
+    let schema = json!({ "schema": { "$ref": format!("#/components/schemas/{schema}") } });
+    let mut responses = errors();
+    responses.insert(
+        String::from("200"),
+        json!({ "description": description, "content": { "application/json": schema, "application/msgpack": schema, "application/cbor": schema, "application/xml": schema, "text/csv": { "schema": { "type": "string", "description": "One row per record, with a column per dotted path, e.g. `country.iso_code`." } }, "text/plain": { "schema": { "type": "string", "description": "The bare value of `field`, or the code of the error." } } } }),
+    );
+
+    Value::Object(responses)
+}
+
+/// The parameters of a lookup route: the address in its path for single lookups, the fields, and the locales for records with names.
+fn parameters(ip: bool, locales: bool) -> Vec<Value> {
+    let mut parameters = Vec::new();
+    if ip {
+        parameters.push(json!({ "name": "ip", "in": "path", "required": true, "description": "An IPv4 or IPv6 address, `me` for the address of the caller, or a hostname with `resolve=true`.", "schema": { "type": "string" }, "example": "81.2.69.142" }));
+        parameters.push(json!({ "name": "rdns", "in": "query", "description": "Whether to add the PTR record of the address as `hostname`, if the server looks them up.", "schema": { "type": "boolean" } }));
+        parameters.push(json!({ "name": "resolve", "in": "query", "description": "Whether to look up the address a hostname in the path resolves to, if the server resolves hostnames.", "schema": { "type": "boolean" } }));
+    }
+    parameters.push(json!({ "name": "format", "in": "query", "description": "The encoding of the response, `json`, `msgpack`, `cbor`, `xml`, `csv`, `text`, `protobuf` for City, Country and ASN records, or `geojson` for City records. Takes precedence over `Accept`.", "schema": { "type": "string", "enum": ["json", "msgpack", "cbor", "xml", "csv", "text", "protobuf", "geojson"] } }));
+    parameters.push(json!({ "name": "field", "in": "query", "description": "The dotted path of the field whose bare value plain text returns, e.g. `country.iso_code`.", "schema": { "type": "string" } }));
+    parameters.push(json!({ "name": "header", "in": "query", "description": "Whether CSV starts with a row of the column names, like `Accept: text/csv; header=present`.", "schema": { "type": "boolean" } }));
+    parameters.push(json!({ "name": "fields", "in": "query", "description": "Comma-separated dotted paths of the fields to return, e.g. `country.iso_code,location`.", "schema": { "type": "string" } }));
+    if locales {
+        parameters.push(json!({ "name": "locale", "in": "query", "description": "Comma-separated locales of the names to return, e.g. `en,de`. Takes precedence over `Accept-Language`.", "schema": { "type": "string" } }));
+        parameters.push(json!({ "name": "Accept-Language", "in": "header", "description": "Locales of the names to return.", "schema": { "type": "string" } }));
+    }
+
+    parameters
+}
+
+/// An OpenAPI 3 description of the lookup routes: their parameters, records, error objects and, if the server requires credentials, how to pass them.
+pub fn document(state: &AppState) -> Value {
+    let mut paths = Map::new();
+
+    for (segment, schema, summary) in LOOKUPS {
+        let locales = matches!(schema, "City" | "Country");
+        let mut responses = responses(schema, "The record of the address.");
+        if matches!(schema, "City" | "Country" | "Asn") {
+            let message = format!("A `geoip2.v1.{schema}Record` message of `/schema.proto`.");
+            responses["200"]["content"]["application/x-protobuf"] = json!({ "schema": { "type": "string", "format": "binary", "description": message } });
+        }
+        if schema == "City" {
+            responses["200"]["content"]["application/geo+json"] = json!({ "schema": { "type": "object", "description": "A GeoJSON Feature with a Point at the location of the record, and the rest of it as properties." } });
+        }
+        paths.insert(
+            format!("/geoip/v2.1/{segment}/{{ip}}"),
+            json!({ "get": { "operationId": segment.replace('-', "_"), "summary": summary, "tags": ["lookup"], "parameters": parameters(true, locales), "responses": responses } }),
+        );
+    }
+
+    let mut raw_parameters = parameters(true, false);
+    raw_parameters.push(json!({ "name": "database", "in": "query", "description": "The database to look up, e.g. `asn`. The custom database by default.", "schema": { "type": "string" } }));
+    paths.insert(
+        String::from("/lookup/{ip}"),
+        json!({ "get": { "operationId": "raw", "summary": "Returns the record of an address in any database as it is stored.", "tags": ["lookup"], "parameters": raw_parameters, "responses": responses("Record", "The record of the address.") } }),
+    );
+
+    let mut batch = responses("City", "The City record of each address, or the error object that takes its place, in order.");
+    for content in batch["200"]["content"].as_object_mut().into_iter().flat_map(|content| content.values_mut()) {
+        content["schema"] = json!({ "type": "array", "items": { "oneOf": [{ "$ref": "#/components/schemas/City" }, { "$ref": "#/components/schemas/Error" }] } });
+    }
+    paths.insert(
+        String::from("/geoip/v2.1/city"),
+        json!({
+            "post": {
+                "operationId": "city_batch",
+                "summary": format!("Looks up the City records of up to {} addresses.", state.batch_limit),
+                "tags": ["lookup"],
+                "parameters": parameters(false, true),
+                "requestBody": { "required": true, "content": { "application/json": { "schema": { "type": "array", "items": { "type": "string" }, "maxItems": state.batch_limit } } } },
+                "responses": batch,
+            },
+        }),
+    );
+    let mut stream = responses("City", "One City record or error object per line of the request, as they are looked up.");
+    stream["200"]["content"] = json!({ "application/x-ndjson": { "schema": { "oneOf": [{ "$ref": "#/components/schemas/City" }, { "$ref": "#/components/schemas/Error" }] } } });
+    paths.insert(
+        String::from("/geoip/v2.1/city/stream"),
+        json!({
+            "post": {
+                "operationId": "city_stream",
+                "summary": "Looks up the City record of each line of the request, streaming the results back, for jobs of any size.",
+                "tags": ["lookup"],
+                "parameters": parameters(false, true).into_iter().filter(|parameter| parameter["name"] != "format").collect::<Vec<_>>(),
+                "requestBody": { "required": true, "content": { "text/plain": { "schema": { "type": "string", "example": "81.2.69.142\n2001:218::1\n" } } } },
+                "responses": stream,
+            },
+        }),
+    );
+    let mut distance_parameters = parameters(false, false);
+    for (name, example) in [("ip_a", "81.2.69.142"), ("ip_b", "2001:218::1")] {
+        distance_parameters.insert(
+            0,
+            json!({ "name": name, "in": "path", "required": true, "description": "An IPv4 or IPv6 address, or `me` for the address of the caller.", "schema": { "type": "string" }, "example": example }),
+        );
+    }
+    paths.insert(
+        String::from("/geoip/v2.1/distance/{ip_a}/{ip_b}"),
+        json!({ "get": { "operationId": "distance", "summary": "Looks up the locations of two addresses in the City database and the great-circle distance between them.", "tags": ["lookup"], "parameters": distance_parameters, "responses": responses("Distance", "The distance between the addresses.") } }),
+    );
+    paths.insert(
+        String::from("/geoip/v2.1/metadata"),
+        json!({ "get": { "operationId": "metadata", "summary": "Returns the metadata of every loaded database, keyed by type.", "tags": ["lookup"], "responses": { "200": { "description": "The metadata of the databases.", "content": { "application/json": { "schema": { "type": "object" } } } } } } }),
+    );
+
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": { "title": "geoip2-server", "version": env!("CARGO_PKG_VERSION"), "description": "Looks addresses up in MaxMind databases, with the routes and records of MaxMind's GeoIP2 web services." },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "apiKey": { "type": "apiKey", "in": "header", "name": "X-API-Key" },
+                "bearer": { "type": "http", "scheme": "bearer", "description": "An API key, or a JWT if the server verifies them." },
+                "basic": { "type": "http", "scheme": "basic", "description": "A MaxMind account ID and license key, as sent by MaxMind's client libraries." },
+            },
+        },
+    });
+    if state.auth.is_enabled() {
+        document["security"] = json!([{ "apiKey": [] }, { "bearer": [] }, { "basic": [] }]);
+    }
+
+    document
+}
+
+pub async fn json(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(document(&state))
+}
+
+/// Swagger UI for [`document`], loaded from a CDN so the server doesn't have to bundle it.
+pub async fn docs() -> Html<&'static str> {
+    Html(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>geoip2-server</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##,
+    )
+}
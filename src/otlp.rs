@@ -0,0 +1,43 @@
+use axum::http::{HeaderMap, HeaderName};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// A layer exporting spans over OTLP/gRPC to the collector at `endpoint`. Also makes the W3C `traceparent` header the propagation format, see [`set_parent`].
+pub fn layer<S: Subscriber + for<'span> LookupSpan<'span>>(endpoint: &str) -> anyhow::Result<impl Layer<S>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(trace::Config::default().with_resource(Resource::new([KeyValue::new("service.name", env!("CARGO_PKG_NAME"))])))
+        .install_batch(runtime::Tokio)?;
+    global::set_tracer_provider(provider.clone());
+
+    Ok(tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME"))))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Continues the trace of the caller, if the request has a `traceparent` header, by making its span the parent of `span`.
+pub fn set_parent(span: &tracing::Span, headers: &HeaderMap) {
+    span.set_parent(global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers))));
+}
+
+/// Exports the spans that are still buffered. Blocks until they are sent.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -0,0 +1,132 @@
+use crate::{database::DatabaseKind, LookupError};
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use ipnetwork::IpNetwork;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// An entry of the overrides file as it is written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    network: String,
+    #[serde(default)]
+    databases: Vec<String>,
+    #[serde(default)]
+    replace: bool,
+    record: Map<String, Value>,
+}
+
+/// A hand-crafted record fragment for the addresses of a network.
+struct Override {
+    network: IpNetwork,
+    /// The kinds of lookups it applies to, or all of them if empty.
+    kinds: Vec<DatabaseKind>,
+    /// Whether the fragment is the whole record, rather than being merged over the one of the database.
+    replace: bool,
+    record: Value,
+}
+
+struct Loaded {
+    /// Most specific network first, so the first one that contains an address is the one that applies to it.
+    overrides: Vec<Override>,
+    digest: [u8; 32],
+}
+
+/// The overrides of `--overrides`, which can be swapped out while requests are being served, like the readers of the databases.
+pub struct Overrides {
+    pub path: PathBuf,
+    loaded: ArcSwap<Loaded>,
+}
+
+impl Overrides {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let loaded = load(&path)?;
+
+        Ok(Overrides { path, loaded: ArcSwap::from_pointee(loaded) })
+    }
+
+    /// Rereads the file and atomically swaps its overrides in, returning how many there are. On error the current overrides stay active.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let loaded = load(&self.path)?;
+        let len = loaded.overrides.len();
+        self.loaded.store(loaded.into());
+
+        Ok(len)
+    }
+
+    /// A hash of the contents of the file, which changes the responses to lookups without changing the build epoch of any database.
+    pub fn digest(&self) -> [u8; 32] {
+        self.loaded.load().digest
+    }
+
+    /// Applies the override of the most specific network containing `ip`, if any applies to lookups of `kind`, to the result of looking it up. Addresses the database has no record for, including reserved ones like office ranges, get the fragment as their record.
+    pub(crate) fn apply(&self, kind: DatabaseKind, ip: IpAddr, record: Result<Bytes, LookupError>) -> Result<Bytes, LookupError> {
+        let loaded = self.loaded.load();
+        let Some(entry) = loaded.overrides.iter().find(|entry| entry.network.contains(ip) && (entry.kinds.is_empty() || entry.kinds.contains(&kind))) else {
+            return record;
+        };
+
+        let mut merged = match (entry.replace, record) {
+            (true, _) | (false, Err(LookupError::IpAddressNotFound | LookupError::IpAddressReserved)) => Value::Object(Map::new()),
+            (false, Ok(record)) => serde_json::from_slice(&record).expect("records are valid JSON"),
+            (false, Err(err)) => return Err(err),
+        };
+        merge(&mut merged, &entry.record);
+
+        Ok(serde_json::to_vec(&merged).expect("records serialize").into())
+    }
+}
+
+/// Reads a YAML list of overrides, e.g. `- { network: 10.20.0.0/16, record: { city: { names: { en: Berlin } } } }`.
+fn load(path: &Path) -> anyhow::Result<Loaded> {
+    let file = std::fs::read(path).with_context(|| format!("Failed to read overrides {}", path.display()))?;
+    let entries: Vec<Entry> = serde_yaml::from_slice(&file).with_context(|| format!("Failed to parse overrides {}", path.display()))?;
+
+    let mut overrides = entries
+        .into_iter()
+        .map(|entry| {
+            let network = entry.network.parse::<IpNetwork>().with_context(|| format!("Invalid network {} in overrides {}", entry.network, path.display()))?;
+            let kinds = entry
+                .databases
+                .iter()
+                .map(|kind| kind.parse::<DatabaseKind>())
+                .collect::<Result<_, _>>()
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid override of {network} in {}", path.display()))?;
+
+            Ok(Override {
+                network,
+                kinds,
+                replace: entry.replace,
+                record: Value::Object(entry.record),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    overrides.sort_by_key(|entry| Reverse(entry.network.prefix()));
+
+    Ok(Loaded { overrides, digest: Sha256::digest(&file).into() })
+}
+
+/// Merges `fragment` over `record`, object by object. A `null` in the fragment removes the field from the record.
+pub(crate) fn merge(record: &mut Value, fragment: &Value) {
+    match (record, fragment) {
+        (Value::Object(record), Value::Object(fragment)) => {
+            for (key, value) in fragment {
+                if value.is_null() {
+                    record.remove(key);
+                } else {
+                    merge(record.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (record, fragment) => *record = fragment.clone(),
+    }
+}
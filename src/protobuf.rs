@@ -0,0 +1,184 @@
+use crate::database::DatabaseKind;
+use axum::http::header;
+use bytes::Bytes;
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// `proto/geoip2.proto`, which describes the `application/x-protobuf` responses and the gRPC service. The records below are written out rather than generated from it, so builds without the `grpc` feature don't need `protoc`, and have to be kept in sync with it.
+pub const SCHEMA: &str = include_str!("../proto/geoip2.proto");
+
+/// A continent, country, subdivision or city. Fields a kind of place doesn't have are left empty.
+#[derive(Clone, PartialEq, Message)]
+pub struct Place {
+    #[prost(uint32, tag = "1")]
+    pub geoname_id: u32,
+    #[prost(string, tag = "2")]
+    pub code: String,
+    #[prost(string, tag = "3")]
+    pub iso_code: String,
+    #[prost(map = "string, string", tag = "4")]
+    pub names: HashMap<String, String>,
+    #[prost(bool, tag = "5")]
+    pub is_in_european_union: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Location {
+    #[prost(uint32, tag = "1")]
+    pub accuracy_radius: u32,
+    #[prost(double, tag = "2")]
+    pub latitude: f64,
+    #[prost(double, tag = "3")]
+    pub longitude: f64,
+    #[prost(uint32, tag = "4")]
+    pub metro_code: u32,
+    #[prost(string, tag = "5")]
+    pub time_zone: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Traits {
+    #[prost(string, tag = "1")]
+    pub network: String,
+    #[prost(bool, tag = "2")]
+    pub is_anonymous_proxy: bool,
+    #[prost(bool, tag = "3")]
+    pub is_satellite_provider: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CountryRecord {
+    #[prost(message, optional, tag = "1")]
+    pub continent: Option<Place>,
+    #[prost(message, optional, tag = "2")]
+    pub country: Option<Place>,
+    #[prost(message, optional, tag = "3")]
+    pub registered_country: Option<Place>,
+    #[prost(message, optional, tag = "4")]
+    pub represented_country: Option<Place>,
+    #[prost(message, optional, tag = "5")]
+    pub traits: Option<Traits>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CityRecord {
+    #[prost(message, optional, tag = "1")]
+    pub city: Option<Place>,
+    #[prost(message, optional, tag = "2")]
+    pub continent: Option<Place>,
+    #[prost(message, optional, tag = "3")]
+    pub country: Option<Place>,
+    #[prost(message, optional, tag = "4")]
+    pub registered_country: Option<Place>,
+    #[prost(message, optional, tag = "5")]
+    pub represented_country: Option<Place>,
+    #[prost(message, repeated, tag = "6")]
+    pub subdivisions: Vec<Place>,
+    #[prost(message, optional, tag = "7")]
+    pub location: Option<Location>,
+    #[prost(string, tag = "8")]
+    pub postal_code: String,
+    #[prost(message, optional, tag = "9")]
+    pub traits: Option<Traits>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AsnRecord {
+    #[prost(uint32, tag = "1")]
+    pub autonomous_system_number: u32,
+    #[prost(string, tag = "2")]
+    pub autonomous_system_organization: String,
+    #[prost(string, tag = "3")]
+    pub network: String,
+}
+
+fn string(record: &Value, pointer: &str) -> String {
+    record.pointer(pointer).and_then(Value::as_str).unwrap_or_default().to_owned()
+}
+
+fn uint(record: &Value, pointer: &str) -> u32 {
+    record.pointer(pointer).and_then(Value::as_u64).unwrap_or_default() as u32
+}
+
+fn flag(record: &Value, pointer: &str) -> bool {
+    record.pointer(pointer).and_then(Value::as_bool).unwrap_or_default()
+}
+
+fn place(place: &Value) -> Option<Place> {
+    let names = place["names"].as_object().map(|names| names.iter().filter_map(|(locale, name)| Some((locale.clone(), name.as_str()?.to_owned()))).collect());
+
+    place.is_object().then(|| Place {
+        geoname_id: uint(place, "/geoname_id"),
+        code: string(place, "/code"),
+        iso_code: string(place, "/iso_code"),
+        names: names.unwrap_or_default(),
+        is_in_european_union: flag(place, "/is_in_european_union"),
+    })
+}
+
+fn traits(record: &Value) -> Option<Traits> {
+    record["traits"].is_object().then(|| Traits {
+        network: string(record, "/traits/network"),
+        is_anonymous_proxy: flag(record, "/traits/is_anonymous_proxy"),
+        is_satellite_provider: flag(record, "/traits/is_satellite_provider"),
+    })
+}
+
+/// The Country record of a Country, City or Enterprise database.
+pub fn country_record(record: &Value) -> CountryRecord {
+    CountryRecord {
+        continent: place(&record["continent"]),
+        country: place(&record["country"]),
+        registered_country: place(&record["registered_country"]),
+        represented_country: place(&record["represented_country"]),
+        traits: traits(record),
+    }
+}
+
+/// The City record of a City or Enterprise database, or of `insights`.
+pub fn city_record(record: &Value) -> CityRecord {
+    let location = record["location"].is_object().then(|| Location {
+        accuracy_radius: uint(record, "/location/accuracy_radius"),
+        latitude: record.pointer("/location/latitude").and_then(Value::as_f64).unwrap_or_default(),
+        longitude: record.pointer("/location/longitude").and_then(Value::as_f64).unwrap_or_default(),
+        metro_code: uint(record, "/location/metro_code"),
+        time_zone: string(record, "/location/time_zone"),
+    });
+
+    CityRecord {
+        city: place(&record["city"]),
+        continent: place(&record["continent"]),
+        country: place(&record["country"]),
+        registered_country: place(&record["registered_country"]),
+        represented_country: place(&record["represented_country"]),
+        subdivisions: record["subdivisions"].as_array().map(|subdivisions| subdivisions.iter().filter_map(place).collect()).unwrap_or_default(),
+        location,
+        postal_code: string(record, "/postal/code"),
+        traits: traits(record),
+    }
+}
+
+pub fn asn_record(record: &Value) -> AsnRecord {
+    AsnRecord {
+        autonomous_system_number: uint(record, "/autonomous_system_number"),
+        autonomous_system_organization: string(record, "/autonomous_system_organization"),
+        network: string(record, "/network"),
+    }
+}
+
+/// Encodes a record of `kind` as its message, or returns `None` for the kinds `geoip2.proto` has no message for.
+pub fn encode(kind: DatabaseKind, record: &Value) -> Option<Bytes> {
+    let message = match kind {
+        DatabaseKind::City | DatabaseKind::Enterprise => city_record(record).encode_to_vec(),
+        DatabaseKind::Country => country_record(record).encode_to_vec(),
+        DatabaseKind::Asn => asn_record(record).encode_to_vec(),
+        _ => return None,
+    };
+
+    Some(message.into())
+}
+
+pub async fn schema() -> ([(header::HeaderName, &'static str); 1], &'static str) {
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], SCHEMA)
+}
@@ -0,0 +1,128 @@
+use axum::extract::ConnectInfo;
+use axum_server::accept::Accept;
+use futures_util::future::BoxFuture;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+use tower_http::add_extension::AddExtension;
+
+/// How long a connection may take to send its PROXY header before it is dropped.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest possible v1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid PROXY protocol header: {message}"))
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut parts = line.trim_end().split(' ').skip(1);
+
+    match parts.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unsupported protocol")),
+    }
+
+    let source = parts.next().and_then(|source| source.parse::<IpAddr>().ok()).ok_or_else(|| invalid("bad source address"))?;
+    let port = parts.nth(1).and_then(|port| port.parse::<u16>().ok()).ok_or_else(|| invalid("bad source port"))?;
+
+    Ok(Some(SocketAddr::new(source, port)))
+}
+
+fn parse_v2(command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if command >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+
+    // LOCAL connections are health checks from the proxy itself and carry no client address.
+    if command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    let port = |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let source = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).expect("length checked"));
+            Ok(Some(SocketAddr::new(source.into(), port(8))))
+        }
+        2 if addresses.len() >= 36 => {
+            let source = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).expect("length checked"));
+            Ok(Some(SocketAddr::new(source.into(), port(32))))
+        }
+        1 | 2 => Err(invalid("address block too short")),
+        _ => Ok(None),
+    }
+}
+
+/// Reads a v1 or v2 PROXY header from the start of `stream`, returning the client address it carries, or `None` for connections from the proxy itself or of unknown protocols. Nothing past the header is consumed.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0; 16];
+    stream.read_exact(&mut header[..6]).await?;
+
+    if &header[..6] == b"PROXY " {
+        let mut line = header[..6].to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+
+        return parse_v1(std::str::from_utf8(&line).map_err(|_| invalid("v1 header is not ASCII"))?);
+    }
+
+    stream.read_exact(&mut header[6..]).await?;
+    if &header[..12] != V2_SIGNATURE {
+        return Err(invalid("missing signature"));
+    }
+
+    let mut addresses = vec![0; u16::from_be_bytes([header[14], header[15]]) as usize];
+    stream.read_exact(&mut addresses).await?;
+
+    parse_v2(header[12], header[13], &addresses)
+}
+
+/// Reads the PROXY header of each connection before handing it to `inner`, and makes the client address from the header available to handlers as their `ConnectInfo`. Must be served with `into_make_service`, since the peer address of the connection is the proxy's.
+#[derive(Clone)]
+pub struct ProxyProtocolAcceptor<A> {
+    inner: A,
+}
+
+impl<A> ProxyProtocolAcceptor<A> {
+    pub fn new(inner: A) -> Self {
+        ProxyProtocolAcceptor { inner }
+    }
+}
+
+impl<A, S> Accept<TcpStream, S> for ProxyProtocolAcceptor<A>
+where
+    A: Accept<TcpStream, AddExtension<S, ConnectInfo<SocketAddr>>> + Clone + Send + Sync + 'static,
+    A::Future: Send,
+    S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let peer = stream.peer_addr()?;
+            let client = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out reading PROXY protocol header"))??;
+
+            inner.accept(stream, AddExtension::new(service, ConnectInfo(client.unwrap_or(peer)))).await
+        })
+    }
+}
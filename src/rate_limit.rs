@@ -0,0 +1,168 @@
+use crate::{auth::Subject, client_ip::ClientIp, AppState, LookupError};
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// A rate like `100/s`, `6000/m` or `100000/h`.
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    per_second: f64,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(rate: &str) -> Result<Self, Self::Err> {
+        let (count, unit) = rate.split_once('/').ok_or_else(|| format!("Invalid rate {rate}, expected e.g. 100/s"))?;
+        let count = count.trim().parse::<f64>().ok().filter(|count| *count > 0.0).ok_or_else(|| format!("Invalid request count in rate {rate}"))?;
+        let seconds = match unit.trim() {
+            "s" | "sec" | "second" => 1.0,
+            "m" | "min" | "minute" => 60.0,
+            "h" | "hour" => 3600.0,
+            unit => return Err(format!("Invalid unit {unit} in rate {rate}, expected s, m or h")),
+        };
+
+        Ok(Rate { per_second: count / seconds })
+    }
+}
+
+impl Rate {
+    pub(crate) fn per_minute(count: u32) -> Self {
+        Rate { per_second: f64::from(count) / 60.0 }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Whether the last request was rejected, so only the first rejection in a row is logged.
+    limited: bool,
+}
+
+/// A request over the limit: how long until the next one is allowed, and whether the previous one was.
+struct Rejection {
+    wait: Duration,
+    first: bool,
+}
+
+/// Token buckets per authenticated caller, or per client address for requests without one.
+pub struct RateLimiter {
+    rate: Rate,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allows `rate` requests on average, and up to `burst` at once. Without a burst, one second worth of requests (at least one) may arrive at once.
+    pub fn new(rate: Rate, burst: Option<u32>) -> Self {
+        let burst = burst.map_or(rate.per_second.max(1.0), f64::from);
+
+        RateLimiter { rate, burst, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long to wait until one is available.
+    pub(crate) fn acquire(&self, key: &str) -> Result<(), Duration> {
+        self.take(key).map_err(|rejection| rejection.wait)
+    }
+
+    fn take(&self, key: &str) -> Result<(), Rejection> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            limited: false,
+        });
+
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            return Ok(());
+        }
+
+        let first = !bucket.limited;
+        bucket.limited = true;
+
+        Err(Rejection {
+            wait: Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate.per_second),
+            first,
+        })
+    }
+
+    /// Forgets buckets that have been idle long enough to be full again, since they are no different from new ones.
+    fn evict(&self) {
+        let refill = Duration::from_secs_f64(self.burst / self.rate.per_second);
+        self.buckets.lock().expect("rate limit lock poisoned").retain(|_, bucket| bucket.updated.elapsed() < refill);
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            self.evict();
+        }
+    }
+}
+
+/// The bucket of a request: who [`auth::require`](crate::auth::require) authenticated it as, or its client address otherwise. Credentials nothing checked don't count, as a client could send new ones with every request to get a full bucket each time.
+pub(crate) fn key(subject: Option<&Subject>, client: ClientIp) -> String {
+    match (subject, client.0) {
+        (Some(Subject(subject)), _) => format!("subject:{subject}"),
+        (None, Some(ip)) => format!("ip:{ip}"),
+        (None, None) => String::from("unknown"),
+    }
+}
+
+/// A request over `limit`, the `key` or `ip` one.
+pub(crate) struct Limited {
+    limit: &'static str,
+    rejection: Rejection,
+}
+
+impl Limited {
+    /// Counts the request in `geoip_rate_limited_total`, returning the seconds until the next one is allowed, for `Retry-After`. A client going over a limit is logged once, rather than for every request it keeps sending.
+    pub(crate) fn report(&self) -> String {
+        metrics::counter!("geoip_rate_limited_total", "limit" => self.limit).increment(1);
+        if self.rejection.first {
+            warn!(limit = self.limit, "rate limit exceeded, rejecting requests for {:.1}s", self.rejection.wait.as_secs_f64());
+        }
+
+        self.rejection.wait.as_secs_f64().ceil().max(1.0).to_string()
+    }
+}
+
+/// Takes a token from the rate limit of the client address of a request, then from the one of its caller. Clients in `--rate-limit-exempt` networks are limited by neither.
+pub(crate) fn check(state: &AppState, client: ClientIp, subject: Option<&Subject>) -> Result<(), Limited> {
+    if client.0.is_some_and(|ip| state.rate_limit_exempt.iter().any(|network| network.contains(ip))) {
+        return Ok(());
+    }
+
+    if let (Some(limiter), Some(ip)) = (&state.ip_rate_limiter, client.0) {
+        limiter.take(&ip.to_string()).map_err(|rejection| Limited { limit: "ip", rejection })?;
+    }
+    if let Some(limiter) = &state.rate_limiter {
+        limiter.take(&key(subject, client)).map_err(|rejection| Limited { limit: "key", rejection })?;
+    }
+
+    Ok(())
+}
+
+/// Rejects requests over the rate limits [`check`] takes from with `429` and a `Retry-After` header.
+pub async fn limit(State(state): State<Arc<AppState>>, client: ClientIp, request: Request, next: Next) -> Response {
+    match check(&state, client, request.extensions().get()) {
+        Ok(()) => next.run(request).await,
+        Err(limited) => ([(header::RETRY_AFTER, limited.report())], LookupError::RateLimitExceeded).into_response(),
+    }
+}
@@ -0,0 +1,81 @@
+use crate::database::DatabaseKind;
+use bytes::Bytes;
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// Finds where the value of the top-level `traits` key starts in a compactly serialized record.
+fn traits_value(json: &[u8]) -> Option<usize> {
+    let (mut depth, mut in_string, mut escaped, mut string_start) = (0, false, false, 0);
+
+    for (index, &byte) in json.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                // Only keys are followed by a colon, so this cannot match a `"traits"` value.
+                b'"' if depth == 1 && &json[string_start..index] == b"traits" && json.get(index + 1) == Some(&b':') => return Some(index + 2),
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => {
+                in_string = true;
+                string_start = index + 1;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Inserts `field` as the first member of the object opening at `at - 1`.
+fn insert_field(json: &mut Vec<u8>, at: usize, field: &str) {
+    let separator = if json.get(at) == Some(&b'}') { "" } else { "," };
+    json.splice(at..at, field.bytes().chain(separator.bytes()));
+}
+
+/// Adds `field` where MaxMind's web service puts the fields of the lookup: under `traits` for City, Country and Enterprise records, creating it if need be, at the top level otherwise.
+fn add_field(kind: DatabaseKind, json: &mut Vec<u8>, field: &str) {
+    match kind {
+        DatabaseKind::City | DatabaseKind::Country | DatabaseKind::Enterprise => match traits_value(json) {
+            Some(at) if json[at] == b'{' => insert_field(json, at + 1, field),
+            Some(at) if json[at..].starts_with(b"null") => {
+                json.splice(at..at + 4, format!("{{{field}}}").into_bytes());
+            }
+            _ => insert_field(json, 1, &format!("\"traits\":{{{field}}}")),
+        },
+        _ => insert_field(json, 1, field),
+    }
+}
+
+/// Serializes a record, adding the network it was found in, e.g. `81.2.69.0/24`, where MaxMind's web service puts it.
+///
+/// The network is spliced into the serialized record, which is a lot cheaper than going through a `serde_json::Value` to add it. Records that aren't objects, which custom databases may have, are left as they are.
+pub fn encode<T: Serialize>(kind: DatabaseKind, record: &T, network: Option<String>) -> Bytes {
+    let mut json = serde_json::to_vec(record).expect("records serialize to JSON");
+
+    let Some(network) = network.filter(|_| json.first() == Some(&b'{')) else {
+        return json.into();
+    };
+    add_field(kind, &mut json, &format!("\"network\":\"{network}\""));
+
+    json.into()
+}
+
+/// Adds the address that was looked up to a serialized record as `ip_address`, like MaxMind's web service. Records are cached by network, so this is done per response rather than in [`encode`].
+pub fn with_ip(kind: DatabaseKind, record: Bytes, ip: IpAddr) -> Bytes {
+    if record.first() != Some(&b'{') {
+        return record;
+    }
+
+    let mut json = record.to_vec();
+    add_field(kind, &mut json, &format!("\"ip_address\":\"{ip}\""));
+
+    json.into()
+}
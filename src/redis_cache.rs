@@ -0,0 +1,113 @@
+use crate::database::DatabaseKind;
+use bytes::Bytes;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+use tracing::{error, warn};
+
+/// How long a Redis call may take before it counts as failed. Anything slower than this is no faster than looking the record up ourselves.
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+/// After this many failed calls in a row, Redis is skipped for `OPEN_FOR`.
+const FAILURE_THRESHOLD: u32 = 5;
+const OPEN_FOR: Duration = Duration::from_secs(30);
+
+/// Stops calling Redis for a while after repeated failures, so an outage degrades to direct lookups instead of adding a timeout to every request.
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    fn is_open(&self) -> bool {
+        let open_until = self.open_until.lock().expect("circuit breaker lock poisoned");
+
+        open_until.is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    fn success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    fn failure(&self) {
+        if self.failures.fetch_add(1, Ordering::Relaxed) + 1 >= FAILURE_THRESHOLD {
+            warn!("Redis failed {FAILURE_THRESHOLD} times in a row, skipping it for {}s", OPEN_FOR.as_secs());
+            *self.open_until.lock().expect("circuit breaker lock poisoned") = Some(Instant::now() + OPEN_FOR);
+            self.failures.store(0, Ordering::Relaxed);
+        }
+        metrics::counter!("geoip_redis_errors_total").increment(1);
+    }
+}
+
+/// Serialized records shared between replicas through Redis, keyed by database, position in its chain, build and address. Each value is the prefix length of the network of the record followed by the record.
+pub struct SharedCache {
+    client: redis::Client,
+    /// Connected on first use, so the server starts even while Redis is down.
+    connection: OnceCell<ConnectionManager>,
+    ttl: Duration,
+    breaker: CircuitBreaker,
+}
+
+impl SharedCache {
+    pub fn new(url: &str, ttl: Duration) -> anyhow::Result<Self> {
+        Ok(SharedCache { client: redis::Client::open(url)?, connection: OnceCell::new(), ttl, breaker: CircuitBreaker::default() })
+    }
+
+    fn key(kind: DatabaseKind, position: usize, build_epoch: u64, ip: IpAddr) -> String {
+        format!("geoip2:{kind}:{position}:{build_epoch}:{ip}")
+    }
+
+    async fn connection(&self) -> redis::RedisResult<ConnectionManager> {
+        self.connection.get_or_try_init(|| ConnectionManager::new(self.client.clone())).await.cloned()
+    }
+
+    /// Runs `call` unless the circuit is open, recording whether it worked.
+    async fn call<T, F: std::future::Future<Output = redis::RedisResult<T>>>(&self, call: impl FnOnce(ConnectionManager) -> F) -> Option<T> {
+        if self.breaker.is_open() {
+            return None;
+        }
+
+        let result = tokio::time::timeout(TIMEOUT, async { call(self.connection().await?).await }).await;
+        match result {
+            Ok(Ok(value)) => {
+                self.breaker.success();
+                Some(value)
+            }
+            Ok(Err(err)) => {
+                error!("Redis call failed: {err}");
+                self.breaker.failure();
+                None
+            }
+            Err(_) => {
+                self.breaker.failure();
+                None
+            }
+        }
+    }
+
+    /// Returns the cached record of `ip` and the prefix length of its network.
+    pub async fn get(&self, kind: DatabaseKind, position: usize, build_epoch: u64, ip: IpAddr) -> Option<(u8, Bytes)> {
+        let key = Self::key(kind, position, build_epoch, ip);
+        let value = self.call(|mut connection| async move { connection.get::<_, Option<Vec<u8>>>(key).await }).await.flatten().map(Bytes::from).filter(|value| !value.is_empty());
+        metrics::counter!("geoip_redis_requests_total", "database" => kind.name(), "result" => if value.is_some() { "hit" } else { "miss" }).increment(1);
+
+        value.map(|value| (value[0], value.slice(1..)))
+    }
+
+    pub async fn set(&self, kind: DatabaseKind, position: usize, build_epoch: u64, ip: IpAddr, prefix_len: u8, record: Bytes) {
+        let (key, ttl) = (Self::key(kind, position, build_epoch, ip), self.ttl.as_secs().max(1));
+        let mut value = Vec::with_capacity(record.len() + 1);
+        value.push(prefix_len);
+        value.extend_from_slice(&record);
+
+        self.call(|mut connection| async move { connection.set_ex::<_, _, ()>(key, value, ttl).await }).await;
+    }
+}
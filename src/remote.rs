@@ -0,0 +1,126 @@
+use crate::database::{self, DatabaseArg, Databases};
+use anyhow::Context;
+use bytes::Bytes;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// Fetches databases given by URL: `http://`, `https://`, and with the `s3` feature, `s3://bucket/key`.
+pub struct Remote {
+    http: reqwest::Client,
+    #[cfg(feature = "s3")]
+    s3: Option<aws_sdk_s3::Client>,
+    /// The version tag (ETag, or Last-Modified for HTTP servers without ETags) of each URL when it was last downloaded.
+    versions: BTreeMap<String, Option<String>>,
+}
+
+#[cfg(feature = "s3")]
+fn s3_location(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("s3://")?.split_once('/')
+}
+
+impl Remote {
+    pub async fn new(args: &[DatabaseArg]) -> Self {
+        #[cfg(feature = "s3")]
+        let s3 = match args.iter().any(|arg| arg.url.as_deref().is_some_and(|url| url.starts_with("s3://"))) {
+            true => Some(aws_sdk_s3::Client::new(&aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await)),
+            false => None,
+        };
+        #[cfg(not(feature = "s3"))]
+        let _ = args;
+
+        Remote {
+            http: reqwest::Client::new(),
+            #[cfg(feature = "s3")]
+            s3,
+            versions: BTreeMap::new(),
+        }
+    }
+
+    async fn version(&self, url: &str) -> anyhow::Result<Option<String>> {
+        #[cfg(feature = "s3")]
+        if let (Some(s3), Some((bucket, key))) = (&self.s3, s3_location(url)) {
+            let head = s3.head_object().bucket(bucket).key(key).send().await?;
+            return Ok(head.e_tag().map(str::to_owned));
+        }
+
+        let response = self.http.head(url).send().await?.error_for_status()?;
+        let headers = response.headers();
+        let version = headers.get(reqwest::header::ETAG).or_else(|| headers.get(reqwest::header::LAST_MODIFIED));
+
+        Ok(version.and_then(|version| version.to_str().ok()).map(str::to_owned))
+    }
+
+    async fn get(&self, url: &str) -> anyhow::Result<(Bytes, Option<String>)> {
+        if url.starts_with("s3://") {
+            #[cfg(feature = "s3")]
+            if let (Some(s3), Some((bucket, key))) = (&self.s3, s3_location(url)) {
+                let object = s3.get_object().bucket(bucket).key(key).send().await?;
+                let version = object.e_tag().map(str::to_owned);
+                return Ok((object.body.collect().await?.into_bytes(), version));
+            }
+
+            anyhow::bail!("This build does not support s3:// databases, enable the `s3` feature");
+        }
+
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        let headers = response.headers();
+        let version = headers.get(reqwest::header::ETAG).or_else(|| headers.get(reqwest::header::LAST_MODIFIED));
+        let version = version.and_then(|version| version.to_str().ok()).map(str::to_owned);
+
+        Ok((response.bytes().await?, version))
+    }
+
+    async fn download(&mut self, arg: &DatabaseArg, url: &str) -> anyhow::Result<()> {
+        info!("downloading database from {url} to {}", arg.path.display());
+        let (mmdb, version) = self.get(url).await.with_context(|| format!("Failed to download database from {url}"))?;
+
+        let path = arg.path.clone();
+        tokio::task::spawn_blocking(move || database::install(&path, &mmdb)).await?.with_context(|| format!("Failed to install database from {url}"))?;
+        self.versions.insert(url.to_owned(), version);
+
+        Ok(())
+    }
+
+    /// Downloads every database given by URL, failing if any of them cannot be fetched or is not a valid database.
+    pub async fn fetch(&mut self, args: &[DatabaseArg]) -> anyhow::Result<()> {
+        for arg in args {
+            if let Some(url) = &arg.url {
+                self.download(arg, url).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks each URL every `interval` and downloads and reloads its database when its version tag changed.
+    pub async fn refresh(mut self, args: Vec<DatabaseArg>, databases: Arc<Databases>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            for arg in &args {
+                let Some(url) = &arg.url else {
+                    continue;
+                };
+
+                let version = match self.version(url).await {
+                    Ok(version) => version,
+                    Err(err) => {
+                        error!("failed to check {url} for updates: {err:#}");
+                        continue;
+                    }
+                };
+
+                if version.is_some() && self.versions.get(url) == Some(&version) {
+                    continue;
+                }
+
+                match self.download(arg, url).await {
+                    Ok(()) => {
+                        let _ = databases.reload_matching(|database| database.path == arg.path);
+                    }
+                    Err(err) => error!("{err:#}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use axum::{extract::Request, http::HeaderName, middleware::Next, response::Response, Router};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: Option<String>;
+}
+
+/// The ID of a request, as given by the client or generated by [`propagate`].
+pub fn id(request: &Request) -> Option<&str> {
+    request.headers().get(X_REQUEST_ID)?.to_str().ok()
+}
+
+/// The ID of the request being handled, so error responses can include it without threading it through every handler.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Option::clone).ok().flatten()
+}
+
+async fn scope(request: Request, next: Next) -> Response {
+    let id = id(&request).map(str::to_owned);
+
+    REQUEST_ID.scope(id, next.run(request)).await
+}
+
+/// Gives every request an `X-Request-Id`, keeping the one it came with if any, and returns it on the response, so client reports can be matched with our logs.
+pub fn propagate(router: Router) -> Router {
+    router.layer(axum::middleware::from_fn(scope)).layer(PropagateRequestIdLayer::new(X_REQUEST_ID)).layer(SetRequestIdLayer::new(X_REQUEST_ID, MakeRequestUuid))
+}
@@ -0,0 +1,62 @@
+use bytes::Bytes;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IPv4 ranges that are not routable on the public internet, as `(network, prefix length)`.
+const RESERVED_V4: &[([u8; 4], u32)] = &[
+    ([0, 0, 0, 0], 8),       // "this" network
+    ([10, 0, 0, 0], 8),      // private, RFC 1918
+    ([100, 64, 0, 0], 10),   // shared address space (CGNAT), RFC 6598
+    ([127, 0, 0, 0], 8),     // loopback
+    ([169, 254, 0, 0], 16),  // link-local
+    ([172, 16, 0, 0], 12),   // private, RFC 1918
+    ([192, 0, 0, 0], 24),    // IETF protocol assignments
+    ([192, 0, 2, 0], 24),    // documentation (TEST-NET-1)
+    ([192, 168, 0, 0], 16),  // private, RFC 1918
+    ([198, 18, 0, 0], 15),   // benchmarking
+    ([198, 51, 100, 0], 24), // documentation (TEST-NET-2)
+    ([203, 0, 113, 0], 24),  // documentation (TEST-NET-3)
+    ([224, 0, 0, 0], 4),     // multicast
+    ([240, 0, 0, 0], 4),     // reserved for future use, and broadcast
+];
+
+/// IPv6 ranges that are not routable on the public internet, as `(network, prefix length)`.
+const RESERVED_V6: &[([u16; 8], u32)] = &[
+    ([0, 0, 0, 0, 0, 0, 0, 0], 128),                // unspecified
+    ([0, 0, 0, 0, 0, 0, 0, 1], 128),                // loopback
+    ([0x0100, 0, 0, 0, 0, 0, 0, 0], 64),            // discard-only
+    ([0x2001, 0x0db8, 0, 0, 0, 0, 0, 0], 32),       // documentation
+    ([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7),             // unique local
+    ([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10),            // link-local
+    ([0xff00, 0, 0, 0, 0, 0, 0, 0], 8),             // multicast
+];
+
+fn is_reserved_v4(ip: Ipv4Addr) -> bool {
+    let ip = u32::from(ip);
+
+    RESERVED_V4.iter().any(|&(network, len)| ip >> (32 - len) == u32::from_be_bytes(network) >> (32 - len))
+}
+
+fn is_reserved_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_reserved_v4(ip);
+    }
+
+    let ip = u128::from(ip);
+
+    RESERVED_V6.iter().any(|&(network, len)| ip >> (128 - len) == u128::from(Ipv6Addr::from(network)) >> (128 - len))
+}
+
+/// Whether `ip` belongs to a private, loopback, link-local, CGNAT, multicast, documentation or otherwise reserved range, which no database has data for.
+pub fn is_reserved(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_reserved_v4(ip),
+        IpAddr::V6(ip) => is_reserved_v6(ip),
+    }
+}
+
+/// Parses the record of `--private-response`, a JSON object such as `{"country":{"iso_code":"ZZ"}}`, serializing it the way records are.
+pub fn parse_response(response: &str) -> Result<Bytes, String> {
+    let record = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(response).map_err(|err| format!("expected a JSON object: {err}"))?;
+
+    Ok(serde_json::to_vec(&record).expect("records serialize").into())
+}
@@ -0,0 +1,110 @@
+use crate::{
+    database::{DatabaseKind, Databases},
+    decode_kind, LookupError,
+};
+use std::{fmt::Write, net::IpAddr};
+use tracing::{error, info};
+
+/// How one database fared in the self-test.
+pub struct Check {
+    database: String,
+    database_type: String,
+    route: String,
+    /// Whether the route of the database can serve its type.
+    type_ok: bool,
+    /// What looking up the test address returned, e.g. `found` or `IP_ADDRESS_NOT_FOUND`.
+    lookup: String,
+    lookup_ok: bool,
+}
+
+impl Check {
+    pub fn ok(&self) -> bool {
+        self.type_ok && self.lookup_ok
+    }
+}
+
+/// The route serving lookups in databases of `kind`.
+fn route(kind: DatabaseKind) -> String {
+    match kind {
+        DatabaseKind::Custom => String::from("/lookup/:ip"),
+        kind => format!("/geoip/v2.1/{}/:ip", kind.name()),
+    }
+}
+
+/// Checks that the route of every database can serve its type and decodes the record of `ip` in it with the type of its route. Like `/status`, custom databases may not have the address, so for them only a read error counts as a failure.
+pub fn run(databases: &Databases, ip: IpAddr, network: bool) -> Vec<Check> {
+    databases
+        .iter()
+        .map(|database| {
+            let reader = database.reader();
+            let database_type = reader.metadata.database_type.clone();
+            let (lookup, lookup_ok) = match decode_kind(database.kind, &reader, ip, network) {
+                Ok(_) => (String::from("found"), true),
+                Err(err) => {
+                    let ok = err == LookupError::IpAddressNotFound && database.kind == DatabaseKind::Custom;
+                    (err.body().1["code"].as_str().unwrap_or_default().to_owned(), ok)
+                }
+            };
+
+            Check {
+                database: database.name(),
+                type_ok: database.kind.serves(&database_type),
+                database_type,
+                route: route(database.kind),
+                lookup,
+                lookup_ok,
+            }
+        })
+        .collect()
+}
+
+/// Logs a line per database, at error level for those that failed, while starting up.
+pub fn log(checks: &[Check], ip: IpAddr) {
+    for check in checks {
+        match check.ok() {
+            true => info!(database = %check.database, database_type = %check.database_type, route = %check.route, lookup = %check.lookup, "self-test of {} passed", check.database),
+            false => error!(
+                database = %check.database,
+                database_type = %check.database_type,
+                route = %check.route,
+                lookup = %check.lookup,
+                "self-test of {} failed looking up {ip}",
+                check.database
+            ),
+        }
+    }
+}
+
+/// A table of the checks, one database per row, for `--self-test`.
+pub fn table(checks: &[Check], ip: IpAddr) -> String {
+    let lookup = format!("LOOKUP {ip}");
+    let rows = checks
+        .iter()
+        .map(|check| {
+            [
+                check.database.clone(),
+                check.database_type.clone(),
+                check.route.clone(),
+                String::from(if check.type_ok { "ok" } else { "mismatch" }),
+                check.lookup.clone(),
+                String::from(if check.ok() { "PASS" } else { "FAIL" }),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["DATABASE", "TYPE", "ROUTE", "TYPE CHECK", lookup.as_str(), "RESULT"].map(String::from);
+
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row.iter().zip(widths).map(|(column, width)| format!("{column:width$}")).collect::<Vec<_>>().join("  ");
+        let _ = writeln!(table, "{}", line.trim_end());
+    }
+
+    table
+}
@@ -0,0 +1,191 @@
+use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use std::{
+    collections::HashMap,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::warn;
+
+/// How often the metrics are sent to the agent.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The largest datagram to send, which fits in the MTU of most networks.
+const MAX_PACKET: usize = 1432;
+
+/// How many samples of a histogram go in one line, so a line always fits in a datagram.
+const SAMPLES_PER_LINE: usize = 64;
+
+/// What was counted since the last flush, and the total for [`CounterFn::absolute`].
+#[derive(Default)]
+struct StatsdCounter {
+    pending: AtomicU64,
+    total: AtomicU64,
+}
+
+impl CounterFn for StatsdCounter {
+    fn increment(&self, value: u64) {
+        self.pending.fetch_add(value, Ordering::Relaxed);
+        self.total.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.total.swap(value, Ordering::Relaxed);
+        self.pending.fetch_add(value.saturating_sub(previous), Ordering::Relaxed);
+    }
+}
+
+/// The bits of an `f64`.
+#[derive(Default)]
+struct StatsdGauge(AtomicU64);
+
+impl StatsdGauge {
+    fn update(&self, update: impl Fn(f64) -> f64) {
+        let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some(update(f64::from_bits(bits)).to_bits()));
+    }
+}
+
+impl GaugeFn for StatsdGauge {
+    fn increment(&self, value: f64) {
+        self.update(|gauge| gauge + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|gauge| gauge - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// The samples recorded since the last flush, sent as a DogStatsD distribution.
+#[derive(Default)]
+struct StatsdHistogram(Mutex<Vec<f64>>);
+
+impl HistogramFn for StatsdHistogram {
+    fn record(&self, value: f64) {
+        self.0.lock().expect("histogram lock poisoned").push(value);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: Mutex<HashMap<Key, Arc<StatsdCounter>>>,
+    gauges: Mutex<HashMap<Key, Arc<StatsdGauge>>>,
+    histograms: Mutex<HashMap<Key, Arc<StatsdHistogram>>>,
+}
+
+/// Sends the metrics to a DogStatsD agent every few seconds, with their labels as tags: counters as what they counted since the last time, gauges as their value, and histograms as distributions of their samples.
+pub struct StatsdRecorder {
+    registry: Arc<Registry>,
+}
+
+impl StatsdRecorder {
+    /// Sends the metrics to the agent at `host`, e.g. `localhost:8125`, prefixing their names with `prefix.` if given, with the `tags` of every metric, e.g. `env:production`, on top of their own.
+    pub fn new(host: &str, prefix: Option<String>, tags: Vec<String>) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(if host.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+        socket.connect(host)?;
+        socket.set_nonblocking(true)?;
+        let socket = tokio::net::UdpSocket::from_std(socket)?;
+
+        let registry = Arc::new(Registry::default());
+        let flushed = registry.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(FLUSH_INTERVAL).await;
+                for packet in packets(&flushed.lines(prefix.as_deref(), &tags)) {
+                    if let Err(err) = socket.send(packet.as_bytes()).await {
+                        warn!("failed to send metrics to StatsD: {err}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(StatsdRecorder { registry })
+    }
+}
+
+impl Registry {
+    /// The lines of everything to send, taking what the counters and histograms have collected since the last time.
+    fn lines(&self, prefix: Option<&str>, tags: &[String]) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for (key, counter) in self.counters.lock().expect("counters lock poisoned").iter() {
+            let value = counter.pending.swap(0, Ordering::Relaxed);
+            if value > 0 {
+                lines.push(format!("{}:{value}|c{}", name(prefix, key), self::tags(key, tags)));
+            }
+        }
+        for (key, gauge) in self.gauges.lock().expect("gauges lock poisoned").iter() {
+            lines.push(format!("{}:{}|g{}", name(prefix, key), f64::from_bits(gauge.0.load(Ordering::Relaxed)), self::tags(key, tags)));
+        }
+        for (key, histogram) in self.histograms.lock().expect("histograms lock poisoned").iter() {
+            let samples = std::mem::take(&mut *histogram.0.lock().expect("histogram lock poisoned"));
+            for samples in samples.chunks(SAMPLES_PER_LINE) {
+                let samples = samples.iter().map(f64::to_string).collect::<Vec<_>>().join(":");
+                lines.push(format!("{}:{samples}|d{}", name(prefix, key), self::tags(key, tags)));
+            }
+        }
+
+        lines
+    }
+}
+
+fn name(prefix: Option<&str>, key: &Key) -> String {
+    match prefix {
+        Some(prefix) => format!("{prefix}.{}", key.name()),
+        None => key.name().to_owned(),
+    }
+}
+
+/// The DogStatsD tags of a metric, e.g. `|#database:city,env:production`.
+fn tags(key: &Key, tags: &[String]) -> String {
+    let tags = key.labels().map(|label| format!("{}:{}", label.key(), label.value())).chain(tags.iter().cloned()).collect::<Vec<_>>();
+
+    match tags.is_empty() {
+        true => String::new(),
+        false => format!("|#{}", tags.join(",")),
+    }
+}
+
+/// Packs lines into as few datagrams as fit them.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::<String>::new();
+
+    for line in lines {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET => {
+                packet.push('\n');
+                packet.push_str(line);
+            }
+            _ => packets.push(line.clone()),
+        }
+    }
+
+    packets
+}
+
+impl Recorder for StatsdRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.registry.counters.lock().expect("counters lock poisoned").entry(key.clone()).or_default().clone())
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.registry.gauges.lock().expect("gauges lock poisoned").entry(key.clone()).or_default().clone())
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.registry.histograms.lock().expect("histograms lock poisoned").entry(key.clone()).or_default().clone())
+    }
+}
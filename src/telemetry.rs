@@ -0,0 +1,156 @@
+use crate::access_log;
+use crate::statsd::StatsdRecorder;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Histogram buckets in seconds, from the microseconds a lookup takes to the slowest requests worth telling apart.
+const BUCKETS: &[f64] = &[0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Installs the global recorder, of Prometheus unless `prometheus` is false, and of `statsd` if given, returning the handle used to render `/metrics`.
+pub fn install(prometheus: bool, statsd: Option<StatsdRecorder>) -> anyhow::Result<Option<PrometheusHandle>> {
+    let prometheus = match prometheus {
+        true => Some(PrometheusBuilder::new().set_buckets(BUCKETS)?.build_recorder()),
+        false => None,
+    };
+    let handle = prometheus.as_ref().map(|recorder| recorder.handle());
+
+    let installed = match (prometheus, statsd) {
+        (Some(prometheus), None) => metrics::set_global_recorder(prometheus).is_ok(),
+        (None, Some(statsd)) => metrics::set_global_recorder(statsd).is_ok(),
+        (prometheus, statsd) => {
+            let recorders = prometheus
+                .map(|recorder| Box::new(recorder) as Box<dyn Recorder + Send + Sync>)
+                .into_iter()
+                .chain(statsd.map(|recorder| Box::new(recorder) as Box<dyn Recorder + Send + Sync>));
+            metrics::set_global_recorder(Fanout(recorders.collect())).is_ok()
+        }
+    };
+    if !installed {
+        anyhow::bail!("A metrics recorder is already installed");
+    }
+
+    if let Some(upkeep) = handle.clone() {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                upkeep.run_upkeep();
+            }
+        });
+    }
+
+    Ok(handle)
+}
+
+/// Records every metric in each of its recorders, so Prometheus can be scraped while StatsD is pushed to.
+struct Fanout(Vec<Box<dyn Recorder + Send + Sync>>);
+
+struct Counters(Vec<Counter>);
+
+impl CounterFn for Counters {
+    fn increment(&self, value: u64) {
+        self.0.iter().for_each(|counter| counter.increment(value));
+    }
+
+    fn absolute(&self, value: u64) {
+        self.0.iter().for_each(|counter| counter.absolute(value));
+    }
+}
+
+struct Gauges(Vec<Gauge>);
+
+impl GaugeFn for Gauges {
+    fn increment(&self, value: f64) {
+        self.0.iter().for_each(|gauge| gauge.increment(value));
+    }
+
+    fn decrement(&self, value: f64) {
+        self.0.iter().for_each(|gauge| gauge.decrement(value));
+    }
+
+    fn set(&self, value: f64) {
+        self.0.iter().for_each(|gauge| gauge.set(value));
+    }
+}
+
+struct Histograms(Vec<Histogram>);
+
+impl HistogramFn for Histograms {
+    fn record(&self, value: f64) {
+        self.0.iter().for_each(|histogram| histogram.record(value));
+    }
+}
+
+impl Recorder for Fanout {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.0.iter().for_each(|recorder| recorder.describe_counter(key.clone(), unit, description.clone()));
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.0.iter().for_each(|recorder| recorder.describe_gauge(key.clone(), unit, description.clone()));
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.0.iter().for_each(|recorder| recorder.describe_histogram(key.clone(), unit, description.clone()));
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(Arc::new(Counters(self.0.iter().map(|recorder| recorder.register_counter(key, metadata)).collect())))
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(Arc::new(Gauges(self.0.iter().map(|recorder| recorder.register_gauge(key, metadata)).collect())))
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(Arc::new(Histograms(self.0.iter().map(|recorder| recorder.register_histogram(key, metadata)).collect())))
+    }
+}
+
+/// The type of lookup a route makes, e.g. `city` for `/geoip/v2.1/city/:ip`, or `None` for routes that don't look up a single address.
+fn lookup_type(route: &str) -> Option<&str> {
+    match route {
+        "/lookup/:ip" => Some("raw"),
+        route => route.strip_prefix("/geoip/v2.1/")?.strip_suffix("/:ip"),
+    }
+}
+
+/// Counts a lookup in `geoip_lookups_total` by its type, whether the address was found, and the country it resolved to, so the traffic mix by geography shows without the access logs.
+fn count_lookup(lookup_type: &str, response: &Response) {
+    let result = match response.status() {
+        StatusCode::OK | StatusCode::NOT_MODIFIED => "hit",
+        StatusCode::NOT_FOUND => "miss",
+        _ => "error",
+    };
+    let country = response.extensions().get::<access_log::Lookup>().and_then(|lookup| lookup.country.clone()).unwrap_or_else(|| String::from("none"));
+
+    metrics::counter!("geoip_lookups_total", "type" => lookup_type.to_owned(), "result" => result, "country" => country).increment(1);
+}
+
+/// Middleware counting requests and recording their latency per endpoint and status code.
+pub async fn track(path: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let endpoint = path.as_ref().map_or("unmatched", MatchedPath::as_str).to_owned();
+    let method = request.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    if let Some(lookup_type) = lookup_type(&endpoint) {
+        count_lookup(lookup_type, &response);
+    }
+
+    let status = response.status().as_u16().to_string();
+    metrics::histogram!("http_request_duration_seconds", "endpoint" => endpoint.clone(), "method" => method.clone()).record(start.elapsed());
+    metrics::counter!("http_requests_total", "endpoint" => endpoint, "method" => method, "status" => status).increment(1);
+
+    response
+}
@@ -0,0 +1,95 @@
+use crate::{access_log, database::DatabaseKind, lookup_shared, AppState, LookupError, LookupIp};
+use axum::{extract::State, Extension, Json};
+use bytes::Bytes;
+use chrono::{Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use maxminddb::geoip2;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// The offset a time zone has from UTC right now, with whether it is daylight saving time and its abbreviation, e.g. `BST`.
+struct CurrentOffset {
+    seconds: i32,
+    dst: bool,
+    abbreviation: String,
+}
+
+fn offset(time_zone: &str) -> Option<CurrentOffset> {
+    let tz = time_zone.parse::<Tz>().ok()?;
+    let offset = tz.offset_from_utc_datetime(&Utc::now().naive_utc());
+
+    Some(CurrentOffset {
+        seconds: offset.fix().local_minus_utc(),
+        dst: !offset.dst_offset().is_zero(),
+        abbreviation: offset.abbreviation().to_owned(),
+    })
+}
+
+/// The current offset of a time zone from UTC in seconds.
+pub fn utc_offset_seconds(time_zone: &str) -> Option<i32> {
+    offset(time_zone).map(|offset| offset.seconds)
+}
+
+/// Formats an offset like ISO 8601, e.g. `+01:00` or `-09:30`.
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Adds the current `utc_offset` of the `time_zone` of a serialized City or Enterprise record next to it, under `location`.
+///
+/// Like the network, it is spliced into the record rather than going through a `serde_json::Value`. The key can only match where it is a key, as quotes within strings are escaped.
+pub fn add_offset(record: Bytes) -> Bytes {
+    const KEY: &[u8] = b"\"time_zone\":\"";
+
+    let Some(start) = record.windows(KEY.len()).position(|window| window == KEY).map(|at| at + KEY.len()) else {
+        return record;
+    };
+    let Some(end) = record[start..].iter().position(|&byte| byte == b'"').map(|len| start + len) else {
+        return record;
+    };
+    let Some(offset) = std::str::from_utf8(&record[start..end]).ok().and_then(offset) else {
+        return record;
+    };
+
+    let mut json = record.to_vec();
+    json.splice(end + 1..end + 1, format!(",\"utc_offset\":\"{}\"", format_offset(offset.seconds)).into_bytes());
+
+    json.into()
+}
+
+/// Like [`add_offset`], for records that are already parsed.
+pub fn add_offset_value(record: &mut Value) {
+    let Some(location) = record.get_mut("location").and_then(Value::as_object_mut) else {
+        return;
+    };
+    let Some(offset) = location.get("time_zone").and_then(Value::as_str).and_then(offset) else {
+        return;
+    };
+
+    location.insert("utc_offset".to_owned(), json!(format_offset(offset.seconds)));
+}
+
+/// Looks up the time zone of an address in the City database, with its current offset from UTC, for schedulers that need nothing else.
+pub async fn lookup(State(state): State<Arc<AppState>>, LookupIp(ip): LookupIp) -> Result<(Extension<access_log::Lookup>, Json<Value>), LookupError> {
+    let readers = state.databases.readers(DatabaseKind::City).ok_or(LookupError::DatabaseNotLoaded)?;
+    let city = lookup_shared::<geoip2::City>(DatabaseKind::City, &readers, ip, &state).await?;
+    let lookup = access_log::Lookup::new(ip, &city);
+
+    let city: Value = serde_json::from_slice(&city).expect("records are valid JSON");
+    let time_zone = city.pointer("/location/time_zone").and_then(Value::as_str).ok_or(LookupError::IpAddressNotFound)?;
+    let offset = offset(time_zone).ok_or(LookupError::IpAddressNotFound)?;
+
+    Ok((
+        Extension(lookup),
+        Json(json!({
+            "time_zone": time_zone,
+            "utc_offset": format_offset(offset.seconds),
+            "utc_offset_seconds": offset.seconds,
+            "is_dst": offset.dst,
+            "abbreviation": offset.abbreviation,
+        })),
+    ))
+}
@@ -0,0 +1,141 @@
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use notify::{RecursiveMode, Watcher};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use std::{collections::BTreeSet, fs::File, io::BufReader, path::PathBuf, sync::Arc};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// How connections are accepted when serving HTTPS.
+#[derive(Clone)]
+pub enum Acceptor {
+    Rustls(RustlsConfig),
+    #[cfg(feature = "acme")]
+    Acme(rustls_acme::axum::AxumAcceptor),
+}
+
+/// Where to read the certificate chain and key from, and optionally the CA that client certificates must be signed by.
+#[derive(Clone, Debug)]
+pub struct TlsArgs {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+fn certificates(path: &PathBuf) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("Failed to open {}", path.display()))?);
+    let certificates = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>().with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    if certificates.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+
+    Ok(certificates)
+}
+
+fn private_key(path: &PathBuf) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("Failed to open {}", path.display()))?);
+    rustls_pemfile::private_key(&mut reader).with_context(|| format!("Failed to read private key from {}", path.display()))?.with_context(|| format!("No private key found in {}", path.display()))
+}
+
+impl TlsArgs {
+    /// Reads the certificate and key from disk and builds a rustls config that serves HTTP/2 and HTTP/1.1, requiring client certificates when a client CA is given.
+    pub fn server_config(&self) -> anyhow::Result<ServerConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in certificates(client_ca)? {
+                    roots.add(certificate).with_context(|| format!("Invalid client CA certificate in {}", client_ca.display()))?;
+                }
+
+                builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certificates(&self.cert)?, private_key(&self.key)?).context("Invalid TLS certificate or key")?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+
+    pub fn config(&self) -> anyhow::Result<RustlsConfig> {
+        Ok(RustlsConfig::from_config(Arc::new(self.server_config()?)))
+    }
+
+    /// Reads the certificate and key again and swaps them into `config`. Connections already established keep the old certificate, and if the new files are invalid the old certificate stays in use.
+    pub fn reload(&self, config: &RustlsConfig) -> anyhow::Result<()> {
+        match self.server_config() {
+            Ok(server_config) => {
+                config.reload_from_config(Arc::new(server_config));
+                info!("reloaded TLS certificate from {}", self.cert.display());
+                Ok(())
+            }
+            Err(err) => {
+                error!("failed to reload TLS certificate: {err:#}");
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Watches the directories containing the certificate, key and client CA, and reloads them whenever anything in those directories changes. Kubernetes updates mounted secrets by swapping a `..data` symlink rather than touching the files themselves, so any event counts.
+pub async fn watch(args: TlsArgs, config: RustlsConfig) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = tx.send(());
+        }
+    })?;
+
+    for dir in [Some(&args.cert), Some(&args.key), args.client_ca.as_ref()].into_iter().flatten().map(|path| crate::watch::parent(path)).collect::<BTreeSet<_>>() {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        info!("watching {} for TLS certificate changes", dir.display());
+    }
+
+    while rx.recv().await.is_some() {
+        let debounce = tokio::time::sleep(crate::watch::DEBOUNCE);
+        tokio::pin!(debounce);
+
+        loop {
+            tokio::select! {
+                _ = &mut debounce => break,
+                Some(()) = rx.recv() => {},
+            }
+        }
+
+        let _ = args.reload(&config);
+    }
+
+    Ok(())
+}
+
+/// Provisions and renews a certificate for `domains` from Let's Encrypt using the TLS-ALPN-01 challenge, keeping the account and certificates in `cache` so restarts don't request new ones.
+#[cfg(feature = "acme")]
+pub fn acme(domains: &[String], contact: Option<&str>, cache: PathBuf, production: bool) -> Acceptor {
+    use futures_util::StreamExt;
+
+    let mut state = rustls_acme::AcmeConfig::new(domains)
+        .contact(contact.map(|email| format!("mailto:{email}")))
+        .cache(rustls_acme::caches::DirCache::new(cache))
+        .directory_lets_encrypt(production)
+        .state();
+    let acceptor = state.axum_acceptor(state.default_rustls_config());
+
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("acme: {event:?}"),
+                Err(err) => error!("acme: {err}"),
+            }
+        }
+    });
+
+    Acceptor::Acme(acceptor)
+}
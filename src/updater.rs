@@ -0,0 +1,100 @@
+use crate::database::{self, DatabaseArg, Databases};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::{io::Read, path::Path, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+const DOWNLOAD_URL: &str = "https://download.maxmind.com/geoip/databases";
+
+/// Downloads databases from MaxMind, replacing what `geoipupdate` would otherwise do.
+pub struct Updater {
+    client: reqwest::Client,
+    account_id: String,
+    license_key: String,
+}
+
+impl Updater {
+    pub fn new(account_id: String, license_key: String) -> Self {
+        Updater { client: reqwest::Client::new(), account_id, license_key }
+    }
+
+    async fn fetch(&self, edition: &str, suffix: &str) -> anyhow::Result<bytes::Bytes> {
+        let response = self
+            .client
+            .get(format!("{DOWNLOAD_URL}/{edition}/download"))
+            .query(&[("suffix", suffix)])
+            .basic_auth(&self.account_id, Some(&self.license_key))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.bytes().await?)
+    }
+
+    /// Downloads the latest `edition`, verifies its checksum and installs it at `path`.
+    pub async fn download(&self, edition: &str, path: &Path) -> anyhow::Result<()> {
+        let checksum = self.fetch(edition, "tar.gz.sha256").await.with_context(|| format!("Failed to download checksum of {edition}"))?;
+        let archive = self.fetch(edition, "tar.gz").await.with_context(|| format!("Failed to download {edition}"))?;
+
+        let edition = edition.to_owned();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let expected = std::str::from_utf8(&checksum)?.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+            let actual = format!("{:x}", Sha256::digest(&archive));
+            if expected != actual {
+                anyhow::bail!("Checksum mismatch for {edition}: expected {expected}, got {actual}");
+            }
+
+            let mmdb = extract(&archive).with_context(|| format!("Failed to extract {edition}"))?;
+            database::install(&path, &mmdb).with_context(|| format!("Failed to install {edition}"))
+        })
+        .await?
+    }
+
+    /// Downloads every database given on the command line that does not exist yet, so the server can start from an empty volume. Since there is no metadata to read the edition from, these must be given with an explicit type and are assumed to be GeoLite2.
+    pub async fn bootstrap(&self, args: &[DatabaseArg]) -> anyhow::Result<()> {
+        for arg in args.iter().filter(|arg| !arg.path.exists()) {
+            let kind = arg.kind.with_context(|| format!("Database {} does not exist and has no type to download it as", arg.path.display()))?;
+            let edition = kind.edition().with_context(|| format!("Database {} does not exist and {kind} databases cannot be downloaded from MaxMind", arg.path.display()))?;
+
+            info!("downloading {edition} to {}", arg.path.display());
+            self.download(edition, &arg.path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads every loaded MaxMind database again each `interval` and swaps it in. The edition is taken from the `database_type` of the database currently loaded.
+    pub async fn run(self, databases: Arc<Databases>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            for database in databases.iter().filter(|database| database.kind.edition().is_some()) {
+                let edition = database.reader().metadata.database_type.clone();
+
+                info!("updating {edition} at {}", database.path.display());
+                match self.download(&edition, &database.path).await {
+                    Ok(()) => {
+                        let _ = databases.reload_matching(|other| other.path == database.path);
+                    }
+                    Err(err) => error!("failed to update {edition}: {err:#}"),
+                }
+            }
+        }
+    }
+}
+
+fn extract(archive: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.extension().is_some_and(|extension| extension == "mmdb") {
+            let mut mmdb = Vec::new();
+            entry.read_to_end(&mut mmdb)?;
+            return Ok(mmdb);
+        }
+    }
+
+    anyhow::bail!("Archive does not contain a .mmdb file")
+}
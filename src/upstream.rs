@@ -0,0 +1,110 @@
+use crate::{
+    database::DatabaseKind,
+    overrides,
+    rate_limit::{Rate, RateLimiter},
+    LookupError,
+};
+use bytes::Bytes;
+use serde_json::{Map, Value};
+use std::{net::IpAddr, time::Duration};
+use tracing::warn;
+
+/// How long to wait for MaxMind's web service before answering from the databases alone.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks MaxMind's web service for the City and Country records of addresses the databases have no record for, or no city for, within a budget of requests per minute, since each one is billed.
+pub struct Upstream {
+    client: reqwest::Client,
+    url: String,
+    account_id: String,
+    license_key: String,
+    budget: RateLimiter,
+    /// The records of the web service by endpoint and address, or `None` for addresses it doesn't have either.
+    records: moka::sync::Cache<(&'static str, IpAddr), Option<Value>>,
+}
+
+impl Upstream {
+    /// Asks the web service at `url`, e.g. `https://geoip.maxmind.com`, at most `budget` times a minute, and keeps its answers for `ttl`.
+    pub fn new(url: String, account_id: String, license_key: String, budget: u32, ttl: Duration) -> Self {
+        Upstream {
+            client: reqwest::Client::builder().timeout(TIMEOUT).build().expect("HTTP client builds"),
+            url: url.trim_end_matches('/').to_owned(),
+            account_id,
+            license_key,
+            // A whole minute of the budget can be spent at once.
+            budget: RateLimiter::new(Rate::per_minute(budget), Some(budget)),
+            records: moka::sync::Cache::builder().max_capacity(100_000).time_to_live(ttl).build(),
+        }
+    }
+
+    /// Merges the record of the web service over the result of looking `ip` up in the databases, if they have no record for it or, for City lookups, no city. Anything else, and anything the web service can't answer, is returned as it is.
+    pub(crate) async fn fill(&self, kind: DatabaseKind, ip: IpAddr, record: Result<Bytes, LookupError>) -> Result<Bytes, LookupError> {
+        let endpoint = match kind {
+            DatabaseKind::City => "city",
+            DatabaseKind::Country => "country",
+            _ => return record,
+        };
+
+        let mut merged = match &record {
+            Ok(local) => {
+                let local: Value = serde_json::from_slice(local).expect("records are valid JSON");
+                if kind != DatabaseKind::City || local.get("city").is_some() {
+                    return record;
+                }
+                local
+            }
+            Err(LookupError::IpAddressNotFound) => Value::Object(Map::new()),
+            Err(_) => return record,
+        };
+
+        let Some(remote) = self.record(endpoint, ip).await else {
+            return record;
+        };
+        overrides::merge(&mut merged, &remote);
+
+        Ok(serde_json::to_vec(&merged).expect("records serialize").into())
+    }
+
+    async fn record(&self, endpoint: &'static str, ip: IpAddr) -> Option<Value> {
+        if let Some(record) = self.records.get(&(endpoint, ip)) {
+            metrics::counter!("geoip_upstream_requests_total", "endpoint" => endpoint, "result" => "cached").increment(1);
+            return record;
+        }
+        if self.budget.acquire("upstream").is_err() {
+            metrics::counter!("geoip_upstream_requests_total", "endpoint" => endpoint, "result" => "over_budget").increment(1);
+            return None;
+        }
+
+        let (result, record) = match self.fetch(endpoint, ip).await {
+            Ok(Some(record)) => ("found", Some(record)),
+            Ok(None) => ("not_found", None),
+            Err(err) => {
+                warn!("failed to look up {} in MaxMind's {endpoint} web service: {err:#}", crate::anonymize::ip(ip));
+                metrics::counter!("geoip_upstream_requests_total", "endpoint" => endpoint, "result" => "error").increment(1);
+                return None;
+            }
+        };
+        metrics::counter!("geoip_upstream_requests_total", "endpoint" => endpoint, "result" => result).increment(1);
+        self.records.insert((endpoint, ip), record.clone());
+
+        record
+    }
+
+    /// The record of `ip`, without the fields that are about the request rather than the address, or `None` if the web service has no record for it.
+    async fn fetch(&self, endpoint: &str, ip: IpAddr) -> anyhow::Result<Option<Value>> {
+        let response = self.client.get(format!("{}/geoip/v2.1/{endpoint}/{ip}", self.url)).basic_auth(&self.account_id, Some(&self.license_key)).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let mut record = serde_json::from_slice::<Value>(&response.error_for_status()?.bytes().await?)?;
+        if let Some(record) = record.as_object_mut() {
+            record.remove("maxmind");
+        }
+        if let Some(traits) = record.get_mut("traits").and_then(Value::as_object_mut) {
+            traits.remove("ip_address");
+        }
+
+        Ok(Some(record))
+    }
+}
@@ -0,0 +1,65 @@
+use crate::database::Databases;
+use notify::{RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// How long to wait for more events after the first one before reloading. Tools like `geoipupdate` write to a temporary file and rename it over the database, which shows up as several events.
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+
+pub fn parent(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn changed_files(event: notify::Result<notify::Event>, files: &mut BTreeSet<OsString>) {
+    let Ok(event) = event else {
+        return;
+    };
+
+    if event.kind.is_create() || event.kind.is_modify() {
+        files.extend(event.paths.iter().filter_map(|path| path.file_name()).map(|name| name.to_os_string()));
+    }
+}
+
+/// Watches the directories containing the databases and reloads a database whenever its file is replaced or modified. The parent directory is watched rather than the file itself so atomic renames over the database are seen too.
+pub async fn watch(databases: Arc<Databases>) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = tx.send(event);
+    })?;
+
+    for dir in databases.iter().map(|database| parent(&database.path)).collect::<BTreeSet<_>>() {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        info!("watching {} for database changes", dir.display());
+    }
+
+    while let Some(event) = rx.recv().await {
+        let mut files = BTreeSet::new();
+        changed_files(event, &mut files);
+
+        let debounce = tokio::time::sleep(DEBOUNCE);
+        tokio::pin!(debounce);
+
+        loop {
+            tokio::select! {
+                _ = &mut debounce => break,
+                Some(event) = rx.recv() => changed_files(event, &mut files),
+            }
+        }
+
+        if !files.is_empty() {
+            let _ = databases.reload_matching(|database| database.path.file_name().is_some_and(|name| files.contains(name)));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use ipnetwork::IpNetwork;
+use serde_json::{json, Value};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Marks the start of the metadata section of a database.
+const METADATA_START: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+#[derive(Clone)]
+enum Node {
+    Empty,
+    Data(u32),
+    /// Points past the end of the file, so looking it up fails.
+    Corrupt,
+    Branch(Box<[Node; 2]>),
+}
+
+/// A search tree record once the nodes are numbered.
+enum Record {
+    Node(u32),
+    Empty,
+    Data(u32),
+    Corrupt,
+}
+
+/// Writes IPv6 databases in the MaxMind DB format with 32-bit records, IPv4 networks living under `::/96`, so the tests don't depend on fixture files.
+pub struct Writer {
+    database_type: String,
+    build_epoch: u64,
+    root: Node,
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(database_type: &str) -> Self {
+        Writer {
+            database_type: database_type.to_owned(),
+            build_epoch: 1_700_000_000,
+            root: Node::Empty,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn build_epoch(mut self, build_epoch: u64) -> Self {
+        self.build_epoch = build_epoch;
+        self
+    }
+
+    /// Adds `record` for every address of `network`, e.g. `81.2.69.0/24`.
+    pub fn insert(mut self, network: &str, record: Value) -> Self {
+        let offset = self.data.len() as u32;
+        encode(&mut self.data, &record);
+        self.insert_node(network, Node::Data(offset));
+        self
+    }
+
+    /// Adds a `network` whose record cannot be read.
+    pub fn insert_corrupt(mut self, network: &str) -> Self {
+        self.insert_node(network, Node::Corrupt);
+        self
+    }
+
+    fn insert_node(&mut self, network: &str, value: Node) {
+        let network = network.parse::<IpNetwork>().expect("valid test network");
+        let (bits, prefix) = match network {
+            IpNetwork::V4(network) => (u128::from(u32::from(network.network())), network.prefix() + 96),
+            IpNetwork::V6(network) => (u128::from(network.network()), network.prefix()),
+        };
+        let path = (0..prefix).map(|bit| (bits >> (127 - bit)) & 1 == 1).collect::<Vec<_>>();
+
+        insert(&mut self.root, &path, value);
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut nodes = Vec::new();
+        match &self.root {
+            Node::Branch(children) => number(children, &mut nodes),
+            root => number(&[root.clone(), root.clone()], &mut nodes),
+        };
+        let node_count = nodes.len() as u32;
+
+        let mut database = Vec::new();
+        for records in &nodes {
+            for record in records {
+                let value = match record {
+                    Record::Node(index) => *index,
+                    Record::Empty => node_count,
+                    Record::Data(offset) => node_count + 16 + offset,
+                    Record::Corrupt => u32::MAX,
+                };
+                database.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        database.extend_from_slice(&[0; 16]);
+        database.extend_from_slice(&self.data);
+
+        database.extend_from_slice(METADATA_START);
+        let metadata = json!({
+            "binary_format_major_version": 2,
+            "binary_format_minor_version": 0,
+            "build_epoch": self.build_epoch,
+            "database_type": self.database_type,
+            "description": { "en": "geoip2-server test database" },
+            "ip_version": 6,
+            "languages": ["de", "en", "ja"],
+            "node_count": node_count,
+            "record_size": 32,
+        });
+        encode(&mut database, &metadata);
+
+        database
+    }
+
+    /// Writes the database to a new file in the temporary directory.
+    pub fn write(&self) -> PathBuf {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!("geoip2-server-test-{}-{}.mmdb", std::process::id(), FILES.fetch_add(1, Ordering::Relaxed)));
+        std::fs::write(&path, self.build()).expect("test database written");
+        path
+    }
+}
+
+fn insert(node: &mut Node, path: &[bool], value: Node) {
+    let Some((&bit, rest)) = path.split_first() else {
+        *node = value;
+        return;
+    };
+
+    if !matches!(node, Node::Branch(_)) {
+        // The rest of a network that gets split keeps its record.
+        let inherited = std::mem::replace(node, Node::Empty);
+        *node = Node::Branch(Box::new([inherited.clone(), inherited]));
+    }
+    let Node::Branch(children) = node else { unreachable!() };
+
+    insert(&mut children[bit as usize], rest, value);
+}
+
+/// Numbers the nodes below `children` depth first, returning the number of their parent.
+fn number(children: &[Node; 2], nodes: &mut Vec<[Record; 2]>) -> u32 {
+    let index = nodes.len();
+    nodes.push([Record::Empty, Record::Empty]);
+
+    for (bit, child) in children.iter().enumerate() {
+        nodes[index][bit] = match child {
+            Node::Empty => Record::Empty,
+            Node::Data(offset) => Record::Data(*offset),
+            Node::Corrupt => Record::Corrupt,
+            Node::Branch(grandchildren) => Record::Node(number(grandchildren, nodes)),
+        };
+    }
+
+    index as u32
+}
+
+/// Writes the control byte of a field of `kind` and `size`, with the extended type and size bytes the format needs for them.
+fn control(out: &mut Vec<u8>, kind: u8, size: usize) {
+    let (kind, extended) = match kind {
+        1..=7 => (kind << 5, None),
+        _ => (0, Some(kind - 7)),
+    };
+    let (size, extra) = match size {
+        0..=28 => (size as u8, Vec::new()),
+        29..=284 => (29, vec![(size - 29) as u8]),
+        285..=65820 => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+        _ => (31, ((size - 65821) as u32).to_be_bytes()[1..].to_vec()),
+    };
+
+    out.push(kind | size);
+    out.extend(extended);
+    out.extend(extra);
+}
+
+/// Encodes JSON in the MaxMind DB data format: integers as `uint32`, or `uint64` if they don't fit, and other numbers as doubles.
+fn encode(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => panic!("the MaxMind DB format has no null"),
+        Value::Bool(value) => control(out, 14, *value as usize),
+        Value::Number(number) => match number.as_u64() {
+            Some(number) => {
+                let bytes = number.to_be_bytes();
+                let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+                let (kind, bytes) = match u32::try_from(number) {
+                    Ok(_) => (6, &bytes[zeros.max(4)..]),
+                    Err(_) => (9, &bytes[zeros..]),
+                };
+                control(out, kind, bytes.len());
+                out.extend_from_slice(bytes);
+            }
+            None => {
+                control(out, 3, 8);
+                out.extend_from_slice(&number.as_f64().expect("finite number").to_be_bytes());
+            }
+        },
+        Value::String(string) => {
+            control(out, 2, string.len());
+            out.extend_from_slice(string.as_bytes());
+        }
+        Value::Array(values) => {
+            control(out, 11, values.len());
+            values.iter().for_each(|value| encode(out, value));
+        }
+        Value::Object(map) => {
+            control(out, 7, map.len());
+            for (key, value) in map {
+                encode(out, &Value::String(key.clone()));
+                encode(out, value);
+            }
+        }
+    }
+}
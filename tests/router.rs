@@ -0,0 +1,898 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Json, Router,
+};
+use bytes::Bytes;
+use common::Writer;
+use geoip2_server::{json_errors, router, AddressPreference, ClientFilter, Compat, Config, DatabaseArg, Databases, HostnameResolver, Overrides, Rate, RateLimiter, RecordCache, Upstream};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+fn city() -> Writer {
+    Writer::new("GeoIP2-City")
+        .insert(
+            "81.2.69.0/24",
+            json!({
+                "city": { "geoname_id": 2643743, "names": { "de": "London", "en": "London", "ja": "ロンドン" } },
+                "continent": { "code": "EU", "geoname_id": 6255148, "names": { "de": "Europa", "en": "Europe" } },
+                "country": { "geoname_id": 2635167, "is_in_european_union": false, "iso_code": "GB", "names": { "de": "Vereinigtes Königreich", "en": "United Kingdom" } },
+                "location": { "accuracy_radius": 10, "latitude": 51.5142, "longitude": -0.0931, "time_zone": "Europe/London" },
+                "subdivisions": [{ "geoname_id": 6269131, "iso_code": "ENG", "names": { "en": "England" } }],
+            }),
+        )
+        .insert(
+            "2001:218::/32",
+            json!({
+                "country": { "geoname_id": 1861060, "iso_code": "JP", "names": { "en": "Japan", "ja": "日本" } },
+                "location": { "accuracy_radius": 100, "latitude": 35.68536, "longitude": 139.75309, "time_zone": "Asia/Tokyo" },
+            }),
+        )
+        .insert_corrupt("81.2.70.0/24")
+}
+
+fn asn() -> Writer {
+    Writer::new("GeoLite2-ASN").insert("81.2.69.0/24", json!({ "autonomous_system_number": 20712, "autonomous_system_organization": "Andrews & Arnold Ltd" }))
+}
+
+fn databases(writers: &[Writer]) -> Arc<Databases> {
+    let args = writers.iter().map(|writer| DatabaseArg { kind: None, path: writer.write(), url: None }).collect::<Vec<_>>();
+
+    Arc::new(Databases::open(&args, true).expect("test databases open"))
+}
+
+fn app(config: Config) -> Router {
+    json_errors(router(config))
+}
+
+fn default_app() -> Router {
+    app(Config::new(databases(&[city(), asn()])))
+}
+
+async fn send(app: Router, request: Request<Body>) -> Response {
+    app.oneshot(request).await.expect("router is infallible")
+}
+
+async fn body(response: Response) -> Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.expect("body read");
+
+    serde_json::from_slice(&body).expect("body is JSON")
+}
+
+async fn get(app: Router, uri: &str) -> (StatusCode, Value) {
+    let response = send(app, Request::get(uri).body(Body::empty()).unwrap()).await;
+
+    (response.status(), body(response).await)
+}
+
+async fn post(app: Router, uri: &str, json: Value) -> (StatusCode, Value) {
+    let request = Request::post(uri).header(header::CONTENT_TYPE, "application/json").body(Body::from(json.to_string())).unwrap();
+    let response = send(app, request).await;
+
+    (response.status(), body(response).await)
+}
+
+/// Asserts that a response is the error object of `code`, with the status MaxMind's web service uses for it.
+fn assert_error((status, body): (StatusCode, Value), expected_status: StatusCode, code: &str) {
+    assert_eq!(status, expected_status, "{body}");
+    assert_eq!(body["code"], code, "{body}");
+    assert!(body["error"].is_string(), "{body}");
+}
+
+#[tokio::test]
+async fn city_lookup() {
+    let (status, city) = get(default_app(), "/geoip/v2.1/city/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["city"]["names"]["en"], "London");
+    assert_eq!(city["country"]["iso_code"], "GB");
+    assert_eq!(city["location"]["latitude"], 51.5142);
+    assert_eq!(city["subdivisions"][0]["iso_code"], "ENG");
+    assert_eq!(city["traits"]["network"], "81.2.69.0/24");
+}
+
+#[tokio::test]
+async fn ipv6_lookup() {
+    let (status, city) = get(default_app(), "/geoip/v2.1/city/2001:218::1").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["country"]["iso_code"], "JP");
+    assert_eq!(city["traits"]["network"], "2001:218::/32");
+}
+
+#[tokio::test]
+async fn country_lookup_falls_back_to_city_database() {
+    let (status, country) = get(default_app(), "/geoip/v2.1/country/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(country["country"]["iso_code"], "GB");
+    assert!(country.get("city").is_none());
+}
+
+#[tokio::test]
+async fn asn_lookup() {
+    let (status, asn) = get(default_app(), "/geoip/v2.1/asn/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(asn["autonomous_system_number"], 20712);
+    assert_eq!(asn["network"], "81.2.69.0/24");
+}
+
+#[tokio::test]
+async fn insights_merges_databases() {
+    let (status, insights) = get(default_app(), "/geoip/v2.1/insights/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(insights["city"]["names"]["en"], "London");
+    assert_eq!(insights["traits"]["autonomous_system_organization"], "Andrews & Arnold Ltd");
+}
+
+#[tokio::test]
+async fn raw_lookup() {
+    let (status, asn) = get(default_app(), "/lookup/81.2.69.142?database=asn").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(asn["autonomous_system_number"], 20712);
+}
+
+#[tokio::test]
+async fn raw_lookup_database_invalid() {
+    assert_error(get(default_app(), "/lookup/81.2.69.142?database=cty").await, StatusCode::BAD_REQUEST, "DATABASE_INVALID");
+    // A valid type that isn't loaded is still the server's problem.
+    assert_error(get(default_app(), "/lookup/81.2.69.142?database=isp").await, StatusCode::NOT_IMPLEMENTED, "DATABASE_NOT_LOADED");
+}
+
+#[tokio::test]
+async fn metadata() {
+    let (status, metadata) = get(default_app(), "/geoip/v2.1/metadata").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(metadata["city"]["database_type"], "GeoIP2-City");
+    assert_eq!(metadata["asn"]["ip_version"], 6);
+}
+
+#[tokio::test]
+async fn locale_query() {
+    let (_, city) = get(default_app(), "/geoip/v2.1/city/81.2.69.142?locale=ja").await;
+
+    assert_eq!(city["city"]["names"], json!({ "ja": "ロンドン" }));
+    assert_eq!(city["continent"]["names"], json!({}));
+}
+
+#[tokio::test]
+async fn accept_language() {
+    let request = Request::get("/geoip/v2.1/city/81.2.69.142").header(header::ACCEPT_LANGUAGE, "de-CH, en;q=0").body(Body::empty()).unwrap();
+    let city = body(send(default_app(), request).await).await;
+
+    assert_eq!(city["country"]["names"], json!({ "de": "Vereinigtes Königreich" }));
+}
+
+#[tokio::test]
+async fn field_selection() {
+    let (_, city) = get(default_app(), "/geoip/v2.1/city/81.2.69.142?fields=country.iso_code,location.time_zone").await;
+
+    assert_eq!(city, json!({ "country": { "iso_code": "GB" }, "location": { "time_zone": "Europe/London" } }));
+}
+
+#[tokio::test]
+async fn messagepack_response() {
+    let request = Request::get("/geoip/v2.1/city/81.2.69.142").header(header::ACCEPT, "application/msgpack, application/json;q=0.5").body(Body::empty()).unwrap();
+    let response = send(default_app(), request).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/msgpack");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let city: Value = rmp_serde::from_slice(&body).expect("body is MessagePack");
+    assert_eq!(city["country"]["iso_code"], "GB");
+}
+
+#[tokio::test]
+async fn cbor_response() {
+    let response = send(default_app(), Request::get("/geoip/v2.1/asn/81.2.69.142?format=cbor").body(Body::empty()).unwrap()).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/cbor");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let asn: Value = ciborium::from_reader(&body[..]).expect("body is CBOR");
+    assert_eq!(asn["autonomous_system_number"], 20712);
+}
+
+#[tokio::test]
+async fn xml_response() {
+    let response = send(default_app(), Request::get("/geoip/v2.1/city/81.2.69.142?format=xml&locale=ja").body(Body::empty()).unwrap()).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/xml; charset=utf-8");
+    let xml = String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<response>"), "{xml}");
+    assert!(xml.contains("<city><geoname_id>2643743</geoname_id><names><ja>ロンドン</ja></names></city>"), "{xml}");
+    assert!(xml.contains("<subdivisions><item><geoname_id>6269131</geoname_id>"), "{xml}");
+}
+
+#[tokio::test]
+async fn csv_response() {
+    let request = Request::get("/geoip/v2.1/city/81.2.69.142?fields=location.time_zone,country.iso_code")
+        .header(header::ACCEPT, "text/csv; header=present")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(default_app(), request).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    let csv = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(csv, "location.time_zone,country.iso_code\nEurope/London,GB\n");
+
+    let request = Request::post("/geoip/v2.1/city?format=csv&fields=country.iso_code,code")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"["81.2.69.142", "1.1.1.1"]"#))
+        .unwrap();
+    let csv = axum::body::to_bytes(send(default_app(), request).await.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(csv, "GB,,\n,IP_ADDRESS_NOT_FOUND,The supplied IP address is not in the database.\n");
+}
+
+#[tokio::test]
+async fn text_response() {
+    let response = send(default_app(), Request::get("/geoip/v2.1/country/81.2.69.142?format=text&field=country.iso_code").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+    assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), "GB\n");
+
+    let request = Request::get("/geoip/v2.1/country/1.1.1.1?field=country.iso_code").header(header::ACCEPT, "text/plain").body(Body::empty()).unwrap();
+    let response = send(default_app(), request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), "IP_ADDRESS_NOT_FOUND\n");
+}
+
+#[tokio::test]
+async fn geojson_response() {
+    let response = send(default_app(), Request::get("/geoip/v2.1/city/81.2.69.142?format=geojson").body(Body::empty()).unwrap()).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/geo+json");
+    let feature = body(response).await;
+    assert_eq!(feature["type"], "Feature");
+    assert_eq!(feature["geometry"], json!({ "type": "Point", "coordinates": [-0.0931, 51.5142] }));
+    assert_eq!(feature["properties"]["location"]["time_zone"], "Europe/London");
+    assert!(feature["properties"]["location"].get("latitude").is_none());
+    assert_eq!(feature["properties"]["city"]["names"]["en"], "London");
+
+    assert_error(get(default_app(), "/geoip/v2.1/asn/81.2.69.142?format=geojson").await, StatusCode::BAD_REQUEST, "FORMAT_INVALID");
+}
+
+#[derive(prost::Message)]
+struct AsnRecord {
+    #[prost(uint32, tag = "1")]
+    autonomous_system_number: u32,
+    #[prost(string, tag = "2")]
+    autonomous_system_organization: String,
+    #[prost(string, tag = "3")]
+    network: String,
+}
+
+#[tokio::test]
+async fn protobuf_response() {
+    let request = Request::get("/geoip/v2.1/asn/81.2.69.142").header(header::ACCEPT, "application/x-protobuf").body(Body::empty()).unwrap();
+    let response = send(default_app(), request).await;
+
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-protobuf");
+    let message = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let asn = <AsnRecord as prost::Message>::decode(message).expect("body is an AsnRecord");
+    assert_eq!(asn.autonomous_system_number, 20712);
+    assert_eq!(asn.autonomous_system_organization, "Andrews & Arnold Ltd");
+    assert_eq!(asn.network, "81.2.69.0/24");
+
+    // Error objects have no message, and stay JSON.
+    let request = Request::get("/geoip/v2.1/asn/10.0.0.1").header(header::ACCEPT, "application/x-protobuf").body(Body::empty()).unwrap();
+    let response = send(default_app(), request).await;
+    assert_error((response.status(), body(response).await), StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED");
+    assert_error(get(default_app(), "/geoip/v2.1/isp/81.2.69.142?format=protobuf").await, StatusCode::BAD_REQUEST, "FORMAT_INVALID");
+}
+
+#[tokio::test]
+async fn protobuf_schema() {
+    let response = send(default_app(), Request::get("/schema.proto").body(Body::empty()).unwrap()).await;
+    let schema = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    assert!(std::str::from_utf8(&schema).unwrap().contains("message CityRecord {"));
+}
+
+#[tokio::test]
+async fn me_uses_forwarded_address() {
+    let request = Request::get("/geoip/v2.1/city/me").header("x-forwarded-for", "81.2.69.142").body(Body::empty()).unwrap();
+    let city = body(send(default_app(), request).await).await;
+
+    assert_eq!(city["city"]["names"]["en"], "London");
+}
+
+#[tokio::test]
+async fn batch_lookup() {
+    let (status, cities) = post(default_app(), "/geoip/v2.1/city", json!(["81.2.69.142", "1.1.1.1", "nonsense"])).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cities[0]["city"]["names"]["en"], "London");
+    assert_eq!(cities[1]["code"], "IP_ADDRESS_NOT_FOUND");
+    assert_eq!(cities[2]["code"], "IP_ADDRESS_INVALID");
+}
+
+#[tokio::test]
+async fn stream_lookup() {
+    let request = Request::post("/geoip/v2.1/city/stream").body(Body::from("81.2.69.142\n\n2001:218::1\n")).unwrap();
+    let response = send(default_app(), request).await;
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let cities = body.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()).map(|line| serde_json::from_slice::<Value>(line).unwrap()).collect::<Vec<_>>();
+
+    assert_eq!(cities.len(), 2);
+    assert_eq!(cities[0]["country"]["iso_code"], "GB");
+    assert_eq!(cities[1]["country"]["iso_code"], "JP");
+}
+
+#[tokio::test]
+async fn conditional_request() {
+    let app = default_app();
+    let response = send(app.clone(), Request::get("/geoip/v2.1/city/81.2.69.142").body(Body::empty()).unwrap()).await;
+    let etag = response.headers()[header::ETAG].clone();
+
+    let request = Request::get("/geoip/v2.1/city/81.2.69.142").header(header::IF_NONE_MATCH, etag).body(Body::empty()).unwrap();
+    assert_eq!(send(app, request).await.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn errors_carry_request_id() {
+    let request = Request::get("/geoip/v2.1/city/nonsense").header("x-request-id", "test-request").body(Body::empty()).unwrap();
+    let response = send(default_app(), request).await;
+
+    assert_eq!(response.headers()["x-request-id"], "test-request");
+    assert_eq!(body(response).await["request_id"], "test-request");
+}
+
+#[tokio::test]
+async fn health_and_status() {
+    let mut config = Config::new(databases(&[city()]));
+    config.status_ip = "81.2.69.142".parse().unwrap();
+    let app = app(config);
+
+    assert_eq!(get(app.clone(), "/healthz").await.0, StatusCode::OK);
+    let (status, body) = get(app.clone(), "/status").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["databases"]["city"]["status"], "ok");
+    assert_eq!(get(app, "/readyz").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn version() {
+    let (status, body) = get(app(Config::new(databases(&[city()]))), "/version").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["features"].is_array());
+}
+
+#[tokio::test]
+async fn stale_database_is_not_ready() {
+    let mut config = Config::new(databases(&[city().build_epoch(1_500_000_000)]));
+    config.status_ip = "81.2.69.142".parse().unwrap();
+    config.max_database_age = Some(Duration::from_secs(86400));
+    let (status, body) = get(app(config), "/readyz").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["databases"]["city"]["stale"].as_bool().unwrap());
+}
+
+#[tokio::test]
+async fn status_fails_without_status_address() {
+    let (status, body) = get(app(Config::new(databases(&[city()]))), "/status").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["databases"]["city"]["status"], "error");
+}
+
+#[tokio::test]
+async fn ip_address_invalid() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/nonsense").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_INVALID");
+}
+
+#[tokio::test]
+async fn ip_address_required() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/me").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_REQUIRED");
+}
+
+#[tokio::test]
+async fn ip_address_not_found() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/1.1.1.1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+    assert_error(get(default_app(), "/geoip/v2.1/asn/2001:218::1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+    assert_error(get(default_app(), "/geoip/v2.1/insights/1.1.1.1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn ip_address_reserved() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/10.0.0.1").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED");
+    assert_error(get(default_app(), "/geoip/v2.1/city/::1").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED");
+}
+
+#[tokio::test]
+async fn database_not_loaded() {
+    assert_error(get(default_app(), "/geoip/v2.1/isp/81.2.69.142").await, StatusCode::NOT_IMPLEMENTED, "DATABASE_NOT_LOADED");
+    assert_error(get(default_app(), "/lookup/81.2.69.142").await, StatusCode::NOT_IMPLEMENTED, "DATABASE_NOT_LOADED");
+}
+
+#[tokio::test]
+async fn database_lookup_failed() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/81.2.70.1").await, StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_LOOKUP_FAILED");
+}
+
+#[tokio::test]
+async fn database_reload_failed_and_type_mismatch() {
+    let path = city().write();
+    let databases = Arc::new(Databases::open(&[DatabaseArg { kind: None, path: path.clone(), url: None }], true).unwrap());
+    let app = app(Config::new(databases));
+
+    std::fs::write(&path, b"not a database").unwrap();
+    assert_error(post(app.clone(), "/admin/reload", json!(null)).await, StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_RELOAD_FAILED");
+    assert_eq!(get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await.0, StatusCode::OK);
+
+    std::fs::write(&path, asn().build()).unwrap();
+    assert_eq!(post(app.clone(), "/admin/reload", json!(null)).await.0, StatusCode::OK);
+    assert_error(get(app, "/geoip/v2.1/city/81.2.69.142").await, StatusCode::BAD_REQUEST, "DATABASE_TYPE_MISMATCH");
+}
+
+#[tokio::test]
+async fn batch_too_large() {
+    let mut config = Config::new(databases(&[city()]));
+    config.batch_limit = 1;
+
+    assert_error(post(app(config), "/geoip/v2.1/city", json!(["81.2.69.142", "2001:218::1"])).await, StatusCode::BAD_REQUEST, "BATCH_TOO_LARGE");
+}
+
+#[tokio::test]
+async fn authorization_invalid() {
+    let keys = std::env::temp_dir().join(format!("geoip2-server-test-{}-api-keys", std::process::id()));
+    std::fs::write(&keys, "# test keys\nsecret\n").unwrap();
+    let mut config = Config::new(databases(&[city()]));
+    config.auth.load_api_keys(&keys).unwrap();
+    let app = app(config);
+
+    assert_error(get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await, StatusCode::UNAUTHORIZED, "AUTHORIZATION_INVALID");
+    let request = Request::get("/geoip/v2.1/city/81.2.69.142").header("x-api-key", "secret").body(Body::empty()).unwrap();
+    assert_eq!(send(app.clone(), request).await.status(), StatusCode::OK);
+    // Health checks stay open.
+    assert_eq!(get(app, "/healthz").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rate_limit_exceeded() {
+    let mut config = Config::new(databases(&[city()]));
+    config.rate_limiter = Some(Arc::new(RateLimiter::new("1/h".parse::<Rate>().unwrap(), Some(1))));
+    let app = app(config);
+
+    assert_eq!(get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await.0, StatusCode::OK);
+    let response = send(app, Request::get("/geoip/v2.1/city/81.2.69.142").body(Body::empty()).unwrap()).await;
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_error((response.status(), body(response).await), StatusCode::TOO_MANY_REQUESTS, "RATE_LIMIT_EXCEEDED");
+}
+
+#[tokio::test]
+async fn rate_limit_ignores_unchecked_keys() {
+    let mut config = Config::new(databases(&[city()]));
+    config.rate_limiter = Some(Arc::new(RateLimiter::new("1/h".parse::<Rate>().unwrap(), Some(1))));
+    let app = app(config);
+    let with_key = |key: &str| Request::get("/geoip/v2.1/city/81.2.69.142").header("x-forwarded-for", "203.0.113.7").header("x-api-key", key).body(Body::empty()).unwrap();
+
+    assert_eq!(send(app.clone(), with_key("first")).await.status(), StatusCode::OK);
+    // Without --api-keys nothing checks the key, so a new one doesn't get a new bucket.
+    let response = send(app, with_key("second")).await;
+    assert_error((response.status(), body(response).await), StatusCode::TOO_MANY_REQUESTS, "RATE_LIMIT_EXCEEDED");
+}
+
+#[tokio::test]
+async fn ip_rate_limit_exempts_networks() {
+    let mut config = Config::new(databases(&[city()]));
+    config.ip_rate_limiter = Some(Arc::new(RateLimiter::new("1/h".parse::<Rate>().unwrap(), Some(1))));
+    config.rate_limit_exempt = vec!["10.0.0.0/8".parse().unwrap()];
+    let app = app(config);
+    let from = |client: &str| Request::get("/geoip/v2.1/city/81.2.69.142").header("x-forwarded-for", client).header("x-api-key", "secret").body(Body::empty()).unwrap();
+
+    assert_eq!(send(app.clone(), from("203.0.113.7")).await.status(), StatusCode::OK);
+    let response = send(app.clone(), from("203.0.113.7")).await;
+    assert_error((response.status(), body(response).await), StatusCode::TOO_MANY_REQUESTS, "RATE_LIMIT_EXCEEDED");
+    // Another client has a bucket of its own, and internal ones have none.
+    assert_eq!(send(app.clone(), from("203.0.113.8")).await.status(), StatusCode::OK);
+    for _ in 0..3 {
+        assert_eq!(send(app.clone(), from("10.1.2.3")).await.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn body_too_large() {
+    let mut config = Config::new(databases(&[city()]));
+    config.max_body_size = 64;
+    let app = app(config);
+    let ips = serde_json::json!(["81.2.69.142"; 10]);
+
+    assert_error(post(app.clone(), "/geoip/v2.1/city", ips.clone()).await, StatusCode::PAYLOAD_TOO_LARGE, "BODY_TOO_LARGE");
+    // Without a Content-Length, the body is cut off as it is read.
+    let request = Request::post("/geoip/v2.1/city")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from_stream(futures_util::stream::iter([Ok::<_, std::io::Error>(ips.to_string())])))
+        .unwrap();
+    let response = send(app.clone(), request).await;
+    assert_error((response.status(), body(response).await), StatusCode::PAYLOAD_TOO_LARGE, "BODY_TOO_LARGE");
+    assert_eq!(post(app, "/geoip/v2.1/city", serde_json::json!(["81.2.69.142"])).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn client_filter() {
+    let mut config = Config::new(databases(&[city()]));
+    config.client_filter = ClientFilter {
+        allow: vec!["10.0.0.0/8".parse().unwrap()],
+        deny: vec!["10.66.0.0/16".parse().unwrap()],
+    };
+    let app = app(config);
+    let from = |client: &str| Request::get("/healthz").header("x-forwarded-for", client).body(Body::empty()).unwrap();
+
+    assert_eq!(send(app.clone(), from("10.1.2.3")).await.status(), StatusCode::OK);
+    for client in ["203.0.113.7", "10.66.1.1"] {
+        let response = send(app.clone(), from(client)).await;
+        assert_error((response.status(), body(response).await), StatusCode::FORBIDDEN, "CLIENT_NOT_ALLOWED");
+    }
+    // Clients whose address isn't known aren't on the allowlist either.
+    assert_error(get(app, "/geoip/v2.1/city/81.2.69.142").await, StatusCode::FORBIDDEN, "CLIENT_NOT_ALLOWED");
+}
+
+#[tokio::test]
+async fn server_overloaded() {
+    let mut config = Config::new(databases(&[city()]));
+    config.max_in_flight = Some(0);
+
+    assert_error(get(app(config), "/geoip/v2.1/city/81.2.69.142").await, StatusCode::SERVICE_UNAVAILABLE, "SERVER_OVERLOADED");
+}
+
+#[tokio::test]
+async fn request_timeout() {
+    let mut config = Config::new(databases(&[city()]));
+    config.request_timeout = Duration::from_millis(50);
+    let stalled = Body::from_stream(futures_util::stream::pending::<Result<Bytes, std::io::Error>>());
+    let request = Request::post("/geoip/v2.1/city").header(header::CONTENT_TYPE, "application/json").body(stalled).unwrap();
+    let response = send(app(config), request).await;
+
+    assert_error((response.status(), body(response).await), StatusCode::GATEWAY_TIMEOUT, "REQUEST_TIMEOUT");
+}
+
+#[tokio::test]
+async fn route_not_found() {
+    assert_error(get(default_app(), "/geoip/v2.1/nonsense/81.2.69.142").await, StatusCode::NOT_FOUND, "ROUTE_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn method_not_allowed() {
+    let response = send(default_app(), Request::builder().method(Method::DELETE).uri("/geoip/v2.1/city/81.2.69.142").body(Body::empty()).unwrap()).await;
+
+    assert!(response.headers()[header::ALLOW].to_str().unwrap().contains("GET"));
+    assert_error((response.status(), body(response).await), StatusCode::METHOD_NOT_ALLOWED, "METHOD_NOT_ALLOWED");
+}
+
+#[tokio::test]
+async fn format_invalid() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/81.2.69.142?format=yaml").await, StatusCode::BAD_REQUEST, "FORMAT_INVALID");
+}
+
+#[tokio::test]
+async fn openapi_document() {
+    let (status, document) = get(default_app(), "/openapi.json").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(document["paths"]["/geoip/v2.1/city/{ip}"]["get"]["operationId"], "city");
+    assert!(document["components"]["schemas"]["Error"]["properties"]["code"]["enum"].as_array().unwrap().contains(&json!("IP_ADDRESS_NOT_FOUND")));
+    assert!(document.get("security").is_none());
+}
+
+#[tokio::test]
+async fn swagger_ui() {
+    assert_error(get(default_app(), "/docs").await, StatusCode::NOT_FOUND, "ROUTE_NOT_FOUND");
+
+    let mut config = Config::new(databases(&[city()]));
+    config.docs = true;
+    let response = send(app(config), Request::get("/docs").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+}
+
+#[tokio::test]
+async fn graphql_lookup() {
+    let query = r#"{ lookup(ip: "81.2.69.142", locales: ["de"]) { city { city { names { locale name } } country { isoCode } } asn { autonomousSystemNumber } } }"#;
+    let (status, response) = post(default_app(), "/graphql", json!({ "query": query })).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let lookup = &response["data"]["lookup"];
+    assert_eq!(lookup["city"]["city"]["names"], json!([{ "locale": "de", "name": "London" }]));
+    assert_eq!(lookup["city"]["country"]["isoCode"], "GB");
+    assert!(lookup["city"].get("location").is_none());
+    assert_eq!(lookup["asn"]["autonomousSystemNumber"], 20712);
+}
+
+#[tokio::test]
+async fn graphql_errors() {
+    let query = r#"{ found: lookup(ip: "2001:218::1") { asn { network } } reserved: lookup(ip: "10.0.0.1") { city { traits { network } } } }"#;
+    let (status, response) = post(default_app(), "/graphql", json!({ "query": query })).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(response["data"]["found"]["asn"].is_null());
+    assert_eq!(response["errors"][0]["path"], json!(["reserved", "city"]));
+    assert_eq!(response["errors"][0]["extensions"]["code"], "IP_ADDRESS_RESERVED");
+}
+
+#[tokio::test]
+async fn freegeoip_compat() {
+    let mut config = Config::new(databases(&[city()]));
+    config.compat = vec![Compat::Freegeoip];
+    let (status, location) = get(app(config), "/json/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(location["ip"], "81.2.69.142");
+    assert_eq!(location["country_code"], "GB");
+    assert_eq!(location["region_name"], "England");
+    assert_eq!(location["city"], "London");
+    assert_eq!(location["zip_code"], "");
+    assert_eq!(location["latitude"], 51.5142);
+    assert_eq!(location["metro_code"], 0);
+
+    let (status, _) = get(default_app(), "/json/81.2.69.142").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn ip_api_compat() {
+    let mut config = Config::new(databases(&[city(), asn()]));
+    config.compat = vec![Compat::IpApi];
+    let app = app(config);
+
+    let (status, location) = get(app.clone(), "/json/81.2.69.142").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(location["status"], "success");
+    assert_eq!(location["countryCode"], "GB");
+    assert_eq!(location["regionName"], "England");
+    assert_eq!(location["lat"], 51.5142);
+    assert_eq!(location["as"], "AS20712 Andrews & Arnold Ltd");
+    assert_eq!(location["query"], "81.2.69.142");
+    assert!(location.get("message").is_none());
+
+    let (_, location) = get(app.clone(), "/json/81.2.69.142?fields=country,city&lang=de").await;
+    assert_eq!(location, json!({ "country": "Vereinigtes Königreich", "city": "London" }));
+
+    // 16386 is status and countryCode.
+    let (_, location) = get(app.clone(), "/json/81.2.69.142?fields=16386").await;
+    assert_eq!(location, json!({ "status": "success", "countryCode": "GB" }));
+
+    let (status, location) = get(app, "/json/10.0.0.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(location, json!({ "status": "fail", "message": "private range", "query": "10.0.0.1" }));
+}
+
+#[tokio::test]
+async fn ipinfo_compat() {
+    let mut config = Config::new(databases(&[city(), asn()]));
+    config.compat = vec![Compat::Ipinfo];
+    let app = app(config);
+
+    let (status, location) = get(app.clone(), "/81.2.69.142").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        location,
+        json!({ "ip": "81.2.69.142", "city": "London", "region": "England", "country": "GB", "loc": "51.5142,-0.0931", "org": "AS20712 Andrews & Arnold Ltd", "timezone": "Europe/London" })
+    );
+
+    let response = send(app.clone(), Request::get("/81.2.69.142/country").body(Body::empty()).unwrap()).await;
+    assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), "GB\n");
+
+    let (_, location) = get(app.clone(), "/10.0.0.1").await;
+    assert_eq!(location, json!({ "ip": "10.0.0.1", "bogon": true }));
+
+    assert_error(get(app, "/favicon.ico").await, StatusCode::NOT_FOUND, "ROUTE_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn enriched_countries() {
+    let mut config = Config::new(databases(&[city()]));
+    config.enrich_countries = true;
+    let (status, city) = get(app(config), "/geoip/v2.1/city/81.2.69.142").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["country"]["flag"], "🇬🇧");
+    assert_eq!(city["country"]["currency_code"], "GBP");
+    assert_eq!(city["country"]["calling_code"], "+44");
+    assert_eq!(city["continent"]["name"], "Europe");
+
+    let (_, city) = get(default_app(), "/geoip/v2.1/city/81.2.69.142").await;
+    assert!(city["country"].get("flag").is_none());
+}
+
+#[tokio::test]
+async fn time_zone_lookup() {
+    let (status, time_zone) = get(default_app(), "/geoip/v2.1/timezone/2001:218::1").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(time_zone, json!({ "time_zone": "Asia/Tokyo", "utc_offset": "+09:00", "utc_offset_seconds": 32400, "is_dst": false, "abbreviation": "JST" }));
+
+    let (_, city) = get(default_app(), "/geoip/v2.1/city/2001:218::1").await;
+    assert_eq!(city["location"]["utc_offset"], "+09:00");
+    assert_eq!(city["location"]["time_zone"], "Asia/Tokyo");
+}
+
+#[tokio::test]
+async fn distance_between_addresses() {
+    let (status, distance) = get(default_app(), "/geoip/v2.1/distance/81.2.69.142/2001:218::1").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let km = distance["distance_km"].as_f64().unwrap();
+    assert!((9550.0..9600.0).contains(&km), "{distance}");
+    assert!((km / distance["distance_miles"].as_f64().unwrap() - 1.609344).abs() < 0.001, "{distance}");
+    assert_eq!(distance["from"], json!({ "ip": "81.2.69.142", "latitude": 51.5142, "longitude": -0.0931, "accuracy_radius": 10 }));
+    assert_eq!(distance["to"]["accuracy_radius"], 100);
+
+    assert_error(get(default_app(), "/geoip/v2.1/distance/81.2.69.142/1.1.1.1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn addresses_are_normalized() {
+    let (status, city) = get(default_app(), "/geoip/v2.1/city/::ffff:81.2.69.142").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["traits"]["ip_address"], "81.2.69.142");
+    assert_eq!(city["traits"]["network"], "81.2.69.0/24");
+
+    let (status, city) = get(default_app(), "/geoip/v2.1/city/2001:0218:0000:0000:0000:0000:0000:00AB").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["traits"]["ip_address"], "2001:218::ab");
+
+    let (_, asn) = get(default_app(), "/geoip/v2.1/asn/81.2.69.142").await;
+    assert_eq!(asn["ip_address"], "81.2.69.142");
+
+    assert_error(get(default_app(), "/geoip/v2.1/city/fe80::1%25eth0").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED");
+}
+
+#[tokio::test]
+async fn hostnames_need_resolution() {
+    assert_error(get(default_app(), "/geoip/v2.1/city/example.com?resolve=true").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_INVALID");
+
+    let resolving = || {
+        let mut config = Config::new(databases(&[city()]));
+        config.hostnames = Some(Arc::new(HostnameResolver::new(AddressPreference::Ipv4, None)));
+        app(config)
+    };
+    assert_error(get(resolving(), "/geoip/v2.1/city/example.com").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_INVALID");
+    assert_error(get(resolving(), "/geoip/v2.1/city/not_a%20host?resolve=true").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_INVALID");
+}
+
+#[tokio::test]
+async fn resolution_rate_limit_ignores_unchecked_keys() {
+    let mut config = Config::new(databases(&[city()]));
+    let limiter = Arc::new(RateLimiter::new("1/h".parse::<Rate>().unwrap(), Some(1)));
+    config.hostnames = Some(Arc::new(HostnameResolver::new(AddressPreference::Ipv4, Some(limiter))));
+    let app = app(config);
+    let with_key = |key: &str| Request::get("/geoip/v2.1/city/localhost?resolve=true").header("x-forwarded-for", "203.0.113.7").header("x-api-key", key).body(Body::empty()).unwrap();
+
+    assert_ne!(send(app.clone(), with_key("first")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = send(app, with_key("second")).await;
+    assert_error((response.status(), body(response).await), StatusCode::TOO_MANY_REQUESTS, "RATE_LIMIT_EXCEEDED");
+}
+
+#[tokio::test]
+async fn reverse_dns_is_opt_in() {
+    let (status, city) = get(default_app(), "/geoip/v2.1/city/81.2.69.142?rdns=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(city["traits"].get("hostname").is_none());
+}
+
+#[tokio::test]
+async fn overrides_apply_and_reload() {
+    let path = std::env::temp_dir().join(format!("geoip2-server-test-{}-overrides.yaml", std::process::id()));
+    std::fs::write(
+        &path,
+        "- { network: 81.2.69.128/25, databases: [city], record: { city: { names: { en: Office } }, subdivisions: null } }\n- { network: 10.20.0.0/16, record: { country: { iso_code: DE } } }\n",
+    )
+    .unwrap();
+    let databases = Databases::open(&[DatabaseArg { kind: None, path: city().write(), url: None }], true).unwrap().with_overrides(Overrides::open(path.clone()).unwrap());
+    let app = app(Config::new(Arc::new(databases)));
+
+    let (_, city) = get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await;
+    assert_eq!(city["city"]["names"]["en"], "London");
+    let (_, city) = get(app.clone(), "/geoip/v2.1/city/81.2.69.200").await;
+    assert_eq!(city["city"]["names"]["en"], "Office");
+    assert_eq!(city["city"]["names"]["de"], "London");
+    assert_eq!(city["country"]["iso_code"], "GB");
+    assert!(city.get("subdivisions").is_none());
+
+    let (status, country) = get(app.clone(), "/geoip/v2.1/country/10.20.1.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(country["country"]["iso_code"], "DE");
+
+    std::fs::write(&path, "- { network: 81.2.69.0/24, replace: true, record: { country: { iso_code: FR } } }\n").unwrap();
+    assert_eq!(post(app.clone(), "/admin/reload", json!(null)).await.0, StatusCode::OK);
+    let (_, city) = get(app.clone(), "/geoip/v2.1/city/81.2.69.200").await;
+    assert_eq!(city["country"]["iso_code"], "FR");
+    assert!(city.get("city").is_none());
+    assert_error(get(app, "/geoip/v2.1/country/10.20.1.1").await, StatusCode::BAD_REQUEST, "IP_ADDRESS_RESERVED");
+}
+
+#[tokio::test]
+async fn private_response() {
+    let mut config = Config::new(databases(&[city(), asn()]));
+    config.private_response = Some(Bytes::from_static(br#"{"country":{"iso_code":"ZZ"}}"#));
+    let (status, city) = get(app(config), "/geoip/v2.1/city/192.168.1.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city, json!({ "country": { "iso_code": "ZZ" }, "traits": { "ip_address": "192.168.1.1" } }));
+}
+
+#[tokio::test]
+async fn database_chain() {
+    let fallback = Writer::new("GeoLite2-City")
+        .insert("89.160.20.0/24", json!({ "city": { "names": { "en": "Linköping" } }, "country": { "iso_code": "SE" } }))
+        .insert("81.2.69.0/24", json!({ "city": { "names": { "en": "Not London" } } }));
+    let app = app(Config::new(databases(&[city(), fallback])));
+
+    let (_, city) = get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await;
+    assert_eq!(city["city"]["names"]["en"], "London");
+    let (status, city) = get(app.clone(), "/geoip/v2.1/city/89.160.20.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(city["city"]["names"]["en"], "Linköping");
+    let (_, country) = get(app.clone(), "/geoip/v2.1/country/89.160.20.1").await;
+    assert_eq!(country["country"]["iso_code"], "SE");
+    assert_error(get(app.clone(), "/geoip/v2.1/city/1.1.1.1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+
+    let (_, status) = get(app, "/status").await;
+    assert!(status["databases"]["city"].is_object());
+    assert!(status["databases"]["city.1"].is_object());
+}
+
+#[tokio::test]
+async fn database_chain_cache() {
+    let fallback = Writer::new("GeoLite2-City").insert("81.2.0.0/16", json!({ "city": { "names": { "en": "Not London" } } }));
+    let mut config = Config::new(databases(&[city(), fallback]));
+    config.cache = Some(RecordCache::new(100, Duration::from_secs(60)));
+    let app = app(config);
+
+    let (_, city) = get(app.clone(), "/geoip/v2.1/city/81.2.1.1").await;
+    assert_eq!(city["city"]["names"]["en"], "Not London");
+    // The network cached from the fallback covers the address, but the first database has a record of its own.
+    let (_, city) = get(app, "/geoip/v2.1/city/81.2.69.142").await;
+    assert_eq!(city["city"]["names"]["en"], "London");
+}
+
+#[tokio::test]
+async fn database_chain_cache_warm() {
+    let fallback = Writer::new("GeoLite2-City").insert("81.2.0.0/16", json!({ "city": { "names": { "en": "Not London" } } }));
+    let mut config = Config::new(databases(&[city(), fallback]));
+    config.cache = Some(RecordCache::new(100, Duration::from_secs(60)));
+    let app = app(config);
+
+    let (status, warmed) = post(app.clone(), "/admin/cache/warm", json!(["81.2.1.1"])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(warmed["records"], 1);
+    // Warming cached the network of the fallback as its own, not as one of the first database.
+    let (_, city) = get(app, "/geoip/v2.1/city/81.2.69.142").await;
+    assert_eq!(city["city"]["names"]["en"], "London");
+}
+
+#[tokio::test]
+async fn upstream_fills_in_misses() {
+    let maxmind = Router::new().route(
+        "/geoip/v2.1/city/:ip",
+        axum::routing::get(|| async { Json(json!({ "city": { "names": { "en": "Paris" } }, "country": { "iso_code": "FR" }, "traits": { "ip_address": "1.1.1.1" }, "maxmind": { "queries_remaining": 1 } })) }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, maxmind).await });
+
+    let mut config = Config::new(databases(&[city()]));
+    config.upstream = Some(Arc::new(Upstream::new(url, "1".into(), "key".into(), 1, Duration::from_secs(60))));
+    let app = app(config);
+
+    let (_, london) = get(app.clone(), "/geoip/v2.1/city/81.2.69.142").await;
+    assert_eq!(london["city"]["names"]["en"], "London");
+
+    let (status, paris) = get(app.clone(), "/geoip/v2.1/city/1.1.1.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(paris, json!({ "city": { "names": { "en": "Paris" } }, "country": { "iso_code": "FR" }, "traits": { "ip_address": "1.1.1.1" } }));
+    assert_eq!(get(app.clone(), "/geoip/v2.1/city/1.1.1.1").await.1["city"]["names"]["en"], "Paris");
+
+    // The budget of one request a minute is spent.
+    assert_error(get(app, "/geoip/v2.1/city/1.0.0.1").await, StatusCode::NOT_FOUND, "IP_ADDRESS_NOT_FOUND");
+}